@@ -18,6 +18,7 @@ use git2::{ObjectType, Oid};
 use tracing::{Level, field, instrument};
 
 use crate::fs;
+use crate::fs::builds::chase_hook::ChaseHooks;
 use crate::fs::fileattr::{
     CreateFileAttr, Dentry, FileAttr, FileType, InoFlag, ObjectAttr, SetFileAttr, StorageNode,
     dir_attr, file_attr,
@@ -27,7 +28,7 @@ use crate::fs::meta_db::{DbReturn, InodeTable};
 use crate::fs::ops::readdir::{
     BuildCtxMetadata, DirectoryEntry, DirectoryEntryPlus, DirectoryStreamCookie,
 };
-use crate::fs::repo::{GitRepo, InoState, RefState};
+use crate::fs::repo::{GitRepo, InoState, RefChanges, RefState};
 use crate::inodes::{Inodes, NormalIno, VirtualIno};
 use crate::internals::cache::LruCache;
 use crate::mount::InvalMsg;
@@ -39,6 +40,7 @@ pub mod handles;
 pub mod meta_db;
 pub mod ops;
 pub mod repo;
+pub mod views;
 
 #[cfg(test)]
 mod test;
@@ -47,6 +49,10 @@ const META_STORE: &str = "fs_meta.db";
 const LIVE_FOLDER: &str = "live";
 const BUILD_FOLDER: &str = "build";
 const CHASE_FOLDER: &str = "chase";
+/// Virtual folder inside each Snap folder, holding the latest chase results for the commit
+const RESULTS_FOLDER: &str = ".chase";
+/// Folder inside the repo root, holding the Snap folders of commits outside the refs
+const COMMITS_FOLDER: &str = "Commits";
 const TEMP_FOLDER: &str = ".temp";
 pub const REPO_SHIFT: u8 = 48;
 pub const ROOT_INO: u64 = 1;
@@ -148,6 +154,8 @@ pub struct GitFs {
     read_only: bool,
     vfile_entry: RwLock<HashMap<VirtualIno, VFileEntry>>,
     notifier: crossbeam_channel::Sender<InvalMsg>,
+    /// Hooks run at the end of every chase
+    pub chase_hooks: ChaseHooks,
 }
 
 pub struct Handle {
@@ -262,6 +270,7 @@ impl GitFs {
         mount_point: PathBuf,
         read_only: bool,
         notifier: Arc<OnceLock<fuser::Notifier>>,
+        chase_hooks: ChaseHooks,
     ) -> anyhow::Result<Arc<Self>> {
         let (tx_inval, rx_inval) = crossbeam_channel::unbounded::<InvalMsg>();
 
@@ -275,6 +284,7 @@ impl GitFs {
             next_inode: DashMap::new(),
             vfile_entry: RwLock::new(HashMap::new()),
             notifier: tx_inval.clone(),
+            chase_hooks,
         };
 
         let fs = Arc::new(fs);
@@ -1016,6 +1026,34 @@ impl GitFs {
         }
     }
 
+    /// Symlinks can only be created inside the build folders
+    #[instrument(level = "debug", skip(self), fields(parent = %parent), ret(level = Level::DEBUG), err(Display))]
+    pub fn symlink(&self, parent: u64, name: &OsStr, target: &Path) -> anyhow::Result<FileAttr> {
+        let parent: Inodes = parent.into();
+        if name.is_empty() || name == "." || name == ".." {
+            bail!(std::io::Error::from_raw_os_error(libc::EINVAL));
+        }
+        if memchr::memchr2(b'/', b'\\', name.as_bytes()).is_some() {
+            tracing::error!("invalid name: contains '/' or '\\' {}", name.display());
+            bail!(std::io::Error::from_raw_os_error(libc::EINVAL))
+        }
+        if self.read_only {
+            bail!(std::io::Error::from_raw_os_error(libc::EROFS))
+        }
+        if !self.exists(parent)? {
+            tracing::error!("Parent {} does not exist", parent);
+            bail!(std::io::Error::from_raw_os_error(libc::ENOENT))
+        }
+
+        let ctx = FsOperationContext::get_operation(self, parent);
+        match ctx? {
+            FsOperationContext::InsideGitDir => {
+                ops::create::symlink_git(self, parent.to_norm(), name, target)
+            }
+            _ => bail!(std::io::Error::from_raw_os_error(libc::EPERM)),
+        }
+    }
+
     #[instrument(level = "debug", skip(self), fields(parent = %parent), ret(level = Level::DEBUG), err(Display))]
     pub fn create(
         &self,
@@ -1497,16 +1535,19 @@ impl GitFs {
         Ok(())
     }
 
-    pub fn update_repo(&self, repo_name: &str, remote: Option<String>) -> anyhow::Result<()> {
-        if let Some(repo_entry) = self.repos_map.get(repo_name) {
-            let repo_ino = GitFs::repo_id_to_ino(*repo_entry.value());
-            let repo = self.get_repo(repo_ino)?;
-            let repo_path = self.repos_dir.join(&repo.repo_dir);
-            repo.update_fetch(remote, &repo_path)?;
-        } else {
+    /// Returns the new commits of each ref
+    pub fn update_repo(
+        &self,
+        repo_name: &str,
+        remote: Option<String>,
+    ) -> anyhow::Result<RefChanges> {
+        let Some(repo_entry) = self.repos_map.get(repo_name) else {
             bail!(std::io::Error::from_raw_os_error(libc::EINVAL))
-        }
-        Ok(())
+        };
+        let repo_ino = GitFs::repo_id_to_ino(*repo_entry.value());
+        let repo = self.get_repo(repo_ino)?;
+        let repo_path = self.repos_dir.join(&repo.repo_dir);
+        repo.update_fetch(remote, &repo_path)
     }
 
     fn write_root_ino(&self, repo_ino: u64) -> anyhow::Result<()> {
@@ -1571,6 +1612,15 @@ impl GitFs {
             bail!(std::io::Error::from_raw_os_error(libc::EPERM));
         };
         let ino_flag = self.get_ino_flag_from_db(ino)?;
+        // Symlinks of the build folders are not followed
+        if ino_flag == InoFlag::InsideBuild && path.symlink_metadata()?.is_symlink() {
+            let mut attr: FileAttr = file_attr(ino_flag).into();
+            attr.ino = ino.into();
+            attr.kind = FileType::Symlink;
+            attr.perm = 0o777;
+            attr.size = std::fs::read_link(&path)?.as_os_str().len() as u64;
+            return Ok(attr);
+        }
         let mut attr = self.refresh_medata_using_path(path, ino_flag)?;
         attr.ino = ino.into();
 
@@ -1778,22 +1828,6 @@ impl GitFs {
 
     fn ensure_base_dirs_exist(&self) -> anyhow::Result<()> {
         if !self.repos_dir.exists() {
-            let mut attr: FileAttr = CreateFileAttr {
-                kind: FileType::Directory,
-                ino_flag: InoFlag::Root,
-                perm: 0o775,
-                mode: libc::S_IFDIR,
-                uid: 0,
-                gid: 0,
-                rdev: 0,
-                flags: 0,
-            }
-            .into();
-            unsafe {
-                attr.uid = libc::getuid();
-                attr.gid = libc::getgid();
-            }
-
             let repos_dir = &self.repos_dir;
             std::fs::create_dir_all(repos_dir)?;
         }
@@ -8,9 +8,19 @@ use git2::Oid;
 use tempfile::TempDir;
 
 pub mod chase;
+pub mod chase_console;
+pub mod chase_diff;
+pub mod chase_env;
 pub mod chase_handle;
+pub mod chase_hook;
+pub mod chase_live;
+pub mod chase_log;
+pub mod chase_record;
+pub mod chase_repro;
 pub mod chase_resolver;
 pub mod chase_runner;
+pub mod chase_tests;
+pub mod chase_watch;
 pub mod inject;
 pub mod job;
 pub mod logger;
@@ -4,6 +4,22 @@ local commits = {
 local commands = {
 }
 
+-- Steps ran before and after the commands of each commit. Teardown steps always run, even after a failure or a cancel.
+-- A step can run only once for the whole chase: {"./start_db.sh", once = true}
+-- Commands and steps run in the Snap folder, unless cwd is set: {"npm test", cwd = "frontend"}
+-- Commands can run on a pseudo-terminal, to keep their colors and progress output: {"cargo build", pty = true} / {"pytest", pty = {cols = 120, rows = 40}}
+-- The results of single tests can be read from the output of a command, with parser = "libtest", "tap" or "junit":
+-- {"cargo test -- -Z unstable-options --format json", parser = "libtest"}
+-- {"mvn test", parser = "junit", results = "target/surefire-reports"}
+local setup = {
+}
+local teardown = {
+}
+
+-- Run the commands through a shell, so pipes, && and redirections work. Can be true, false or the path to a shell.
+-- Single commands can also opt in or out: {"cargo test 2>&1 | tee out.txt", shell = true}
+local shell = false
+
 local patches = {{
   path = "",
   code = [[
@@ -12,6 +28,27 @@ local patches = {{
   },
 }
 
+-- Other repos mounted in guse, made available to the commands.
+-- The path to the Snap folder is set in the env variable GUSE_REPO_<NAME>
+-- pin can be a commit hash, or "Before" to use the latest commit before the date of each chased commit
+-- "Before" uses main (or HEAD), "Before:<branch>" uses the given branch
+local repos = {
+}
+
+-- Local commands to run when the chase ends, fails or is cancelled.
+-- Each command receives the path to the JSON report of the run on stdin.
+local hooks = {
+}
+-- Files where a line summarizing the chase is appended when it ends
+local notify_files = {
+}
+
+-- Checks that each commit builds to byte-identical artifacts (files or folders, relative to the Snap folder).
+-- mode "Twice" builds each commit a second time from a clean Snap folder, "Previous" compares with the latest run that built the commit.
+-- A commit with different artifacts counts as failed. local repro = {mode = "Twice", artifacts = {"target/release/app"}}
+local repro = {
+}
+
 -- Sets the run mode. For the moment, only "Continuous" mode is implemented. "Binary" mode is a work in progress.
 local run_mode = "Continuous"
 -- Sets the build mode. Can be "FirstFailure" or "Continuous"
@@ -20,26 +57,111 @@ local stop_mode = "Continuous"
   -- HOW TO ADD COMMITS:
   -- single commits:            local commits = {"hash", "hash", "hash",}
   -- a complete branch or Pr:   local commits = {Branch = "branch_name"} / { Pr = "Pr_number" }
-  -- a range of commits:        local commits = {Range = "hash...hash"}
+  -- a range of commits:        local commits = {Range = "hash..hash"}
+  -- a tag or any revspec:      local commits = {Tag = "v1.2"} / {Rev = "main~10"}
+  -- a date window:             local commits = {{Since = "2024-01-01", Until = "2024-02-01", Branch = "main"}}
+  -- only the first/last N:     local commits = {Branch = "branch_name", Last = 10}
+  -- the merge commit of a Pr:  local commits = {PrMerge = "Pr_number"}
+  -- every open Pr:             local commits = {Pr = "all"} / {PrMerge = "all"}
+  -- filter the open Prs:       local commits = {PrMerge = "all", PrMin = 100, PrSince = "14d"}
+  -- the uncommitted work:      local commits = {Live = true} / {Live = "copy"}
   -- Or any combination of them
 
+-- Refs watched for new commits. After `guse repo update` fetches new commits on them, the chase runs on those commits.
+-- The output is appended to watch.log, next to this script. A script with only watch rules is not ran by hand.
+-- local watch = {Branch = "main", Pr = "all"} / {Branch = {"main", "dev"}, Tag = "all"}
+local watch = {
+}
+
+-- Ranges and date windows only follow the first parent of merge commits
+local first_parent = false
+
+-- The order of the commits. Can be "as_given", "topo" (parents first), "date" (oldest first) or "reverse"
+local order = "as_given"
+
 -- Load functions
 for input_type, oid in pairs(commits) do
-  if type(input_type) == "number" then 
+  if type(input_type) == "number" and type(oid) == "table" then
+    cfg.add_window(oid.Since, oid.Until, oid.Branch)
+  elseif type(input_type) == "number" then
     cfg.add_commit("commit", oid)
+  elseif input_type == "First" or input_type == "Last" then
+    cfg.set_limit(input_type, oid)
+  elseif input_type == "PrMin" or input_type == "PrSince" then
+    cfg.set_pr_filter(input_type, oid)
+  elseif input_type == "Live" then
+    cfg.set_live(oid)
   else
     cfg.add_commit(input_type, oid)
   end
 end
 
+for kind, names in pairs(watch) do
+  if type(names) == "table" then
+    for _, name in ipairs(names) do
+      cfg.add_watch(kind, name)
+    end
+  else
+    cfg.add_watch(kind, names)
+  end
+end
+
+if first_parent ~= nil then
+  cfg.set_first_parent(first_parent)
+end
+
+if order ~= nil then
+  cfg.set_order(order)
+end
+
+if shell ~= nil then
+  cfg.set_shell(shell)
+end
+
 for _, command in ipairs(commands) do
-  cfg.add_command(command)
+  if type(command) == "table" then
+    cfg.add_command(command[1], command)
+  else
+    cfg.add_command(command)
+  end
+end
+
+for _, step in ipairs(setup) do
+  if type(step) == "table" then
+    cfg.setup(step[1], step)
+  else
+    cfg.setup(step)
+  end
+end
+
+for _, step in ipairs(teardown) do
+  if type(step) == "table" then
+    cfg.teardown(step[1], step)
+  else
+    cfg.teardown(step)
+  end
 end
 
 for _, p in ipairs(patches) do
   cfg.add_patch(p.path, p.code)
 end
 
+for _, r in ipairs(repos) do
+  cfg.add_repo(r.name, r.repo, r.pin)
+end
+
+for _, command in ipairs(hooks) do
+  cfg.add_hook(command)
+end
+
+for _, path in ipairs(notify_files) do
+  cfg.add_notify_file(path)
+end
+
+if repro.artifacts ~= nil then
+  cfg.set_repro(repro.mode or "Twice", repro.artifacts)
+end
+
 if run_mode ~= nil then
   cfg.set_run_mode(run_mode)
 end
@@ -1,8 +1,8 @@
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{BTreeSet, HashMap, VecDeque},
     io::Write,
     os::unix::net::UnixStream,
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::{Arc, OnceLock, atomic::AtomicU64},
 };
 
@@ -15,15 +15,28 @@ use crate::{
     fs::{
         GitFs,
         builds::{
+            chase_diff::{RunDiff, diff_lines, log_tail},
+            chase_env::ChaseEnv,
             chase_handle::ChaseHandle,
+            chase_hook::ChaseHooks,
+            chase_live::{LiveMode, LiveTarget, live_oid},
+            chase_log::{DEFAULT_LOG_LIMIT, LogStream, find_log, read_log},
+            chase_record::{ChaseRecord, find_run_dir},
+            chase_repro::ReproCheck,
             chase_resolver::{
-                cleanup_builds, resolve_path_for_refs, validate_commit_refs, validate_commits,
+                MatchedInputs, cleanup_builds, find_repo_ino, pin_secondary_commits,
+                remove_build_files, resolve_chase_commits, resolve_detached_snaps,
+                resolve_path_for_refs, validate_commit_refs, validate_commits,
             },
             chase_runner::ChaseRunner,
             inject::InjectedMetadata,
-            logger::CmdResult,
-            reporter::{ErrorResolver, Updater},
-            runtime::{ChaseRunMode, ChaseStopMode, LuaConfig},
+            job::DEFAULT_KILL_GRACE,
+            logger::{CmdResult, DEFAULT_MAX_LINE, display_line},
+            reporter::{ChaseError, ErrorResolver, GuseResult, Updater},
+            runtime::{
+                ChaseCommand, ChaseRunMode, ChaseSteps, ChaseStopMode, InputTypes, LuaConfig,
+                RepoInput,
+            },
         },
         fileattr::FileType,
     },
@@ -43,7 +56,11 @@ pub fn next_chase_id() -> u64 {
 pub struct Chase {
     // Makes sure Oids are read in the correct order, as they were input by the user
     pub commits: VecDeque<Oid>,
-    pub commands: VecDeque<String>,
+    // The inputs of the script that matched each commit
+    pub matched: HashMap<Oid, Vec<String>>,
+    pub commands: VecDeque<ChaseCommand>,
+    // Setup and teardown steps, per commit and per chase
+    pub steps: ChaseSteps,
     pub run_mode: ChaseRunMode,
     pub stop_mode: ChaseStopMode,
     // Holds the path for the Snap folders and the ino of the snap folders
@@ -51,6 +68,34 @@ pub struct Chase {
     // Logging to file enabled/disabled
     pub patches: Vec<(PathBuf, String)>,
     pub args: ChaseArgs,
+    // Other repos whose Snap folders are made available to the commands
+    pub repos: Vec<SecondaryRepo>,
+    // Hooks declared by the script, run when the chase ends
+    pub hooks: ChaseHooks,
+    // Checks that each commit builds to the same artifacts
+    pub repro: Option<ReproCheck>,
+    // The live folder, chased after the commits
+    pub live: Option<LiveTarget>,
+    // Env variables from the env files. Not saved in the chase record.
+    pub env: ChaseEnv,
+}
+
+/// A secondary repo used by the chase, pinned to a commit for each chased commit
+#[derive(Clone)]
+pub struct SecondaryRepo {
+    pub name: String,
+    pub repo_ino: u64,
+    // Chased commit -> commit used from the secondary repo
+    pub pins: HashMap<Oid, Oid>,
+    // Holds the path for the Snap folders and the ino of the snap folders
+    pub commit_paths: HashMap<Oid, (PathBuf, u64)>,
+}
+
+impl SecondaryRepo {
+    /// Name of the env variable holding the path to the Snap folder
+    pub fn env_name(&self) -> String {
+        format!("GUSE_REPO_{}", self.name.to_uppercase())
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -59,6 +104,92 @@ pub struct Chase {
 pub struct ChaseArgs {
     pub log: bool,
     pub no_move: bool,
+    // Clone the build contents between targets instead of moving them
+    #[serde(default)]
+    pub clone: bool,
+    // Size cap in bytes for the log of each commit
+    #[serde(default = "default_log_limit")]
+    pub log_limit: u64,
+    // Which build sessions are kept after the chase
+    #[serde(default)]
+    pub keep: KeepMode,
+    // Seconds between SIGTERM and SIGKILL when a command is stopped
+    #[serde(default = "default_kill_grace")]
+    pub kill_grace: u64,
+    // Colors of the command output shown in the cli. The log file always keeps the raw bytes.
+    #[serde(default)]
+    pub ansi: AnsiMode,
+    // Size cap in bytes for a single line of command output
+    #[serde(default = "default_max_line")]
+    pub max_line: usize,
+}
+
+/// Build sessions kept (pinned) after a chase, so the Snap folders can be inspected
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeepMode {
+    #[default]
+    None,
+    Failed,
+    All,
+}
+
+impl KeepMode {
+    pub fn from_name(mode: &str) -> Option<Self> {
+        match mode.to_lowercase().as_str() {
+            "none" => Some(Self::None),
+            "failed" => Some(Self::Failed),
+            "all" => Some(Self::All),
+            _ => None,
+        }
+    }
+}
+
+/// ANSI escape codes (colors) in the command output shown in the cli
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnsiMode {
+    #[default]
+    Keep,
+    Strip,
+}
+
+impl AnsiMode {
+    pub fn from_name(mode: &str) -> Option<Self> {
+        match mode.to_lowercase().as_str() {
+            "keep" => Some(Self::Keep),
+            "strip" => Some(Self::Strip),
+            _ => None,
+        }
+    }
+}
+
+impl ChaseArgs {
+    /// Used for the chases started by the watch rules, which have no cli to pass them
+    pub fn watch() -> Self {
+        Self {
+            log: true,
+            no_move: false,
+            clone: false,
+            log_limit: default_log_limit(),
+            keep: KeepMode::None,
+            kill_grace: default_kill_grace(),
+            ansi: AnsiMode::Strip,
+            max_line: default_max_line(),
+        }
+    }
+}
+
+fn default_log_limit() -> u64 {
+    DEFAULT_LOG_LIMIT
+}
+
+fn default_kill_grace() -> u64 {
+    DEFAULT_KILL_GRACE
+}
+
+fn default_max_line() -> usize {
+    DEFAULT_MAX_LINE
 }
 
 // Accepts a handshake between "client" and "server"
@@ -79,6 +210,35 @@ pub fn start_chase(
     stream: &mut UnixStream,
     args: ChaseArgs,
     chase_id: ChaseId,
+) -> anyhow::Result<()> {
+    chase_commits(fs, repo_name, script, stream, args, chase_id, None)
+}
+
+/// Runs the script on the given commits instead of its inputs. Used by the watch rules.
+///
+/// The chase id is released once the chase ends, even if it could not start.
+pub fn chase_commits(
+    fs: &GitFs,
+    repo_name: &str,
+    script: &str,
+    stream: &mut UnixStream,
+    args: ChaseArgs,
+    chase_id: ChaseId,
+    given: Option<(VecDeque<Oid>, MatchedInputs)>,
+) -> anyhow::Result<()> {
+    let res = prepare_chase(fs, repo_name, script, stream, args, chase_id, given);
+    ChaseHandle::deregister_chase_id(chase_id);
+    res
+}
+
+fn prepare_chase(
+    fs: &GitFs,
+    repo_name: &str,
+    script: &str,
+    stream: &mut UnixStream,
+    args: ChaseArgs,
+    chase_id: ChaseId,
+    given: Option<(VecDeque<Oid>, MatchedInputs)>,
 ) -> anyhow::Result<()> {
     if let CmdResult::Err(e) = ChaseHandle::start_run(chase_id) {
         stream.update(&format!("Chase id not found. Unable to cancel run: {e}"))?;
@@ -92,27 +252,397 @@ pub fn start_chase(
 
     // Read and parse the script
     let cfg = LuaConfig::read_lua(&script_path).resolve(stream)?;
+    let env = load_env(&script_path, stream)?;
 
     // Validate the commits, find the Oid
-    let commits = validate_commits(fs, repo_ino, &cfg.commits).resolve(stream)?;
-    let commands: VecDeque<String> = cfg.commands.into();
+    let watched = given.is_some();
+    let (commits, matched) = match given {
+        Some(given) => given,
+        None if cfg.commits.is_empty() && cfg.live.is_none() => {
+            // Watch only script, it has nothing to chase by hand
+            return GuseResult::<()>::Err(ChaseError::NoCommits).resolve(stream);
+        }
+        None if cfg.commits.is_empty() => (VecDeque::new(), HashMap::new()),
+        None => resolve_chase_commits(fs, repo_ino, &cfg).resolve(stream)?,
+    };
+    // The watch rules only chase the new commits
+    let live = match cfg.live.filter(|_| !watched) {
+        Some(mode) => Some(resolve_live(fs, repo_ino, mode, stream)?),
+        None => None,
+    };
+    let commands: VecDeque<ChaseCommand> = cfg.commands.into();
     let c_oid_vec = commits.iter().collect::<Vec<&Oid>>();
 
     // Find the Snap folders on disk
     let c_refs = validate_commit_refs(fs, repo_ino, &c_oid_vec).resolve(stream)?;
-    let paths = resolve_path_for_refs(fs, repo_ino, c_refs).resolve(stream)?;
+    let mut paths = resolve_path_for_refs(fs, repo_ino, c_refs).resolve(stream)?;
+    let detached = resolve_detached_snaps(fs, repo_ino, &c_oid_vec, &mut paths).resolve(stream)?;
+    if !detached.is_empty() {
+        stream.update(&detached_message(&detached))?;
+    }
+
+    // Find the Snap folders of the secondary repos
+    let repos = resolve_secondary_repos(fs, repo_ino, &commits, &cfg.repos, live.as_ref(), stream)?;
 
     // Prepare the build ctx
     let chase: Chase = Chase {
         commits,
+        matched,
         commands,
+        steps: cfg.steps,
         run_mode: cfg.run_mode,
         stop_mode: cfg.stop_mode,
         commit_paths: paths,
         patches: cfg.patches,
         args,
+        repos,
+        hooks: cfg.hooks,
+        repro: cfg.repro,
+        live,
+        env,
+    };
+
+    // Folder to save the logs and the chase record to
+    let name = format!("{}", chrono::offset::Utc::now());
+    let dir_path = script_path.join(name);
+    run_chase(fs, repo_ino, chase, &dir_path, None, stream, chase_id)
+}
+
+/// Resumes a chase from the record saved in its run folder, skipping the completed commits
+pub fn resume_chase(
+    fs: &GitFs,
+    repo_name: &str,
+    script: &str,
+    run: &str,
+    stream: &mut UnixStream,
+    chase_id: ChaseId,
+) -> anyhow::Result<()> {
+    let res = prepare_resume(fs, repo_name, script, run, stream, chase_id);
+    ChaseHandle::deregister_chase_id(chase_id);
+    res
+}
+
+fn prepare_resume(
+    fs: &GitFs,
+    repo_name: &str,
+    script: &str,
+    run: &str,
+    stream: &mut UnixStream,
+    chase_id: ChaseId,
+) -> anyhow::Result<()> {
+    if let CmdResult::Err(e) = ChaseHandle::start_run(chase_id) {
+        stream.update(&format!("Chase id not found. Unable to cancel run: {e}"))?;
+    };
+    let repo_ino = get_repo_ino(fs, repo_name, stream)?;
+    let repo = fs.get_repo(repo_ino)?;
+
+    let script_path = repo.chase_dir.join(script);
+    let record = find_run_dir(&script_path, run)
+        .and_then(|dir| Ok((ChaseRecord::load(&dir)?, dir)))
+        .and_then(|(record, dir)| Ok((record.commit_oids()?, record, dir)));
+    let (commits, mut record, dir_path) = match record {
+        Ok(r) => r,
+        Err(e) => {
+            stream.update(&format!("Could not resume the chase: {e}\n"))?;
+            bail!("")
+        }
     };
+    if let Some(failed) = record.first_failure() {
+        stream.update(&format!(
+            "The chase stopped at its first failure, on commit {failed}. Nothing to resume.\n"
+        ))?;
+        bail!("")
+    }
+    stream.update(&resume_message(script, &dir_path))?;
+    let env = load_env(&script_path, stream)?;
+
+    // Find the Snap folders on disk. Inodes are not kept between sessions.
+    let commits: VecDeque<Oid> = commits.into();
+    let c_oid_vec = commits.iter().collect::<Vec<&Oid>>();
+    let c_refs = validate_commit_refs(fs, repo_ino, &c_oid_vec).resolve(stream)?;
+    let mut paths = resolve_path_for_refs(fs, repo_ino, c_refs).resolve(stream)?;
+    let detached = resolve_detached_snaps(fs, repo_ino, &c_oid_vec, &mut paths).resolve(stream)?;
+    if !detached.is_empty() {
+        stream.update(&detached_message(&detached))?;
+    }
+
+    let mut repos = Vec::with_capacity(record.repos.len());
+    for rec in &record.repos {
+        let sec_ino = find_repo_ino(fs, &rec.repo).resolve(stream)?;
+        let pins = match rec.pin_oids() {
+            Ok(p) => p,
+            Err(e) => {
+                stream.update(&format!("Could not resume the chase: {e}\n"))?;
+                bail!("")
+            }
+        };
+        repos.push(secondary_repo(fs, &rec.name, sec_ino, pins, stream)?);
+    }
+
+    // The live folder is copied again, its state is updated if it was not chased yet
+    let live = match record.live.as_ref().map(|l| l.mode) {
+        Some(mode) => {
+            let live = resolve_live(fs, repo_ino, mode, stream)?;
+            if !record.completed.contains(&live_oid().to_string()) {
+                record.live = Some(live.state.clone());
+            }
+            Some(live)
+        }
+        None => None,
+    };
+
+    let matched = match record.matched_oids() {
+        Ok(m) => m,
+        Err(e) => {
+            stream.update(&format!("Could not resume the chase: {e}\n"))?;
+            bail!("")
+        }
+    };
+    let chase: Chase = Chase {
+        commits,
+        matched,
+        commands: record.commands.clone().into(),
+        steps: record.steps.clone(),
+        run_mode: record.run_mode.clone(),
+        stop_mode: record.stop_mode.clone(),
+        commit_paths: paths,
+        patches: record.patches.clone(),
+        args: record.args.clone(),
+        repos,
+        hooks: record.hooks.clone(),
+        repro: record.repro.clone(),
+        live,
+        env,
+    };
+    run_chase(
+        fs,
+        repo_ino,
+        chase,
+        &dir_path,
+        Some(record),
+        stream,
+        chase_id,
+    )
+}
 
+/// Compares two runs of a script: commits whose result changed, commits found in only one run,
+/// and the end of the log of each regressed commit
+pub fn print_chase_diff(
+    fs: &GitFs,
+    repo_name: &str,
+    script: &str,
+    runs: (&str, &str),
+    tail: usize,
+    stream: &mut UnixStream,
+) -> anyhow::Result<()> {
+    let repo_ino = get_repo_ino(fs, repo_name, stream)?;
+    let repo = fs.get_repo(repo_ino)?;
+    let script_path = repo.chase_dir.join(script);
+
+    let load = |run: &str| {
+        find_run_dir(&script_path, run).and_then(|dir| Ok((ChaseRecord::load(&dir)?, dir)))
+    };
+    let ((record_a, dir_a), (record_b, dir_b)) =
+        match load(runs.0).and_then(|a| Ok((a, load(runs.1)?))) {
+            Ok(r) => r,
+            Err(e) => {
+                stream.update(&format!("Could not compare the runs: {e}\n"))?;
+                bail!("")
+            }
+        };
+    let name = |dir: &Path| {
+        dir.file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned()
+    };
+    let (name_a, name_b) = (name(&dir_a), name(&dir_b));
+    let diff = RunDiff::new(&record_a, &record_b);
+
+    let mut out = format!(
+        "Comparing {name_a} (A) with {name_b} (B): {} changed, {} unchanged, {} only in A, {} only in B\n",
+        diff.changed.len(),
+        diff.unchanged,
+        diff.only_a.len(),
+        diff.only_b.len()
+    );
+    if !diff.changed.is_empty() {
+        out.push_str("\nChanged:\n");
+        for (oid, before, after) in &diff.changed {
+            out.push_str(&format!(
+                "    {oid:.7} {} -> {}\n",
+                before.as_str(),
+                after.as_str()
+            ));
+        }
+    }
+    for (label, commits) in [("A", &diff.only_a), ("B", &diff.only_b)] {
+        if commits.is_empty() {
+            continue;
+        }
+        out.push_str(&format!("\nOnly in {label}:\n"));
+        for (oid, status) in commits {
+            out.push_str(&format!("    {oid:.7} {}\n", status.as_str()));
+        }
+    }
+    stream.update(&out)?;
+
+    for oid in diff.regressed() {
+        let mut out = format!("\nRegressed {oid:.7}, last {tail} log lines (- A, + B):\n");
+        match (log_tail(&dir_a, oid, tail), log_tail(&dir_b, oid, tail)) {
+            (Ok(a), Ok(b)) => {
+                for line in diff_lines(&a, &b) {
+                    out.push_str(&format!("    {line}\n"));
+                }
+            }
+            (Err(e), _) | (_, Err(e)) => {
+                out.push_str(&format!("    No log to compare: {e}\n"));
+            }
+        }
+        stream.update(&out)?;
+    }
+    Ok(())
+}
+
+/// Sends the log of a commit from a previous run, optionally filtered by stream
+pub fn print_chase_log(
+    fs: &GitFs,
+    repo_name: &str,
+    script: &str,
+    run: &str,
+    commit: &str,
+    streams: &[String],
+    stream: &mut UnixStream,
+) -> anyhow::Result<()> {
+    let repo_ino = get_repo_ino(fs, repo_name, stream)?;
+    let repo = fs.get_repo(repo_ino)?;
+    let script_path = repo.chase_dir.join(script);
+
+    let streams = streams
+        .iter()
+        .filter_map(|s| LogStream::from_name(s))
+        .collect::<Vec<LogStream>>();
+    let lines = find_run_dir(&script_path, run)
+        .and_then(|dir| find_log(&dir, commit))
+        .and_then(|path| read_log(&path));
+    let lines = match lines {
+        Ok(l) => l,
+        Err(e) => {
+            stream.update(&format!("Could not read the log: {e}\n"))?;
+            bail!("")
+        }
+    };
+
+    let start = lines.first().map(|l| l.t_stmp).unwrap_or_default();
+    let mut out = String::new();
+    for line in lines {
+        if !streams.is_empty() && !streams.contains(&line.stream) {
+            continue;
+        }
+        let secs = (line.t_stmp - start) as f64 / 1_000_000.0;
+        let text = display_line(&line.line, AnsiMode::Strip);
+        out.push_str(&format!(
+            "[{secs:>10.3}s] {:>4} | {text}",
+            line.stream.as_str()
+        ));
+        if !out.ends_with('\n') {
+            out.push('\n');
+        }
+        // Send in chunks, the log can be big
+        if out.len() > 64 * 1024 {
+            stream.update(&out)?;
+            out.clear();
+        }
+    }
+    stream.update(&out)?;
+    Ok(())
+}
+
+/// Lists the build sessions kept after a chase
+pub fn list_pinned_builds(
+    fs: &GitFs,
+    repo_name: &str,
+    stream: &mut UnixStream,
+) -> anyhow::Result<()> {
+    let repo_ino = get_repo_ino(fs, repo_name, stream)?;
+    let repo = fs.get_repo(repo_ino)?;
+    let pinned = repo.pinned_build_sessions();
+    if pinned.is_empty() {
+        stream.update("No pinned build sessions.\n")?;
+        return Ok(());
+    }
+    let oids = pinned.iter().map(|(oid, _)| oid).collect::<Vec<&Oid>>();
+    let c_refs = validate_commit_refs(fs, repo_ino, &oids).resolve(stream)?;
+    let mut paths = resolve_path_for_refs(fs, repo_ino, c_refs).resolve(stream)?;
+    resolve_detached_snaps(fs, repo_ino, &oids, &mut paths).resolve(stream)?;
+    for (oid, build_path) in &pinned {
+        let snap = paths
+            .get(oid)
+            .map(|(p, _)| p.display().to_string())
+            .unwrap_or_default();
+        stream.update(&format!(
+            "{oid} {snap}\n    build: {}\n",
+            build_path.display()
+        ))?;
+    }
+    Ok(())
+}
+
+/// Releases a pinned build session (or all of them) and removes its files
+pub fn release_pinned_builds(
+    fs: &GitFs,
+    repo_name: &str,
+    commit: Option<&str>,
+    stream: &mut UnixStream,
+) -> anyhow::Result<()> {
+    let repo_ino = get_repo_ino(fs, repo_name, stream)?;
+    let repo = fs.get_repo(repo_ino)?;
+    let oids: Vec<Oid> = match commit {
+        Some(commit) => validate_commits(
+            fs,
+            repo_ino,
+            &[(InputTypes::Commit, commit.to_string())],
+            false,
+        )
+        .resolve(stream)?
+        .into(),
+        None => repo
+            .pinned_build_sessions()
+            .into_iter()
+            .map(|(oid, _)| oid)
+            .collect(),
+    };
+    let pinned = repo.pinned_build_sessions();
+    let oids = oids
+        .iter()
+        .filter(|oid| pinned.iter().any(|(p, _)| p == *oid))
+        .collect::<Vec<&Oid>>();
+    if oids.is_empty() {
+        stream.update("No pinned build sessions to release.\n")?;
+        return Ok(());
+    }
+    let c_refs = validate_commit_refs(fs, repo_ino, &oids).resolve(stream)?;
+    let mut paths = resolve_path_for_refs(fs, repo_ino, c_refs).resolve(stream)?;
+    resolve_detached_snaps(fs, repo_ino, &oids, &mut paths).resolve(stream)?;
+    for oid in oids {
+        repo.set_build_session_pinned(*oid, false);
+        if let Some(&(_, snap_ino)) = paths.get(oid) {
+            remove_build_files(fs, snap_ino)?;
+        }
+        stream.update(&format!("Released build session for commit {oid}\n"))?;
+    }
+    Ok(())
+}
+
+fn run_chase(
+    fs: &GitFs,
+    repo_ino: u64,
+    chase: Chase,
+    dir_path: &Path,
+    record: Option<ChaseRecord>,
+    stream: &mut UnixStream,
+    chase_id: ChaseId,
+) -> anyhow::Result<()> {
     // Cleanup any existing files
     cleanup_builds(fs, repo_ino, &chase)?;
 
@@ -120,23 +650,128 @@ pub fn start_chase(
     check_patches(fs, &chase, stream)?;
 
     // run chase
-    let name = format!("{}", chrono::offset::Utc::now());
-    // Folder to save logs to (if enabled)
-    let dir_path = script_path.join(name);
     let Some(handle) = ChaseHandle::get_handle(&chase_id) else {
         bail!("Error. Chase handle does not exist for id {chase_id}")
     };
     let mut chase_runner: ChaseRunner<'_, UnixStream> =
-        ChaseRunner::new(&dir_path, fs, stream, chase.clone(), handle);
+        ChaseRunner::new(dir_path, fs, stream, chase.clone(), handle);
+    if let Some(record) = record {
+        chase_runner = chase_runner.resume_from(record)?;
+    }
     let _ = chase_runner.run();
 
+    // Pin the sessions to keep, so they survive the cleanup
+    let kept: Vec<Oid> = match chase.args.keep {
+        KeepMode::None => vec![],
+        KeepMode::Failed => chase_runner.failed_commits(),
+        KeepMode::All => chase.commits.iter().copied().collect(),
+    };
+    let repo = fs.get_repo(repo_ino)?;
+    for oid in kept {
+        if repo.set_build_session_pinned(oid, true)
+            && let Some((path, _)) = chase.commit_paths.get(&oid)
+        {
+            chase_runner.reporter.update(&format!(
+                "Kept build session for commit {oid} at {}\n",
+                path.display()
+            ))?;
+        }
+    }
+
     // Cleanup all the files created during the chase
     cleanup_builds(fs, repo_ino, &chase)?;
-
-    ChaseHandle::deregister_chase_id(chase_id);
     Ok(())
 }
 
+/// The live folder uses the commits pinned for its HEAD
+fn resolve_secondary_repos(
+    fs: &GitFs,
+    repo_ino: u64,
+    commits: &VecDeque<Oid>,
+    inputs: &[RepoInput],
+    live: Option<&LiveTarget>,
+    stream: &mut UnixStream,
+) -> anyhow::Result<Vec<SecondaryRepo>> {
+    let head = live.and_then(|l| l.state.head_oid());
+    let mut commits = commits.clone();
+    if let Some(head) = head
+        && !commits.contains(&head)
+    {
+        commits.push_back(head);
+    }
+    let mut repos = Vec::with_capacity(inputs.len());
+    for input in inputs {
+        let (sec_ino, mut pins) =
+            pin_secondary_commits(fs, repo_ino, &commits, input).resolve(stream)?;
+        if let Some(pinned) = head.and_then(|h| pins.get(&h).copied()) {
+            pins.insert(live_oid(), pinned);
+        }
+        repos.push(secondary_repo(fs, &input.name, sec_ino, pins, stream)?);
+    }
+    Ok(repos)
+}
+
+fn resolve_live(
+    fs: &GitFs,
+    repo_ino: u64,
+    mode: LiveMode,
+    stream: &mut UnixStream,
+) -> anyhow::Result<LiveTarget> {
+    match LiveTarget::resolve(fs, repo_ino, mode) {
+        Ok(live) => {
+            stream.update(&format!(
+                "The live folder will be chased after the commits ({})\n",
+                live.state.describe()
+            ))?;
+            Ok(live)
+        }
+        Err(e) => {
+            stream.update(&format!("Could not prepare the live folder: {e:#}\n"))?;
+            bail!("")
+        }
+    }
+}
+
+/// Env variables for the commands, from the daemon env file and the env file of the script
+fn load_env(script_path: &Path, stream: &mut UnixStream) -> anyhow::Result<ChaseEnv> {
+    match ChaseEnv::load(script_path) {
+        Ok(env) => {
+            if !env.is_empty() {
+                stream.update(&format!(
+                    "Loaded {} env variable(s). The values of the secrets are masked in the output.\n",
+                    env.vars().len()
+                ))?;
+            }
+            Ok(env)
+        }
+        Err(e) => {
+            stream.update(&format!("{e:#}\n"))?;
+            bail!("")
+        }
+    }
+}
+
+/// Finds the Snap folders of the pinned commits
+fn secondary_repo(
+    fs: &GitFs,
+    name: &str,
+    sec_ino: u64,
+    pins: HashMap<Oid, Oid>,
+    stream: &mut UnixStream,
+) -> anyhow::Result<SecondaryRepo> {
+    let pinned: BTreeSet<&Oid> = pins.values().collect();
+    let pinned = pinned.into_iter().collect::<Vec<&Oid>>();
+    let sec_refs = validate_commit_refs(fs, sec_ino, &pinned).resolve(stream)?;
+    let mut sec_paths = resolve_path_for_refs(fs, sec_ino, sec_refs).resolve(stream)?;
+    resolve_detached_snaps(fs, sec_ino, &pinned, &mut sec_paths).resolve(stream)?;
+    Ok(SecondaryRepo {
+        name: name.to_string(),
+        repo_ino: sec_ino,
+        pins,
+        commit_paths: sec_paths,
+    })
+}
+
 fn check_patches(fs: &GitFs, chase: &Chase, stream: &mut UnixStream) -> anyhow::Result<()> {
     for (_, snap_ino) in chase.commit_paths.values() {
         for (path, patch) in &chase.patches {
@@ -210,3 +845,22 @@ fn get_repo_ino(fs: &GitFs, repo_name: &str, stream: &mut UnixStream) -> anyhow:
 fn start_message(script: &str) -> String {
     format!("Starting GUSE chase {} \n", script)
 }
+
+/// "Commits outside the refs
+fn detached_message(commits: &[Oid]) -> String {
+    let mut out = format!(
+        "{} commit(s) not found in any ref. Using their Snap folders in {}:\n",
+        commits.len(),
+        crate::fs::COMMITS_FOLDER
+    );
+    for oid in commits {
+        out.push_str(&format!("    {oid}\n"));
+    }
+    out
+}
+
+/// "Resuming GUSE chase
+fn resume_message(script: &str, run_dir: &Path) -> String {
+    let run = run_dir.file_name().unwrap_or_default().to_string_lossy();
+    format!("Resuming GUSE chase {} from run {} \n", script, run)
+}
@@ -0,0 +1,218 @@
+use std::{collections::HashMap, os::unix::net::UnixStream};
+
+use git2::Oid;
+use mlua::Lua;
+
+use crate::fs::{
+    GitFs,
+    builds::{
+        chase_live::LiveState,
+        chase_resolver::{MatchedInputs, find_repo_ino, resolve_chase_commits},
+        reporter::{ErrorResolver, Updater},
+        runtime::{LuaConfig, LuaSandbox},
+    },
+    repo::{GitRepo, RefKind},
+    views::add_git_helpers,
+};
+
+/// Most commits listed when the inputs change
+const MAX_LISTED: usize = 20;
+
+/// The refs of the repo, set as the `repo` table of the console
+#[derive(Debug, Default)]
+pub struct RepoHelpers {
+    pub name: String,
+    pub main: Option<String>,
+    pub branches: Vec<String>,
+    pub tags: Vec<String>,
+    /// PR numbers, with a head or a merge ref
+    pub prs: Vec<String>,
+}
+
+impl RepoHelpers {
+    pub fn new(repo: &GitRepo, name: &str) -> Self {
+        let mut helpers = repo.with_ref_state(|s| {
+            let mut helpers = Self {
+                name: name.to_string(),
+                ..Default::default()
+            };
+            for kind in s.refs_to_snaps.keys() {
+                match kind {
+                    RefKind::Main(n) => {
+                        helpers.main = Some(n.clone());
+                        helpers.branches.push(n.clone());
+                    }
+                    RefKind::Branch(n) => helpers.branches.push(n.clone()),
+                    RefKind::Tag(n) => helpers.tags.push(n.clone()),
+                    RefKind::Pr(n) | RefKind::PrMerge(n) => helpers.prs.push(n.clone()),
+                    RefKind::Head(_) => {}
+                }
+            }
+            helpers
+        });
+        helpers.branches.sort_unstable();
+        helpers.tags.sort_unstable();
+        helpers
+            .prs
+            .sort_unstable_by_key(|n| (n.parse::<u64>().unwrap_or(u64::MAX), n.clone()));
+        helpers.prs.dedup();
+        helpers
+    }
+
+    pub fn set_globals(&self, lua: &Lua) -> mlua::Result<()> {
+        let repo = lua.create_table()?;
+        repo.set("name", self.name.as_str())?;
+        repo.set("main", self.main.as_deref())?;
+        repo.set(
+            "branches",
+            lua.create_sequence_from(self.branches.iter().cloned())?,
+        )?;
+        repo.set("tags", lua.create_sequence_from(self.tags.iter().cloned())?)?;
+        repo.set("prs", lua.create_sequence_from(self.prs.iter().cloned())?)?;
+        lua.globals().set("repo", repo)
+    }
+}
+
+/// Runs the chunks of a `guse lua` session and sends the output of the last one.
+///
+/// When the last chunk changes the inputs, the commits they resolve to are listed.
+/// Returns an error when the chunk should not be kept in the session.
+pub fn eval_lua_console(
+    fs: &GitFs,
+    repo_name: &str,
+    chunks: &[String],
+    stream: &mut UnixStream,
+) -> anyhow::Result<()> {
+    let repo_ino = find_repo_ino(fs, repo_name).resolve(stream)?;
+    let repo = fs.get_repo(repo_ino)?;
+    let helpers = RepoHelpers::new(&repo, repo_name);
+    let eval = LuaConfig::eval_console(chunks, &LuaSandbox::current(), |lua| {
+        helpers.set_globals(lua)?;
+        add_git_helpers(lua, repo.clone())
+    })
+    .resolve(stream)?;
+
+    let mut out = String::new();
+    for line in &eval.output {
+        out.push_str(&format!("{line}\n"));
+    }
+    stream.update(&out)?;
+    if !eval.inputs_changed {
+        return Ok(());
+    }
+
+    let cfg = eval.cfg;
+    if let Some(mode) = cfg.live {
+        match repo.with_repo(|r| LiveState::new(r, mode)) {
+            Ok(state) => stream.update(&format!("Live folder: {}\n", state.describe()))?,
+            Err(e) => stream.update(&format!("Live folder: {e}\n"))?,
+        }
+    }
+    if cfg.commits.is_empty() {
+        stream.update("No commit inputs\n")?;
+        return Ok(());
+    }
+    let (commits, matched) = resolve_chase_commits(fs, repo_ino, &cfg).resolve(stream)?;
+    let summaries = commit_summaries(fs, repo_ino, commits.iter().take(MAX_LISTED))?;
+    stream.update(&resolved_message(
+        &commits.into_iter().collect::<Vec<Oid>>(),
+        &matched,
+        &summaries,
+    ))?;
+    Ok(())
+}
+
+fn commit_summaries<'a>(
+    fs: &GitFs,
+    repo_ino: u64,
+    commits: impl Iterator<Item = &'a Oid>,
+) -> anyhow::Result<HashMap<Oid, String>> {
+    let repo = fs.get_repo(repo_ino)?;
+    Ok(repo.with_repo(|r| {
+        commits
+            .filter_map(|oid| {
+                let commit = r.find_commit(*oid).ok()?;
+                Some((*oid, commit.summary().unwrap_or_default().to_string()))
+            })
+            .collect()
+    }))
+}
+
+/// The number of commits of each input, then the chased commits in order
+fn resolved_message(
+    commits: &[Oid],
+    matched: &MatchedInputs,
+    summaries: &HashMap<Oid, String>,
+) -> String {
+    let mut inputs: Vec<(&str, usize)> = Vec::new();
+    for oid in commits {
+        for label in matched.get(oid).into_iter().flatten() {
+            match inputs.iter_mut().find(|(l, _)| l == label) {
+                Some((_, count)) => *count += 1,
+                None => inputs.push((label, 1)),
+            }
+        }
+    }
+    let mut out = String::new();
+    for (label, count) in inputs {
+        out.push_str(&format!("{label} -> {count} commits\n"));
+    }
+    out.push_str(&format!("{} commits chased:\n", commits.len()));
+    for oid in commits.iter().take(MAX_LISTED) {
+        let labels = matched.get(oid).map(|l| l.join(", ")).unwrap_or_default();
+        let summary = summaries.get(oid).map(String::as_str).unwrap_or_default();
+        out.push_str(&format!("    {oid:.7} {summary} [{labels}]\n"));
+    }
+    if commits.len() > MAX_LISTED {
+        out.push_str(&format!(
+            "    ... and {} more\n",
+            commits.len() - MAX_LISTED
+        ));
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fs::builds::runtime::is_incomplete_chunk;
+
+    #[test]
+    fn test_console_eval() {
+        let helpers = RepoHelpers {
+            name: "guse".to_string(),
+            main: Some("main".to_string()),
+            branches: vec!["dev".to_string(), "main".to_string()],
+            tags: vec!["v1".to_string()],
+            prs: vec!["7".to_string()],
+        };
+        let sandbox = LuaSandbox::default();
+        let eval = |chunks: &[&str]| {
+            let chunks = chunks.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+            LuaConfig::eval_console(&chunks, &sandbox, |lua| helpers.set_globals(lua)).ok()
+        };
+        let branches = "for _, b in ipairs(repo.branches) do cfg.add_commit(\"Branch\", b) end";
+        let session = eval(&["n = 0", branches, "n = #repo.prs; print(\"prs\", n)"]).unwrap();
+        assert_eq!(session.output, ["prs\t1"]);
+        assert!(!session.inputs_changed);
+        assert_eq!(session.cfg.commits.len(), 2);
+
+        assert_eq!(eval(&[branches, "repo.tags"]).unwrap().output, ["{\"v1\"}"]);
+        assert!(
+            eval(&["cfg.set_limit(\"last\", 1)"])
+                .unwrap()
+                .inputs_changed
+        );
+        assert!(eval(&["cfg.nope()"]).is_none());
+        assert!(is_incomplete_chunk("function f()"));
+        assert!(!is_incomplete_chunk("x = 1"));
+
+        let oid = |n: u8| Oid::from_bytes(&[n; 20]).unwrap();
+        let mut matched = MatchedInputs::new();
+        matched.insert(oid(1), vec!["Branch main".to_string()]);
+        matched.insert(oid(2), vec!["Branch main".to_string(), "Pr 7".to_string()]);
+        let message = resolved_message(&[oid(1), oid(2)], &matched, &HashMap::new());
+        assert!(message.starts_with("Branch main -> 2 commits\nPr 7 -> 1 commits\n"));
+        assert!(message.contains("2 commits chased"));
+    }
+}
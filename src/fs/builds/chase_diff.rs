@@ -0,0 +1,189 @@
+use std::path::Path;
+
+use crate::fs::builds::{
+    chase::AnsiMode,
+    chase_log::{find_log, read_log},
+    chase_record::{ChaseRecord, ResultKind},
+    logger::display_line,
+};
+
+/// The result of a commit in a single run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommitStatus {
+    Pass,
+    Fail,
+    /// Not all the commands finished
+    Incomplete,
+}
+
+impl CommitStatus {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pass => "pass",
+            Self::Fail => "fail",
+            Self::Incomplete => "incomplete",
+        }
+    }
+
+    pub fn from_record(record: &ChaseRecord, oid: &str) -> Self {
+        let failed = record
+            .results
+            .iter()
+            .any(|r| r.oid == oid && !matches!(r.result, ResultKind::Ok));
+        if failed {
+            Self::Fail
+        } else if record.completed.iter().any(|c| c == oid) {
+            Self::Pass
+        } else {
+            Self::Incomplete
+        }
+    }
+}
+
+/// The commits of two runs of the same script, matched by oid
+#[derive(Debug, Default)]
+pub struct RunDiff {
+    /// (oid, status in the first run, status in the second run)
+    pub changed: Vec<(String, CommitStatus, CommitStatus)>,
+    pub only_a: Vec<(String, CommitStatus)>,
+    pub only_b: Vec<(String, CommitStatus)>,
+    /// Commits with the same result in both runs
+    pub unchanged: usize,
+}
+
+impl RunDiff {
+    pub fn new(a: &ChaseRecord, b: &ChaseRecord) -> Self {
+        let mut diff = Self::default();
+        for oid in &b.commits {
+            let after = CommitStatus::from_record(b, oid);
+            if !a.commits.contains(oid) {
+                diff.only_b.push((oid.clone(), after));
+                continue;
+            }
+            let before = CommitStatus::from_record(a, oid);
+            if before == after {
+                diff.unchanged += 1;
+            } else {
+                diff.changed.push((oid.clone(), before, after));
+            }
+        }
+        for oid in a.commits.iter().filter(|oid| !b.commits.contains(oid)) {
+            diff.only_a
+                .push((oid.clone(), CommitStatus::from_record(a, oid)));
+        }
+        diff
+    }
+
+    /// Commits that passed in the first run and failed in the second
+    pub fn regressed(&self) -> impl Iterator<Item = &str> {
+        self.changed
+            .iter()
+            .filter(|(_, before, after)| {
+                *before == CommitStatus::Pass && *after == CommitStatus::Fail
+            })
+            .map(|(oid, _, _)| oid.as_str())
+    }
+}
+
+/// The last `n` lines of the log of a commit, as text without colors
+pub fn log_tail(run_dir: &Path, oid: &str, n: usize) -> anyhow::Result<Vec<String>> {
+    let lines = read_log(&find_log(run_dir, oid)?)?;
+    let skip = lines.len().saturating_sub(n);
+    Ok(lines
+        .iter()
+        .skip(skip)
+        .map(|l| {
+            let text = display_line(&l.line, AnsiMode::Strip);
+            format!("{:>4} | {}", l.stream.as_str(), text.trim_end_matches('\n'))
+        })
+        .collect())
+}
+
+/// Line diff of two lists, with the lines prefixed by ' ', '-' or '+'
+pub fn diff_lines(a: &[String], b: &[String]) -> Vec<String> {
+    // Longest common subsequence, the tails are short
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let mut out = Vec::with_capacity(a.len().max(b.len()));
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i] == b[j] {
+            out.push(format!("  {}", a[i]));
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            out.push(format!("- {}", a[i]));
+            i += 1;
+        } else {
+            out.push(format!("+ {}", b[j]));
+            j += 1;
+        }
+    }
+    out.extend(a[i..].iter().map(|l| format!("- {l}")));
+    out.extend(b[j..].iter().map(|l| format!("+ {l}")));
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_run_diff() -> anyhow::Result<()> {
+        let record = |commits: &[&str], completed: &[&str], failed: &[&str]| -> ChaseRecord {
+            let results = failed
+                .iter()
+                .map(|oid| serde_json::json!({"pos": 1, "oid": oid, "result": {"exit_fail": 256}}))
+                .collect::<Vec<_>>();
+            serde_json::from_value(serde_json::json!({
+                "commits": commits,
+                "commands": [],
+                "run_mode": "Continuous",
+                "stop_mode": "Continuous",
+                "patches": [],
+                "repos": [],
+                "args": {"chase-args": "chase_args", "log": false, "no_move": false},
+                "completed": completed,
+                "results": results,
+            }))
+            .unwrap()
+        };
+        let a = record(
+            &["a1", "b2", "c3", "d4"],
+            &["a1", "b2", "c3", "d4"],
+            &["c3"],
+        );
+        let b = record(&["a1", "b2", "c3", "e5"], &["a1", "b2", "c3"], &["b2"]);
+        let diff = RunDiff::new(&a, &b);
+        assert_eq!(diff.unchanged, 1);
+        assert_eq!(
+            diff.changed,
+            vec![
+                ("b2".to_string(), CommitStatus::Pass, CommitStatus::Fail),
+                ("c3".to_string(), CommitStatus::Fail, CommitStatus::Pass),
+            ]
+        );
+        assert_eq!(diff.regressed().collect::<Vec<_>>(), vec!["b2"]);
+        assert_eq!(diff.only_a, vec![("d4".to_string(), CommitStatus::Pass)]);
+        assert_eq!(
+            diff.only_b,
+            vec![("e5".to_string(), CommitStatus::Incomplete)]
+        );
+
+        let before = ["one", "two", "three"].map(String::from);
+        let after = ["one", "2", "three", "four"].map(String::from);
+        assert_eq!(
+            diff_lines(&before, &after),
+            vec!["  one", "- two", "+ 2", "  three", "+ four"]
+        );
+        Ok(())
+    }
+}
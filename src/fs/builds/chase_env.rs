@@ -0,0 +1,195 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use anyhow::{Context, bail};
+
+/// Name of the env file, next to the chase.lua of a script
+pub const ENV_FILE: &str = "env";
+/// Loaded for every chase, before the env file of the script. Set once, from `guse run --chase-env`
+pub static CHASE_ENV_FILE: OnceLock<PathBuf> = OnceLock::new();
+
+/// Same for every secret, so the output does not tell their length
+const MASK: &[u8] = b"***";
+/// Shorter values are not masked, they would hide too much of the output
+const MIN_MASKED_LEN: usize = 4;
+/// The values of the variables with one of these words in their name are masked
+const SECRET_WORDS: &[&str] = &[
+    "TOKEN",
+    "SECRET",
+    "PASS",
+    "KEY",
+    "AUTH",
+    "CREDENTIAL",
+    "PRIVATE",
+];
+
+/// Env variables (tokens, registry settings...) set for every command of a chase.
+///
+/// They are never saved in the chase record or the report. The values of the secrets are masked in the output.
+#[derive(Default, Clone)]
+pub struct ChaseEnv {
+    vars: Vec<(String, String)>,
+    /// Values of the secret looking variables, longest first
+    secrets: Vec<Vec<u8>>,
+}
+
+impl ChaseEnv {
+    /// Reads the daemon env file, then the env file of the script. The script wins on duplicate keys.
+    pub fn load(script_path: &Path) -> anyhow::Result<Self> {
+        let mut env = Self::default();
+        let script_file = script_path.join(ENV_FILE);
+        let files = CHASE_ENV_FILE
+            .get()
+            .map(PathBuf::as_path)
+            .into_iter()
+            .chain(script_file.exists().then_some(script_file.as_path()));
+        for path in files {
+            let data = std::fs::read_to_string(path)
+                .with_context(|| format!("Could not read env file {}", path.display()))?;
+            let vars =
+                parse_env(&data).with_context(|| format!("Bad env file {}", path.display()))?;
+            for (key, value) in vars {
+                env.vars.retain(|(k, _)| *k != key);
+                env.vars.push((key, value));
+            }
+        }
+        env.secrets = env
+            .vars
+            .iter()
+            .filter(|(k, v)| is_secret(k) && v.len() >= MIN_MASKED_LEN)
+            .map(|(_, v)| v.as_bytes().to_vec())
+            .collect();
+        // Longest first, a value can contain another one
+        env.secrets
+            .sort_unstable_by_key(|v| std::cmp::Reverse(v.len()));
+        Ok(env)
+    }
+
+    pub fn vars(&self) -> &[(String, String)] {
+        &self.vars
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vars.is_empty()
+    }
+
+    /// Replaces the secret values found in the line. None if there was nothing to mask.
+    pub fn mask(&self, line: &[u8]) -> Option<Vec<u8>> {
+        let mut out: Option<Vec<u8>> = None;
+        for value in &self.secrets {
+            let current = out.as_deref().unwrap_or(line);
+            if let Some(masked) = replace_all(current, value, MASK) {
+                out = Some(masked);
+            }
+        }
+        out
+    }
+
+    pub fn mask_str(&self, text: &str) -> String {
+        match self.mask(text.as_bytes()) {
+            Some(masked) => String::from_utf8_lossy(&masked).into_owned(),
+            None => text.to_string(),
+        }
+    }
+
+    /// Bytes to read past the end of a capped line, so a secret cut by the cap is still found
+    pub fn lookahead(&self) -> usize {
+        self.secrets
+            .first()
+            .map_or(0, |longest| longest.len().saturating_sub(1))
+    }
+}
+
+fn is_secret(key: &str) -> bool {
+    let key = key.to_ascii_uppercase();
+    SECRET_WORDS.iter().any(|word| key.contains(word))
+}
+
+fn replace_all(haystack: &[u8], needle: &[u8], with: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut found = false;
+    let mut i = 0;
+    while i < haystack.len() {
+        if haystack[i..].starts_with(needle) {
+            out.extend_from_slice(with);
+            i += needle.len();
+            found = true;
+        } else {
+            out.push(haystack[i]);
+            i += 1;
+        }
+    }
+    found.then_some(out)
+}
+
+/// KEY=VALUE lines. Empty lines and lines starting with # are skipped, `export` and quotes are allowed.
+pub fn parse_env(data: &str) -> anyhow::Result<Vec<(String, String)>> {
+    let mut vars = Vec::new();
+    for (i, line) in data.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let Some((key, value)) = line.split_once('=') else {
+            bail!("Line {} is not KEY=VALUE", i + 1);
+        };
+        let key = key.trim();
+        let valid_key = !key.is_empty()
+            && !key.starts_with(|c: char| c.is_ascii_digit())
+            && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid_key {
+            bail!("Bad variable name {key:?} on line {}", i + 1);
+        }
+        let value = value.trim();
+        let value = ['"', '\'']
+            .iter()
+            .find_map(|q| value.strip_prefix(*q).and_then(|v| v.strip_suffix(*q)))
+            .unwrap_or(value);
+        vars.push((key.to_string(), value.to_string()));
+    }
+    Ok(vars)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_env_file_and_mask() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::write(
+            dir.path().join(ENV_FILE),
+            "# local registry\nexport TOKEN=\"s3cr3t-token\"\nPORT = 8080\nSHORT_KEY=ab\nTOKEN=s3cr3t-token-2\nnpm_auth=hunter22\n",
+        )?;
+        let env = ChaseEnv::load(dir.path())?;
+        assert_eq!(
+            env.vars(),
+            [
+                ("PORT".to_string(), "8080".to_string()),
+                ("SHORT_KEY".to_string(), "ab".to_string()),
+                ("TOKEN".to_string(), "s3cr3t-token-2".to_string()),
+                ("npm_auth".to_string(), "hunter22".to_string()),
+            ]
+        );
+
+        // Only the secrets are masked, the port is not one
+        let masked = env.mask(b"auth s3cr3t-token-2 hunter22 on port 8080, ab\n");
+        assert_eq!(
+            masked.as_deref(),
+            Some(&b"auth *** *** on port 8080, ab\n"[..])
+        );
+        assert!(env.mask(b"nothing to hide\n").is_none());
+        assert_eq!(env.lookahead(), "s3cr3t-token-2".len() - 1);
+        assert_eq!(
+            env.mask_str("Failed: TOKEN=\"s3cr3t-token-2\""),
+            "Failed: TOKEN=\"***\""
+        );
+
+        assert!(parse_env("NO_VALUE\n").is_err());
+        assert!(parse_env("1BAD=x\n").is_err());
+        Ok(())
+    }
+}
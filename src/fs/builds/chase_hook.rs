@@ -0,0 +1,283 @@
+use std::{
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::fs::builds::{
+    chase_live::LiveState, chase_record::ResultRecord, job::Job, logger::CmdResult,
+};
+
+/// Name of the file holding the ChaseReport, inside the folder of each run
+pub const REPORT_FILE: &str = "report.json";
+/// Name of the file holding the output of the hook commands, inside the folder of each run
+pub const HOOKS_LOG: &str = "hooks.log";
+/// Seconds a hook command can run before it is terminated
+pub const HOOK_TIMEOUT: u64 = 60;
+
+/// Local commands and files notified when a chase ends.
+///
+/// Set for all chases with `guse run --chase-hook/--chase-notify`, or per script with cfg.add_hook/cfg.add_notify_file.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ChaseHooks {
+    /// Each command receives the path to the JSON report on stdin
+    pub commands: Vec<String>,
+    /// A line summarizing the chase is appended to each file
+    pub notify_files: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChaseOutcome {
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+impl ChaseOutcome {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Completed => "completed",
+            Self::Failed => "failed",
+            Self::Cancelled => "cancelled",
+        }
+    }
+}
+
+/// Summary of a finished chase, saved in the run folder and handed to the hooks
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChaseReport {
+    pub repo: String,
+    pub script: String,
+    pub run_dir: PathBuf,
+    pub outcome: ChaseOutcome,
+    pub total: usize,
+    pub completed: usize,
+    /// Commits with at least one failed command
+    pub failed: Vec<String>,
+    pub results: Vec<ResultRecord>,
+    /// HEAD and the hash of the uncommitted changes, when the live folder was chased.
+    /// Its results use the zero oid.
+    #[serde(default)]
+    pub live: Option<LiveState>,
+}
+
+impl ChaseReport {
+    pub fn save(&self, dir: &Path) -> anyhow::Result<PathBuf> {
+        let path = dir.join(REPORT_FILE);
+        let data = serde_json::to_vec_pretty(self)?;
+        std::fs::write(&path, data)?;
+        Ok(path)
+    }
+
+    /// The line appended to the notification files
+    pub fn summary(&self, report_path: &Path) -> String {
+        format!(
+            "{}\t{}\t{}/{}\t{}/{}\t{}/{} failed\t{}\n",
+            chrono::offset::Utc::now().format("%Y-%m-%d %H:%M:%S"),
+            self.outcome.as_str(),
+            self.repo,
+            self.script,
+            self.completed,
+            self.total,
+            self.failed.len(),
+            self.total,
+            report_path.display()
+        )
+    }
+}
+
+/// How the hook commands of a chase are ran
+#[derive(Debug, Clone, Copy)]
+pub struct HookRun<'a> {
+    /// The output of the commands is appended to this file. Dropped when None.
+    pub log: Option<&'a Path>,
+    /// Time between SIGTERM and SIGKILL, when a hook is terminated
+    pub grace: Duration,
+    pub timeout: Duration,
+}
+
+impl ChaseHooks {
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty() && self.notify_files.is_empty()
+    }
+
+    /// Adds the hooks of other, skipping the ones already present
+    pub fn merge(&mut self, other: &ChaseHooks) {
+        for command in &other.commands {
+            if !self.commands.contains(command) {
+                self.commands.push(command.clone());
+            }
+        }
+        for file in &other.notify_files {
+            if !self.notify_files.contains(file) {
+                self.notify_files.push(file.clone());
+            }
+        }
+    }
+
+    /// Runs every hook. A failing hook does not stop the others.
+    ///
+    /// Returns the errors, so they can be reported to the user
+    pub fn notify(&self, report: &ChaseReport, report_path: &Path, run: HookRun) -> Vec<String> {
+        let mut errors = Vec::new();
+        for command in &self.commands {
+            if let Err(e) = run_hook(command, report_path, run) {
+                errors.push(format!("Hook {command:?} failed: {e}"));
+            }
+        }
+        let line = report.summary(report_path);
+        for file in &self.notify_files {
+            if let Err(e) = append_line(file, &line) {
+                errors.push(format!(
+                    "Could not write to notification file {}: {e}",
+                    file.display()
+                ));
+            }
+        }
+        errors
+    }
+}
+
+/// Runs a hook as a job, so it is terminated with everything it started once it exits or times out
+fn run_hook(command: &str, report_path: &Path, run: HookRun) -> anyhow::Result<()> {
+    let parts = shell_words::split(command).context("Error parsing command")?;
+    let Some((prog, args)) = parts.split_first() else {
+        bail!("Empty command");
+    };
+    let mut cmd = Command::new(prog);
+    cmd.args(args).stdin(Stdio::piped());
+    let mut job = match Job::spawn(cmd, run.grace) {
+        CmdResult::Ok(job) => job,
+        CmdResult::Err(e) => bail!("{}", e.trim_end()),
+        CmdResult::ExitFail(e) => bail!("{e}"),
+    };
+    if let Some(mut stdin) = job.child.stdin.take() {
+        // The hook is free to ignore its stdin
+        let _ = writeln!(stdin, "{}", report_path.display());
+    }
+    // Read in threads, so a hook with a lot of output does not block on a full pipe
+    let stdout = job.child.stdout.take().map(read_all);
+    let stderr = job.child.stderr.take().map(read_all);
+
+    let stop_time = Instant::now() + run.timeout;
+    let status = loop {
+        if let Some(status) = job.child.try_wait()? {
+            break Some(status);
+        }
+        if Instant::now() >= stop_time {
+            break None;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    };
+    // Also stops the processes left running by the hook, which would keep the pipes open
+    job.terminate();
+
+    if let Some(log) = run.log {
+        let mut output = format!("==> {command}\n").into_bytes();
+        for reader in [stdout, stderr].into_iter().flatten() {
+            output.extend(reader.join().unwrap_or_default());
+        }
+        append_line(log, &String::from_utf8_lossy(&output))?;
+    }
+    match status {
+        None => bail!("timed out after {}s", run.timeout.as_secs()),
+        Some(status) if !status.success() => bail!("{status}"),
+        Some(_) => Ok(()),
+    }
+}
+
+fn read_all(mut pipe: impl Read + Send + 'static) -> JoinHandle<Vec<u8>> {
+    std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        let _ = pipe.read_to_end(&mut buf);
+        buf
+    })
+}
+
+fn append_line(path: &Path, line: &str) -> std::io::Result<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    file.write_all(line.as_bytes())
+}
+
+#[cfg(test)]
+mod test {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+
+    #[test]
+    fn test_chase_hooks_notify() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let received = dir.path().join("received");
+        let stub = dir.path().join("stub.sh");
+        std::fs::write(&stub, "#!/bin/sh\nread report\ncp \"$report\" \"$1\"\n")?;
+        std::fs::set_permissions(&stub, std::fs::Permissions::from_mode(0o755))?;
+
+        let report = ChaseReport {
+            repo: "repo".to_string(),
+            script: "script".to_string(),
+            run_dir: dir.path().to_path_buf(),
+            outcome: ChaseOutcome::Failed,
+            total: 3,
+            completed: 2,
+            failed: vec!["abcdef1".to_string()],
+            results: vec![],
+            live: None,
+        };
+        let report_path = report.save(dir.path())?;
+
+        let notify_file = dir.path().join("notifications");
+        let hooks = ChaseHooks {
+            commands: vec![
+                format!("{} {}", stub.display(), received.display()),
+                "false".to_string(),
+            ],
+            notify_files: vec![notify_file.clone()],
+        };
+        let log = dir.path().join(HOOKS_LOG);
+        let run = HookRun {
+            log: Some(&log),
+            grace: Duration::from_millis(200),
+            timeout: Duration::from_secs(10),
+        };
+        let errors = hooks.notify(&report, &report_path, run);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("false"));
+
+        let received: ChaseReport = serde_json::from_slice(&std::fs::read(&received)?)?;
+        assert_eq!(received.outcome, ChaseOutcome::Failed);
+        assert_eq!(received.failed, vec!["abcdef1".to_string()]);
+
+        hooks.notify(&report, &report_path, run);
+        let lines = std::fs::read_to_string(&notify_file)?;
+        assert_eq!(lines.lines().count(), 2);
+        assert!(lines.contains("failed\trepo/script\t2/3\t1/3 failed"));
+
+        // A hung hook is terminated, and its output is logged
+        let hung = ChaseHooks {
+            commands: vec!["sh -c 'echo waiting; sleep 30'".to_string()],
+            notify_files: vec![],
+        };
+        let start = Instant::now();
+        let run = HookRun {
+            timeout: Duration::from_millis(300),
+            ..run
+        };
+        let errors = hung.notify(&report, &report_path, run);
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("timed out"));
+        let logged = std::fs::read_to_string(&log)?;
+        assert!(logged.contains("==> sh -c 'echo waiting; sleep 30'\nwaiting\n"));
+        Ok(())
+    }
+}
@@ -0,0 +1,222 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use git2::{Oid, Repository, Status, StatusOptions};
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+
+use crate::fs::{GitFs, LIVE_FOLDER, builds::chase_runner::clone_file};
+
+/// How the commands of a chase run on the live folder, set with `{Live = true}` or `{Live = "copy"}`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LiveMode {
+    /// In the live folder itself. Build files are left in it.
+    InPlace,
+    /// In a copy of the live folder (without .git and the ignored files), removed after the chase
+    Copy,
+}
+
+/// The state of the live folder when it was chased. Saved in the chase record and the report.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LiveState {
+    pub mode: LiveMode,
+    /// None when HEAD does not point to a commit yet
+    pub head: Option<String>,
+    /// blake3 hash of the uncommitted changes, None when the working tree is clean
+    pub dirty: Option<String>,
+}
+
+impl LiveState {
+    pub fn new(repo: &Repository, mode: LiveMode) -> anyhow::Result<Self> {
+        let head = repo
+            .head()
+            .ok()
+            .and_then(|h| h.peel_to_commit().ok())
+            .map(|c| c.id().to_string());
+        Ok(Self {
+            mode,
+            head,
+            dirty: dirty_hash(repo)?,
+        })
+    }
+
+    pub fn head_oid(&self) -> Option<Oid> {
+        self.head.as_deref().and_then(|h| Oid::from_str(h).ok())
+    }
+
+    pub fn describe(&self) -> String {
+        let head = match &self.head {
+            Some(head) => format!("HEAD {head:.7}"),
+            None => "no HEAD".to_string(),
+        };
+        match &self.dirty {
+            Some(dirty) => format!("{head}, dirty {dirty:.12}"),
+            None => format!("{head}, clean"),
+        }
+    }
+}
+
+/// The live folder, chased after the commits
+#[derive(Clone)]
+pub struct LiveTarget {
+    pub state: LiveState,
+    pub path: PathBuf,
+    /// Deleted when the chase is dropped
+    _copy: Option<Arc<TempDir>>,
+}
+
+impl LiveTarget {
+    pub fn resolve(fs: &GitFs, repo_ino: u64, mode: LiveMode) -> anyhow::Result<Self> {
+        let repo = fs.get_repo(repo_ino)?;
+        let state = repo.with_repo(|r| LiveState::new(r, mode))?;
+        match mode {
+            LiveMode::InPlace => Ok(Self {
+                state,
+                path: fs.mount_point.join(&repo.repo_dir).join(LIVE_FOLDER),
+                _copy: None,
+            }),
+            LiveMode::Copy => {
+                let copy = tempfile::Builder::new()
+                    .prefix("live_")
+                    .tempdir_in(&repo.build_dir)?;
+                repo.with_repo(|r| copy_worktree(r, copy.path()))?;
+                Ok(Self {
+                    state,
+                    path: copy.path().to_path_buf(),
+                    _copy: Some(Arc::new(copy)),
+                })
+            }
+        }
+    }
+}
+
+/// Results, logs and tests of the live folder are stored under this oid
+pub fn live_oid() -> Oid {
+    Oid::zero()
+}
+
+pub fn is_live(oid: &Oid) -> bool {
+    oid.is_zero()
+}
+
+/// Hashes the status and the content of every changed or untracked file. Ignored files are skipped.
+fn dirty_hash(repo: &Repository) -> anyhow::Result<Option<String>> {
+    let Some(workdir) = repo.workdir() else {
+        return Ok(None);
+    };
+    let statuses = repo.statuses(Some(&mut worktree_status(false)))?;
+    let mut entries = statuses
+        .iter()
+        .filter_map(|e| Some((e.path()?.to_string(), e.status().bits())))
+        .collect::<Vec<(String, u32)>>();
+    if entries.is_empty() {
+        return Ok(None);
+    }
+    entries.sort_unstable();
+    let mut hasher = blake3::Hasher::new();
+    for (path, status) in entries {
+        hasher.update(path.as_bytes());
+        hasher.update(&status.to_le_bytes());
+        // Deleted files only count by their status
+        if let Ok(content) = std::fs::read(workdir.join(&path)) {
+            hasher.update(&(content.len() as u64).to_le_bytes());
+            hasher.update(&content);
+        }
+    }
+    Ok(Some(hasher.finalize().to_hex().to_string()))
+}
+
+/// The changed and untracked files of the working tree, or all of its files with `unmodified`. Ignored files are skipped.
+fn worktree_status(unmodified: bool) -> StatusOptions {
+    let mut opts = StatusOptions::new();
+    opts.include_untracked(true)
+        .recurse_untracked_dirs(true)
+        .include_ignored(false)
+        .include_unmodified(unmodified);
+    opts
+}
+
+/// Copies the files of the working tree that git sees, so .git and the ignored build files are left out
+fn copy_worktree(repo: &Repository, dst: &Path) -> anyhow::Result<()> {
+    let Some(workdir) = repo.workdir() else {
+        anyhow::bail!("The repo has no working tree");
+    };
+    let statuses = repo.statuses(Some(&mut worktree_status(true)))?;
+    for entry in statuses.iter() {
+        if entry.status().contains(Status::WT_DELETED) {
+            continue;
+        }
+        let Some(path) = entry.path() else {
+            continue;
+        };
+        let src = workdir.join(path);
+        let target = dst.join(path);
+        let Ok(metadata) = std::fs::symlink_metadata(&src) else {
+            continue;
+        };
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        if metadata.is_symlink() {
+            std::os::unix::fs::symlink(std::fs::read_link(&src)?, &target)?;
+        } else if metadata.is_file() {
+            clone_file(&src, &target)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_live_state_and_copy() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let repo = Repository::init(dir.path())?;
+        std::fs::write(dir.path().join("a.txt"), b"one")?;
+        let mut index = repo.index()?;
+        index.add_path(Path::new("a.txt"))?;
+        let tree = repo.find_tree(index.write_tree()?)?;
+        let sig = git2::Signature::now("guse", "guse@localhost")?;
+        let head = repo.commit(Some("HEAD"), &sig, &sig, "first", &tree, &[])?;
+
+        let clean = LiveState::new(&repo, LiveMode::InPlace)?;
+        assert_eq!(clean.head_oid(), Some(head));
+        assert_eq!(clean.dirty, None);
+
+        std::fs::write(dir.path().join("a.txt"), b"two")?;
+        let dirty = LiveState::new(&repo, LiveMode::InPlace)?;
+        assert!(dirty.dirty.is_some());
+        std::fs::write(dir.path().join("b.txt"), b"new")?;
+        let untracked = LiveState::new(&repo, LiveMode::InPlace)?;
+        assert_ne!(untracked.dirty, dirty.dirty);
+        assert_eq!(
+            LiveState::new(&repo, LiveMode::InPlace)?.dirty,
+            untracked.dirty
+        );
+
+        std::fs::write(dir.path().join(".gitignore"), b"target/\n")?;
+        std::fs::create_dir_all(dir.path().join("target/debug"))?;
+        std::fs::write(dir.path().join("target/debug/app"), b"built")?;
+        std::fs::create_dir(dir.path().join("src"))?;
+        std::fs::write(dir.path().join("src/lib.rs"), b"lib")?;
+        std::os::unix::fs::symlink("a.txt", dir.path().join("link"))?;
+
+        let copy = tempfile::tempdir()?;
+        copy_worktree(&repo, copy.path())?;
+        assert_eq!(std::fs::read(copy.path().join("a.txt"))?, b"two");
+        assert!(copy.path().join("b.txt").exists());
+        assert_eq!(std::fs::read(copy.path().join("src/lib.rs"))?, b"lib");
+        assert_eq!(
+            std::fs::read_link(copy.path().join("link"))?,
+            Path::new("a.txt")
+        );
+        assert!(!copy.path().join(".git").exists());
+        assert!(!copy.path().join("target").exists());
+        Ok(())
+    }
+}
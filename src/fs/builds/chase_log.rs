@@ -0,0 +1,259 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::bail;
+
+use crate::{
+    fs::builds::logger::LogLine,
+    internals::store::{BinDecode, BinEncode},
+};
+
+/// Extension of a log file while the commit is running
+pub const LOG_EXT: &str = "log";
+/// Extension of a finished log file, compressed with zstd
+pub const COMPRESSED_EXT: &str = "log.zst";
+/// Default size cap for the log of a single commit
+pub const DEFAULT_LOG_LIMIT: u64 = 64 * 1024 * 1024;
+
+pub const HEADER: [u8; 4] = [b'G', b'L', b'O', b'G'];
+pub const VERSION: [u8; 4] = 1_u32.to_le_bytes();
+
+/// The stream a line of output came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LogStream {
+    Stdout,
+    Stderr,
+    /// Messages written by guse during the chase
+    Guse,
+}
+
+impl LogStream {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Stdout => "out",
+            Self::Stderr => "err",
+            Self::Guse => "guse",
+        }
+    }
+
+    pub fn from_name(stream: &str) -> Option<Self> {
+        match stream.to_lowercase().as_str() {
+            "out" | "stdout" => Some(Self::Stdout),
+            "err" | "stderr" => Some(Self::Stderr),
+            "guse" => Some(Self::Guse),
+            _ => None,
+        }
+    }
+
+    pub fn into_var(&self) -> u8 {
+        match self {
+            Self::Stdout => b'O',
+            Self::Stderr => b'E',
+            Self::Guse => b'G',
+        }
+    }
+
+    pub fn from_var(var: u8) -> Option<Self> {
+        match var {
+            b'O' => Some(Self::Stdout),
+            b'E' => Some(Self::Stderr),
+            b'G' => Some(Self::Guse),
+            _ => None,
+        }
+    }
+}
+
+/// The log file of a single commit in a chase.
+///
+/// Every line is stored with its stream and a monotonic timestamp (micros).
+/// Once the size cap is reached, a truncation marker is written and the rest of the lines are dropped.
+///
+/// The file is compressed when the commit is finished.
+pub struct ChaseLog {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    written: u64,
+    limit: u64,
+    dropped: u64,
+}
+
+impl ChaseLog {
+    pub fn create(dir: &Path, name: &str, limit: u64) -> std::io::Result<Self> {
+        let path = dir.join(format!("{name}.{LOG_EXT}"));
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .create(true)
+            .open(&path)?;
+        let mut writer = BufWriter::new(file);
+        writer.write_all(&HEADER)?;
+        writer.write_all(&VERSION)?;
+        Ok(Self {
+            path,
+            writer,
+            written: 0,
+            limit,
+            dropped: 0,
+        })
+    }
+
+    pub fn write(&mut self, line: &LogLine) -> std::io::Result<()> {
+        if self.dropped > 0 {
+            self.dropped += 1;
+            return Ok(());
+        }
+        let len = line.line.len() as u64;
+        if self.written + len > self.limit {
+            self.dropped += 1;
+            let marker = format!("--- Log truncated after {} bytes ---\n", self.written);
+            return LogLine::guse(marker.as_bytes()).bin_store(&mut self.writer);
+        }
+        self.written += len;
+        line.bin_store(&mut self.writer)
+    }
+
+    /// Compresses the log with zstd and removes the uncompressed file
+    pub fn finish(mut self) -> std::io::Result<PathBuf> {
+        if self.dropped > 0 {
+            let marker = format!("--- {} lines were dropped ---\n", self.dropped);
+            LogLine::guse(marker.as_bytes()).bin_store(&mut self.writer)?;
+        }
+        self.writer.flush()?;
+        drop(self.writer);
+
+        let compressed = self.path.with_extension(COMPRESSED_EXT);
+        let mut src = File::open(&self.path)?;
+        let dst = File::create(&compressed)?;
+        zstd::stream::copy_encode(&mut src, dst, 0)?;
+        std::fs::remove_file(&self.path)?;
+        Ok(compressed)
+    }
+}
+
+/// Reads back a log file, compressed or not
+pub fn read_log(path: &Path) -> anyhow::Result<Vec<LogLine>> {
+    let file = File::open(path)?;
+    let mut reader: Box<dyn Read> = if path.to_string_lossy().ends_with(COMPRESSED_EXT) {
+        Box::new(zstd::stream::Decoder::new(file)?)
+    } else {
+        Box::new(BufReader::new(file))
+    };
+
+    let mut header = [0u8; 4];
+    reader.read_exact(&mut header)?;
+    if header != HEADER {
+        bail!("{} is not a chase log", path.display())
+    }
+    let mut version = [0u8; 4];
+    reader.read_exact(&mut version)?;
+    if version != VERSION {
+        bail!("Wrong version for chase log {}", path.display())
+    }
+
+    let mut lines = Vec::new();
+    loop {
+        match LogLine::bin_load(&mut reader) {
+            Ok(line) => lines.push(line),
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(lines)
+}
+
+/// Finds the log of a commit in a run folder.
+///
+/// The commit can be a hash (or prefix) or the position of the commit in the chase.
+pub fn find_log(run_dir: &Path, commit: &str) -> anyhow::Result<PathBuf> {
+    let mut found = Vec::new();
+    for entry in std::fs::read_dir(run_dir)? {
+        let path = entry?.path();
+        let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        let Some(stem) = name
+            .strip_suffix(&format!(".{COMPRESSED_EXT}"))
+            .or_else(|| name.strip_suffix(&format!(".{LOG_EXT}")))
+        else {
+            continue;
+        };
+        let Some((pos, oid)) = stem.split_once('_') else {
+            continue;
+        };
+        // Short inputs are positions, the rest are hashes (the file only holds 7 chars)
+        let by_pos = commit.len() < 4 && commit.parse::<usize>().ok() == pos.parse().ok();
+        let by_oid = commit.len() >= 4 && (oid.starts_with(commit) || commit.starts_with(oid));
+        if by_pos || by_oid {
+            found.push(path);
+        }
+    }
+    match found.len() {
+        0 => bail!("No log found for commit {commit} in {}", run_dir.display()),
+        1 => Ok(found.remove(0)),
+        _ => bail!("Multiple logs found for commit {commit}. Please use more HASH characters"),
+    }
+}
+
+impl BinEncode for LogLine {
+    fn bin_store<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writer.write_all(&(self.t_stmp as u64).to_le_bytes())?;
+        writer.write_all(&[self.stream.into_var()])?;
+        (self.line.len() as u32).bin_store(writer)?;
+        writer.write_all(&self.line)
+    }
+}
+
+impl BinDecode for LogLine {
+    fn bin_load<R: Read>(reader: &mut R) -> std::io::Result<Self> {
+        let mut stamp = [0u8; 8];
+        reader.read_exact(&mut stamp)?;
+        let mut var = [0u8; 1];
+        reader.read_exact(&mut var)?;
+        let Some(stream) = LogStream::from_var(var[0]) else {
+            return Err(std::io::Error::other("Invalid log stream"));
+        };
+        let len = u32::bin_load(reader)?;
+        let mut line = vec![0u8; len as usize];
+        reader.read_exact(&mut line)?;
+        Ok(Self {
+            t_stmp: u64::from_le_bytes(stamp) as u128,
+            stream,
+            line,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_chase_log_truncate_and_compress() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut log = ChaseLog::create(dir.path(), "01_abcdef1", 10)?;
+        log.write(&LogLine::new(b"hello\n", LogStream::Stdout))?;
+        log.write(&LogLine::new(b"err\n", LogStream::Stderr))?;
+        log.write(&LogLine::new(b"over the cap\n", LogStream::Stdout))?;
+        log.write(&LogLine::new(b"dropped\n", LogStream::Stdout))?;
+        let path = log.finish()?;
+
+        assert!(path.to_string_lossy().ends_with(COMPRESSED_EXT));
+        assert!(!dir.path().join("01_abcdef1.log").exists());
+        assert_eq!(find_log(dir.path(), "1")?, path);
+        assert_eq!(find_log(dir.path(), "abcdef1234")?, path);
+
+        let lines = read_log(&path)?;
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0].stream, LogStream::Stdout);
+        assert_eq!(lines[0].line, b"hello\n");
+        assert_eq!(lines[1].stream, LogStream::Stderr);
+        assert_eq!(lines[2].stream, LogStream::Guse);
+        assert!(String::from_utf8_lossy(&lines[2].line).contains("truncated"));
+        assert!(String::from_utf8_lossy(&lines[3].line).contains("2 lines were dropped"));
+        assert!(lines[0].t_stmp <= lines[1].t_stmp);
+        Ok(())
+    }
+}
@@ -0,0 +1,463 @@
+use std::{
+    collections::HashMap,
+    os::unix::process::ExitStatusExt,
+    path::{Path, PathBuf},
+    process::ExitStatus,
+    sync::LazyLock,
+};
+
+use anyhow::{Context, bail};
+use git2::Oid;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::fs::{
+    GitFs,
+    builds::{
+        chase::{Chase, ChaseArgs},
+        chase_hook::ChaseHooks,
+        chase_live::LiveState,
+        chase_log::find_log,
+        chase_repro::{ArtifactHashes, ReproCheck},
+        chase_tests::TestStatus,
+        logger::CmdResult,
+        runtime::{ChaseCommand, ChaseRunMode, ChaseSteps, ChaseStopMode},
+    },
+};
+
+/// Name of the file holding the ChaseRecord, inside the folder of each run
+pub const RECORD_FILE: &str = "chase.json";
+
+/// (script folder, commit) -> latest result of the commit
+type ResultsCache = HashMap<(PathBuf, String), Option<CommitResult>>;
+
+/// The latest result of each (script folder, commit), shown in the .chase folders.
+///
+/// The entries of a script are dropped when one of its records is saved.
+static RESULTS_CACHE: LazyLock<Mutex<ResultsCache>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// The state of a chase, saved in the run folder and updated after every commit.
+///
+/// Holds everything needed to resume a chase with the same settings, without reading the script again.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChaseRecord {
+    pub commits: Vec<String>,
+    /// The inputs of the script that matched each commit
+    #[serde(default)]
+    pub matched: Vec<(String, Vec<String>)>,
+    pub commands: Vec<ChaseCommand>,
+    #[serde(default)]
+    pub steps: ChaseSteps,
+    pub run_mode: ChaseRunMode,
+    pub stop_mode: ChaseStopMode,
+    pub patches: Vec<(PathBuf, String)>,
+    pub repos: Vec<RepoRecord>,
+    pub args: ChaseArgs,
+    #[serde(default)]
+    pub hooks: ChaseHooks,
+    #[serde(default)]
+    pub repro: Option<ReproCheck>,
+    /// The live folder, chased after the commits under the zero oid
+    #[serde(default)]
+    pub live: Option<LiveState>,
+    /// Commits for which all the commands have finished
+    pub completed: Vec<String>,
+    pub results: Vec<ResultRecord>,
+    /// The results of single tests, for each completed commit
+    #[serde(default)]
+    pub tests: Vec<(String, Vec<(String, TestStatus)>)>,
+    /// The hashes of the artifacts, for each completed commit
+    #[serde(default)]
+    pub artifacts: Vec<(String, ArtifactHashes)>,
+}
+
+/// A secondary repo and the commits it was pinned to
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RepoRecord {
+    pub name: String,
+    pub repo: String,
+    /// (chased commit, pinned commit)
+    pub pins: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResultRecord {
+    pub pos: usize,
+    pub oid: String,
+    pub result: ResultKind,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResultKind {
+    Ok,
+    Err(String),
+    /// Raw wait status of the process
+    ExitFail(i32),
+}
+
+impl From<&CmdResult<()>> for ResultKind {
+    fn from(value: &CmdResult<()>) -> Self {
+        match value {
+            CmdResult::Ok(_) => Self::Ok,
+            CmdResult::Err(e) => Self::Err(e.clone()),
+            CmdResult::ExitFail(e) => Self::ExitFail(e.into_raw()),
+        }
+    }
+}
+
+impl From<&ResultKind> for CmdResult<()> {
+    fn from(value: &ResultKind) -> Self {
+        match value {
+            ResultKind::Ok => Self::Ok(()),
+            ResultKind::Err(e) => Self::Err(e.clone()),
+            ResultKind::ExitFail(raw) => Self::ExitFail(ExitStatus::from_raw(*raw)),
+        }
+    }
+}
+
+impl ChaseRecord {
+    pub fn new(fs: &GitFs, chase: &Chase) -> anyhow::Result<Self> {
+        let mut repos = Vec::with_capacity(chase.repos.len());
+        for sec in &chase.repos {
+            let sec_repo = fs.get_repo(sec.repo_ino)?;
+            repos.push(RepoRecord {
+                name: sec.name.clone(),
+                repo: sec_repo.repo_dir.clone(),
+                pins: sec
+                    .pins
+                    .iter()
+                    .map(|(oid, pinned)| (oid.to_string(), pinned.to_string()))
+                    .collect(),
+            });
+        }
+        Ok(Self {
+            commits: chase.commits.iter().map(|oid| oid.to_string()).collect(),
+            matched: chase
+                .matched
+                .iter()
+                .map(|(oid, inputs)| (oid.to_string(), inputs.clone()))
+                .collect(),
+            commands: chase.commands.iter().cloned().collect(),
+            steps: chase.steps.clone(),
+            run_mode: chase.run_mode.clone(),
+            stop_mode: chase.stop_mode.clone(),
+            patches: chase.patches.clone(),
+            repos,
+            args: chase.args.clone(),
+            hooks: chase.hooks.clone(),
+            repro: chase.repro.clone(),
+            live: chase.live.as_ref().map(|l| l.state.clone()),
+            completed: Vec::new(),
+            results: Vec::new(),
+            tests: Vec::new(),
+            artifacts: Vec::new(),
+        })
+    }
+
+    pub fn load(dir: &Path) -> anyhow::Result<Self> {
+        let path = dir.join(RECORD_FILE);
+        let data = std::fs::read(&path)
+            .with_context(|| format!("Could not read chase record {}", path.display()))?;
+        let record = serde_json::from_slice(&data)
+            .with_context(|| format!("Chase record {} is corrupted", path.display()))?;
+        Ok(record)
+    }
+
+    /// Written to a temp file first, so a crash never leaves a half written record
+    pub fn save(&self, dir: &Path) -> anyhow::Result<()> {
+        let tmp_path = dir.join(format!("{RECORD_FILE}.tmp"));
+        let data = serde_json::to_vec_pretty(self)?;
+        std::fs::write(&tmp_path, data)?;
+        std::fs::rename(&tmp_path, dir.join(RECORD_FILE))?;
+        if let Some(script_path) = dir.parent() {
+            RESULTS_CACHE
+                .lock()
+                .retain(|(script, _), _| script != script_path);
+        }
+        Ok(())
+    }
+
+    pub fn commit_oids(&self) -> anyhow::Result<Vec<Oid>> {
+        parse_oids(&self.commits)
+    }
+
+    pub fn completed_oids(&self) -> anyhow::Result<Vec<Oid>> {
+        parse_oids(&self.completed)
+    }
+
+    /// The commit whose failure ended the run, when the chase stops at the first failure
+    pub fn first_failure(&self) -> Option<&str> {
+        if self.stop_mode != ChaseStopMode::FirstFailure {
+            return None;
+        }
+        self.results
+            .iter()
+            .find(|r| !matches!(r.result, ResultKind::Ok) && self.completed.contains(&r.oid))
+            .map(|r| r.oid.as_str())
+    }
+
+    pub fn matched_oids(&self) -> anyhow::Result<HashMap<Oid, Vec<String>>> {
+        let mut matched = HashMap::with_capacity(self.matched.len());
+        for (oid, inputs) in &self.matched {
+            matched.insert(Oid::from_str(oid)?, inputs.clone());
+        }
+        Ok(matched)
+    }
+}
+
+impl RepoRecord {
+    pub fn pin_oids(&self) -> anyhow::Result<HashMap<Oid, Oid>> {
+        let mut pins = HashMap::with_capacity(self.pins.len());
+        for (oid, pinned) in &self.pins {
+            pins.insert(Oid::from_str(oid)?, Oid::from_str(pinned)?);
+        }
+        Ok(pins)
+    }
+}
+
+fn parse_oids(oids: &[String]) -> anyhow::Result<Vec<Oid>> {
+    oids.iter()
+        .map(|oid| Oid::from_str(oid).with_context(|| format!("Bad commit {oid} in chase record")))
+        .collect()
+}
+
+/// Finds the folder of a previous run. "latest" will return the most recent one.
+pub fn find_run_dir(script_path: &Path, run: &str) -> anyhow::Result<PathBuf> {
+    if run != "latest" {
+        let run_dir = script_path.join(run);
+        if !run_dir.join(RECORD_FILE).exists() {
+            bail!("Run {run:?} not found in {}", script_path.display());
+        }
+        return Ok(run_dir);
+    }
+    // Run folders are named by their UTC timestamp, so they sort by date
+    let mut latest: Option<PathBuf> = None;
+    for entry in std::fs::read_dir(script_path)? {
+        let path = entry?.path();
+        if !path.join(RECORD_FILE).exists() {
+            continue;
+        }
+        if latest.as_ref().is_none_or(|l| path > *l) {
+            latest = Some(path);
+        }
+    }
+    latest.ok_or_else(|| anyhow::anyhow!("No runs found in {}", script_path.display()))
+}
+
+/// The latest result of a script for a single commit, shown in the .chase folder of the Snap folder
+#[derive(Debug, Clone)]
+pub struct CommitResult {
+    pub script: String,
+    pub run_dir: PathBuf,
+    pub completed: bool,
+    pub results: Vec<ResultRecord>,
+    pub log: Option<PathBuf>,
+}
+
+impl CommitResult {
+    pub fn passed(&self) -> bool {
+        self.completed
+            && self
+                .results
+                .iter()
+                .all(|r| matches!(r.result, ResultKind::Ok))
+    }
+
+    /// First line is pass/fail/incomplete, so the history reads at a glance
+    pub fn summary(&self) -> String {
+        let status = if !self.completed {
+            "incomplete"
+        } else if self.passed() {
+            "pass"
+        } else {
+            "fail"
+        };
+        let run = self
+            .run_dir
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let mut out = format!("{status}\nscript: {}\nrun: {run}\n", self.script);
+        for res in &self.results {
+            let result: CmdResult<()> = (&res.result).into();
+            out.push_str(&format!("pos.{}-{}-{}\n", res.pos, res.oid, result));
+        }
+        out
+    }
+}
+
+/// For each script in the chase folder, finds the most recent run that reached the commit
+pub fn latest_commit_results(chase_dir: &Path, commit: Oid) -> anyhow::Result<Vec<CommitResult>> {
+    let commit = commit.to_string();
+    let mut out = Vec::new();
+    for script in std::fs::read_dir(chase_dir)? {
+        let script_path = script?.path();
+        if !script_path.is_dir() {
+            continue;
+        }
+        let mut cache = RESULTS_CACHE.lock();
+        let key = (script_path, commit.clone());
+        let result = match cache.get(&key) {
+            // The run folder was deleted, an older run might have reached the commit
+            Some(Some(res)) if !res.run_dir.join(RECORD_FILE).exists() => None,
+            Some(res) => Some(res.clone()),
+            None => None,
+        };
+        let result = match result {
+            Some(res) => res,
+            None => {
+                let res = script_commit_result(&key.0, &commit)?;
+                cache.insert(key, res.clone());
+                res
+            }
+        };
+        out.extend(result);
+    }
+    Ok(out)
+}
+
+/// Reads the runs of a script, newest first, until one reached the commit
+fn script_commit_result(script_path: &Path, commit: &str) -> anyhow::Result<Option<CommitResult>> {
+    let mut runs = std::fs::read_dir(script_path)?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.join(RECORD_FILE).exists())
+        .collect::<Vec<PathBuf>>();
+    // Run folders are named by their UTC timestamp, newest first
+    runs.sort_unstable_by(|a, b| b.cmp(a));
+    for run_dir in runs {
+        let Ok(record) = ChaseRecord::load(&run_dir) else {
+            continue;
+        };
+        let results = record
+            .results
+            .iter()
+            .filter(|r| r.oid == commit)
+            .cloned()
+            .collect::<Vec<ResultRecord>>();
+        let completed = record.completed.iter().any(|c| c == commit);
+        if !completed && results.is_empty() {
+            continue;
+        }
+        return Ok(Some(CommitResult {
+            script: script_path
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            log: find_log(&run_dir, commit).ok(),
+            run_dir,
+            completed,
+            results,
+        }));
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+impl ChaseRecord {
+    /// A record of the commits, with a single result for each completed commit
+    pub fn with_results(commits: &[Oid], results: &[(Oid, ResultKind)]) -> Self {
+        Self {
+            commits: commits.iter().map(|oid| oid.to_string()).collect(),
+            matched: Vec::new(),
+            commands: Vec::new(),
+            steps: ChaseSteps::default(),
+            run_mode: ChaseRunMode::default(),
+            stop_mode: ChaseStopMode::default(),
+            patches: Vec::new(),
+            repos: Vec::new(),
+            args: ChaseArgs::watch(),
+            hooks: ChaseHooks::default(),
+            repro: None,
+            live: None,
+            completed: results.iter().map(|(oid, _)| oid.to_string()).collect(),
+            results: results
+                .iter()
+                .enumerate()
+                .map(|(i, (oid, result))| ResultRecord {
+                    pos: i + 1,
+                    oid: oid.to_string(),
+                    result: result.clone(),
+                })
+                .collect(),
+            tests: Vec::new(),
+            artifacts: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_record_round_trip() -> anyhow::Result<()> {
+        let oid = |n: u8| Oid::from_bytes(&[n; 20]).unwrap();
+        let mut record =
+            ChaseRecord::with_results(&[oid(1), oid(2), oid(3)], &[(oid(1), ResultKind::Ok)]);
+        record.matched = vec![(oid(1).to_string(), vec!["Branch main".to_string()])];
+        record.stop_mode = ChaseStopMode::FirstFailure;
+        let dir = tempfile::tempdir()?;
+        record.save(dir.path())?;
+        let loaded = ChaseRecord::load(dir.path())?;
+        assert_eq!(loaded.commit_oids()?, record.commit_oids()?);
+        assert_eq!(loaded.completed_oids()?.len(), 1);
+        assert_eq!(loaded.matched_oids()?.len(), 1);
+        assert_eq!(loaded.first_failure(), None);
+
+        record.completed.push(oid(2).to_string());
+        record.results.push(ResultRecord {
+            pos: 2,
+            oid: oid(2).to_string(),
+            result: ResultKind::ExitFail(256),
+        });
+        record.save(dir.path())?;
+        let loaded = ChaseRecord::load(dir.path())?;
+        assert_eq!(loaded.first_failure(), Some(oid(2).to_string().as_str()));
+        assert!(matches!(
+            CmdResult::<()>::from(&loaded.results[1].result),
+            CmdResult::ExitFail(e) if e.code() == Some(1)
+        ));
+
+        record.stop_mode = ChaseStopMode::Continuous;
+        assert_eq!(record.first_failure(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_latest_commit_results_cache() -> anyhow::Result<()> {
+        let chase_dir = tempfile::tempdir()?;
+        let script_path = chase_dir.path().join("build.lua");
+        let commit = Oid::from_bytes(&[7; 20])?;
+        let mut record =
+            ChaseRecord::with_results(&[commit], &[(commit, ResultKind::ExitFail(256))]);
+        let first_run = script_path.join("2025-01-01_00-00-00");
+        std::fs::create_dir_all(&first_run)?;
+        record.save(&first_run)?;
+        let results = latest_commit_results(chase_dir.path(), commit)?;
+        assert_eq!(results.len(), 1);
+        assert!(
+            results[0]
+                .summary()
+                .starts_with("fail\nscript: build.lua\n")
+        );
+        assert!(latest_commit_results(chase_dir.path(), Oid::zero())?.is_empty());
+
+        // Saving a newer run replaces the cached result
+        let second_run = script_path.join("2025-01-02_00-00-00");
+        std::fs::create_dir_all(&second_run)?;
+        record.results[0].result = ResultKind::Ok;
+        record.save(&second_run)?;
+        let results = latest_commit_results(chase_dir.path(), commit)?;
+        assert_eq!(results[0].run_dir, second_run);
+        assert!(results[0].passed());
+
+        // A deleted run falls back to the older one
+        std::fs::remove_dir_all(&second_run)?;
+        let results = latest_commit_results(chase_dir.path(), commit)?;
+        assert_eq!(results[0].run_dir, first_run);
+        std::fs::remove_dir_all(&first_run)?;
+        assert!(latest_commit_results(chase_dir.path(), commit)?.is_empty());
+        Ok(())
+    }
+}
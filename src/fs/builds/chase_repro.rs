@@ -0,0 +1,197 @@
+use std::{
+    collections::BTreeMap,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::fs::builds::chase_record::{ChaseRecord, RECORD_FILE};
+
+/// blake3 hash of every artifact file, by its path relative to the Snap folder
+pub type ArtifactHashes = BTreeMap<String, String>;
+
+/// How a commit is checked to build to the same artifacts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReproMode {
+    /// The build files are removed and the commit is built a second time
+    Twice,
+    /// The artifacts are compared with the latest previous run of the script that built the commit
+    Previous,
+}
+
+impl ReproMode {
+    pub fn from_name(mode: &str) -> Option<Self> {
+        match mode.to_lowercase().as_str() {
+            "twice" => Some(Self::Twice),
+            "previous" => Some(Self::Previous),
+            _ => None,
+        }
+    }
+}
+
+/// Set with cfg.set_repro
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReproCheck {
+    pub mode: ReproMode,
+    /// Files or folders, relative to the Snap folder
+    pub artifacts: Vec<PathBuf>,
+}
+
+impl ReproCheck {
+    /// Hashes the artifacts found in `root`. Folders are walked, symlinks are hashed by their target.
+    pub fn hash_artifacts(&self, root: &Path) -> anyhow::Result<ArtifactHashes> {
+        let mut hashes = ArtifactHashes::new();
+        for artifact in &self.artifacts {
+            let path = root.join(artifact);
+            if std::fs::symlink_metadata(&path).is_err() {
+                bail!("Artifact {} not found", artifact.display());
+            }
+            let mut stack = vec![path];
+            while let Some(path) = stack.pop() {
+                let metadata = std::fs::symlink_metadata(&path)?;
+                let rel = path
+                    .strip_prefix(root)
+                    .unwrap_or(&path)
+                    .to_string_lossy()
+                    .into_owned();
+                if metadata.is_dir() {
+                    for entry in std::fs::read_dir(&path)? {
+                        stack.push(entry?.path());
+                    }
+                } else if metadata.is_symlink() {
+                    let target = std::fs::read_link(&path)?;
+                    let hash = blake3::hash(target.as_os_str().as_encoded_bytes());
+                    hashes.insert(rel, hash.to_hex().to_string());
+                } else {
+                    hashes.insert(rel, hash_file(&path)?.to_hex().to_string());
+                }
+            }
+        }
+        Ok(hashes)
+    }
+}
+
+fn hash_file(path: &Path) -> anyhow::Result<blake3::Hash> {
+    let mut file = std::fs::File::open(path)
+        .with_context(|| format!("Could not read artifact {}", path.display()))?;
+    let mut hasher = blake3::Hasher::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher.finalize())
+}
+
+/// The artifact files that differ between two builds of the same commit
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ArtifactDiff {
+    pub changed: Vec<String>,
+    pub only_first: Vec<String>,
+    pub only_second: Vec<String>,
+}
+
+impl ArtifactDiff {
+    pub fn new(first: &ArtifactHashes, second: &ArtifactHashes) -> Self {
+        let mut diff = Self::default();
+        for (path, hash) in first {
+            match second.get(path) {
+                Some(other) if other != hash => diff.changed.push(path.clone()),
+                Some(_) => {}
+                None => diff.only_first.push(path.clone()),
+            }
+        }
+        diff.only_second = second
+            .keys()
+            .filter(|path| !first.contains_key(*path))
+            .cloned()
+            .collect();
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changed.is_empty() && self.only_first.is_empty() && self.only_second.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.changed.len() + self.only_first.len() + self.only_second.len()
+    }
+
+    /// One line per differing file
+    pub fn lines(&self) -> Vec<String> {
+        let changed = self.changed.iter().map(|p| format!("  differs: {p}"));
+        let first = self.only_first.iter().map(|p| format!("  missing: {p}"));
+        let second = self.only_second.iter().map(|p| format!("    added: {p}"));
+        changed.chain(first).chain(second).collect()
+    }
+}
+
+/// The artifacts of the commit, from the latest run of the script before `run_dir`. Returns the name of that run.
+pub fn previous_hashes(run_dir: &Path, oid: &str) -> Option<(String, ArtifactHashes)> {
+    let script_path = run_dir.parent()?;
+    let mut runs = std::fs::read_dir(script_path)
+        .ok()?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p != run_dir && p.join(RECORD_FILE).exists())
+        .collect::<Vec<PathBuf>>();
+    // Run folders are named by their UTC timestamp, newest first
+    runs.sort_unstable_by(|a, b| b.cmp(a));
+    runs.into_iter().find_map(|dir| {
+        let record = ChaseRecord::load(&dir).ok()?;
+        let (_, hashes) = record.artifacts.into_iter().find(|(o, _)| o == oid)?;
+        let run = dir.file_name()?.to_string_lossy().into_owned();
+        Some((run, hashes))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_artifact_hashes_and_diff() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("target/release/deps"))?;
+        std::fs::write(root.join("target/release/app"), b"binary")?;
+        std::fs::write(root.join("target/release/deps/lib.rlib"), b"lib")?;
+        std::fs::write(root.join("README"), b"not an artifact")?;
+
+        let check = ReproCheck {
+            mode: ReproMode::Twice,
+            artifacts: vec![PathBuf::from("target/release")],
+        };
+        let first = check.hash_artifacts(root)?;
+        assert_eq!(
+            first.keys().collect::<Vec<_>>(),
+            ["target/release/app", "target/release/deps/lib.rlib"]
+        );
+        assert_eq!(
+            first["target/release/app"],
+            blake3::hash(b"binary").to_hex().to_string()
+        );
+        assert!(ArtifactDiff::new(&first, &check.hash_artifacts(root)?).is_empty());
+
+        std::fs::write(root.join("target/release/app"), b"binary built at 12:00")?;
+        std::fs::remove_file(root.join("target/release/deps/lib.rlib"))?;
+        std::fs::write(root.join("target/release/app.d"), b"deps")?;
+        let diff = ArtifactDiff::new(&first, &check.hash_artifacts(root)?);
+        assert_eq!(diff.changed, ["target/release/app"]);
+        assert_eq!(diff.only_first, ["target/release/deps/lib.rlib"]);
+        assert_eq!(diff.only_second, ["target/release/app.d"]);
+        assert_eq!(diff.len(), 3);
+
+        let missing = ReproCheck {
+            mode: ReproMode::Previous,
+            artifacts: vec![PathBuf::from("dist")],
+        };
+        assert!(missing.hash_artifacts(root).is_err());
+        Ok(())
+    }
+}
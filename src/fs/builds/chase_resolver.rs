@@ -1,5 +1,5 @@
 use std::{
-    collections::{BTreeSet, HashMap, VecDeque},
+    collections::{BTreeSet, HashMap, HashSet, VecDeque},
     ffi::{OsStr, OsString},
     path::PathBuf,
 };
@@ -13,7 +13,7 @@ use crate::fs::{
         chase::Chase,
         inject::InjectedMetadata,
         reporter::{ChaseFsError, ChaseGitError, GuseFsResult, GuseGitResult},
-        runtime::InputTypes,
+        runtime::{ChaseOrder, InputTypes, LuaConfig, PrFilter, RepoInput, RepoPin, parse_date},
     },
     fileattr::FileType,
     repo::RefKind,
@@ -23,6 +23,7 @@ pub fn validate_commits(
     fs: &GitFs,
     repo_ino: u64,
     commits: &[(InputTypes, String)],
+    first_parent: bool,
 ) -> GuseGitResult<VecDeque<Oid>> {
     let Ok(repo) = fs.get_repo(repo_ino) else {
         return Err(ChaseGitError::FsError {
@@ -60,105 +61,142 @@ pub fn validate_commits(
                         Ok(())
                     })?;
                 }
-                &InputTypes::Pr => {
+                &InputTypes::Pr | &InputTypes::PrMerge => {
+                    let kind = match itype {
+                        InputTypes::Pr => RefKind::Pr(commit.to_string()),
+                        _ => RefKind::PrMerge(commit.to_string()),
+                    };
                     repo.with_ref_state(|s| -> GuseGitResult<()> {
-                        if let Some(commits) = s.refs_to_snaps.get(&RefKind::Pr(commit.to_string()))
-                        {
-                            for (_, oid) in commits {
-                                c_oids.push_back(*oid)
+                        match s.refs_to_snaps.get(&kind) {
+                            Some(commits) if !commits.is_empty() => {
+                                for (_, oid) in commits {
+                                    c_oids.push_back(*oid)
+                                }
+                            }
+                            _ => {
+                                return Err(ChaseGitError::BranchNotFound {
+                                    branch_type: itype.as_str().to_string(),
+                                    branch_name: commit.to_string(),
+                                });
                             }
-                        } else {
-                            return Err(ChaseGitError::BranchNotFound {
-                                branch_type: "Pr".to_string(),
-                                branch_name: commit.to_string(),
-                            });
                         }
                         Ok(())
                     })?;
                 }
+                &InputTypes::Tag => {
+                    let tag = format!("refs/tags/{commit}");
+                    c_oids.push_back(peel_commit(r, &tag).map_err(|e| match e {
+                        ChaseGitError::CommitNotFound { .. } => ChaseGitError::BranchNotFound {
+                            branch_type: "Tag".to_string(),
+                            branch_name: commit.to_string(),
+                        },
+                        e => e,
+                    })?);
+                }
+                &InputTypes::Rev => c_oids.push_back(peel_commit(r, commit)?),
                 &InputTypes::Range => {
-                    if !commit.contains("..") {
-                        return Err(ChaseGitError::BadCommitRange {
-                            input: commit.to_string(),
-                        });
-                    }
-                    let mut oids = commit.split("..");
-                    let Some(start) = oids.next() else {
-                        return Err(ChaseGitError::BadCommitRange {
-                            input: commit.to_string(),
-                        });
+                    // a...b is the symmetric difference, as in git
+                    let (start, end, symmetric) = match commit.split_once("...") {
+                        Some((start, end)) => (start, end, true),
+                        None => match commit.split_once("..") {
+                            Some((start, end)) => (start, end, false),
+                            None => {
+                                return Err(ChaseGitError::BadCommitRange {
+                                    input: commit.to_string(),
+                                });
+                            }
+                        },
                     };
-                    let Some(end) = oids.next() else {
+                    if start.is_empty() || end.is_empty() {
                         return Err(ChaseGitError::BadCommitRange {
                             input: commit.to_string(),
                         });
-                    };
-                    let start_commit = {
-                        let start_obj = r
-                            .revparse_single(start)
-                            .map_err(|e| map_git_error(start, e))?;
-                        let oid = start_obj
-                            .peel_to_commit()
-                            .map_err(|e| map_git_error(start, e))?
-                            .id();
-                        r.find_commit(oid)
-                            .map_err(|e| map_git_error(start, e))?
-                            .id()
-                    };
-                    let end_commit = {
-                        let start_obj =
-                            r.revparse_single(end).map_err(|e| map_git_error(end, e))?;
-                        let oid = start_obj
-                            .peel_to_commit()
-                            .map_err(|e| map_git_error(end, e))?
-                            .id();
-                        r.find_commit(oid).map_err(|e| map_git_error(end, e))?.id()
-                    };
-                    let common_ref = repo.with_ref_state(|s| -> GuseGitResult<RefKind> {
-                        let Some(start_refs) = s.snaps_to_ref.get(&start_commit) else {
-                            return Err(ChaseGitError::CommitNotFound {
-                                commit: start_commit.to_string(),
-                            });
-                        };
-                        let Some(end_refs) = s.snaps_to_ref.get(&end_commit) else {
-                            return Err(ChaseGitError::CommitNotFound {
-                                commit: end_commit.to_string(),
-                            });
-                        };
-                        let Some(common_ref_kind) = start_refs.intersection(end_refs).next() else {
-                            return Err(ChaseGitError::NoCommonRef {
+                    }
+                    let mut start_commit = peel_commit(r, start)?;
+                    let mut end_commit = peel_commit(r, end)?;
+                    let mut walk = r.revwalk().map_err(map_walk_error)?;
+                    walk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::REVERSE)
+                        .map_err(map_walk_error)?;
+                    if first_parent {
+                        walk.simplify_first_parent().map_err(map_walk_error)?;
+                    }
+                    if symmetric {
+                        // The commits of both sides since their merge base, without the base
+                        let base = r.merge_base(start_commit, end_commit).map_err(|_| {
+                            ChaseGitError::NoCommonRef {
                                 oid1: start_commit.to_string(),
                                 oid2: end_commit.to_string(),
-                            });
-                        };
-                        Ok(common_ref_kind.clone())
-                    })?;
-                    let range = repo.with_ref_state(|s| -> GuseGitResult<Vec<Oid>> {
-                        let Some(refs) = s.refs_to_snaps.get(&common_ref) else {
-                            // Should be uncreachable.
+                            }
+                        })?;
+                        walk.push(start_commit).map_err(map_walk_error)?;
+                        walk.push(end_commit).map_err(map_walk_error)?;
+                        walk.hide(base).map_err(map_walk_error)?;
+                    } else {
+                        // Accept the range in both directions
+                        if r.graph_descendant_of(start_commit, end_commit)
+                            .map_err(map_walk_error)?
+                        {
+                            std::mem::swap(&mut start_commit, &mut end_commit);
+                        }
+                        if start_commit != end_commit
+                            && !r
+                                .graph_descendant_of(end_commit, start_commit)
+                                .map_err(map_walk_error)?
+                        {
                             return Err(ChaseGitError::NoCommonRef {
                                 oid1: start_commit.to_string(),
                                 oid2: end_commit.to_string(),
                             });
-                        };
-                        let Some(pos1) = refs.iter().position(|(_, oid)| *oid == start_commit)
-                        else {
-                            return Err(ChaseGitError::CommitNotFound {
-                                commit: start_commit.to_string(),
-                            });
-                        };
-                        let Some(pos2) = refs.iter().position(|(_, oid)| *oid == end_commit) else {
-                            return Err(ChaseGitError::CommitNotFound {
-                                commit: start_commit.to_string(),
-                            });
-                        };
-                        let range = refs[pos1.min(pos2)..=pos1.max(pos2)]
-                            .iter()
-                            .map(|(_, oid)| *oid)
-                            .collect::<Vec<Oid>>();
-                        Ok(range)
-                    })?;
-                    c_oids.extend(range);
+                        }
+                        // Both ends are included
+                        c_oids.push_back(start_commit);
+                        walk.push(end_commit).map_err(map_walk_error)?;
+                        walk.hide(start_commit).map_err(map_walk_error)?;
+                    }
+                    for oid in walk {
+                        c_oids.push_back(oid.map_err(map_walk_error)?);
+                    }
+                }
+                InputTypes::Window {
+                    since,
+                    until,
+                    branch,
+                } => {
+                    let since = since.as_deref().and_then(parse_date).unwrap_or(i64::MIN);
+                    let until = until.as_deref().and_then(parse_date).unwrap_or(i64::MAX);
+                    let mut walk = r.revwalk().map_err(map_walk_error)?;
+                    walk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::REVERSE)
+                        .map_err(map_walk_error)?;
+                    if first_parent {
+                        walk.simplify_first_parent().map_err(map_walk_error)?;
+                    }
+                    match branch {
+                        Some(branch) => {
+                            let tip = peel_commit(r, branch).map_err(|e| match e {
+                                ChaseGitError::CommitNotFound { .. } => {
+                                    ChaseGitError::BranchNotFound {
+                                        branch_type: "Branch".to_string(),
+                                        branch_name: branch.to_string(),
+                                    }
+                                }
+                                e => e,
+                            })?;
+                            walk.push(tip).map_err(map_walk_error)?;
+                        }
+                        None => walk.push_head().map_err(map_walk_error)?,
+                    }
+                    for oid in walk {
+                        let oid = oid.map_err(map_walk_error)?;
+                        let time = r
+                            .find_commit(oid)
+                            .map_err(|e| map_git_error(&oid.to_string(), e))?
+                            .time()
+                            .seconds();
+                        // Since is inclusive, until is exclusive
+                        if time >= since && time < until {
+                            c_oids.push_back(oid);
+                        }
+                    }
                 }
                 _ => unreachable!(),
             }
@@ -168,6 +206,240 @@ pub fn validate_commits(
     Ok(c_oids)
 }
 
+/// Chased commit -> the inputs of the script that matched it
+pub type MatchedInputs = HashMap<Oid, Vec<String>>;
+
+/// Resolves the inputs of a script, then deduplicates and orders the commits.
+///
+/// Also returns the inputs that matched each commit, for reporting.
+pub fn resolve_chase_commits(
+    fs: &GitFs,
+    repo_ino: u64,
+    cfg: &LuaConfig,
+) -> GuseGitResult<(VecDeque<Oid>, MatchedInputs)> {
+    let mut commits: Vec<Oid> = Vec::new();
+    let mut matched: MatchedInputs = HashMap::new();
+    let mut seen: HashSet<Oid> = HashSet::new();
+    let inputs = expand_all_prs(fs, repo_ino, &cfg.commits, &cfg.pr_filter)?;
+    for input in &inputs {
+        let label = format!("{} {}", input.0.as_str(), input.1);
+        for oid in validate_commits(fs, repo_ino, std::slice::from_ref(input), cfg.first_parent)? {
+            let labels = matched.entry(oid).or_default();
+            if !labels.contains(&label) {
+                labels.push(label.clone());
+            }
+            // The chase state is kept by oid, so each commit is only chased once
+            if seen.insert(oid) {
+                commits.push(oid);
+            }
+        }
+    }
+
+    match cfg.order {
+        ChaseOrder::AsGiven => {}
+        ChaseOrder::Reverse => commits.reverse(),
+        ChaseOrder::Date | ChaseOrder::Topo => {
+            let Ok(repo) = fs.get_repo(repo_ino) else {
+                return Err(ChaseGitError::FsError {
+                    msg: "Repo not found. Try restarting the session".to_string(),
+                });
+            };
+            let rank = repo.with_repo(|r| commit_ranks(r, &seen, &cfg.order))?;
+            // Stable, so duplicates stay next to each other
+            commits.sort_by_key(|oid| rank.get(oid).copied().unwrap_or(i64::MAX));
+        }
+    }
+    if let Some(limit) = &cfg.limit {
+        limit.apply(&mut commits);
+        matched.retain(|oid, _| commits.contains(oid));
+    }
+    Ok((commits.into(), matched))
+}
+
+/// Replaces `{Pr = "all"}` and `{PrMerge = "all"}` with one input per open PR, so the commits are reported by PR.
+///
+/// A PR is open while its merge ref exists. It is ordered by number.
+fn expand_all_prs(
+    fs: &GitFs,
+    repo_ino: u64,
+    inputs: &[(InputTypes, String)],
+    filter: &PrFilter,
+) -> GuseGitResult<Vec<(InputTypes, String)>> {
+    let Ok(repo) = fs.get_repo(repo_ino) else {
+        return Err(ChaseGitError::FsError {
+            msg: "Repo not found. Try restarting the session".to_string(),
+        });
+    };
+    let mut expanded = Vec::with_capacity(inputs.len());
+    for (itype, value) in inputs {
+        let merge = match itype {
+            InputTypes::Pr if value.eq_ignore_ascii_case("all") => false,
+            InputTypes::PrMerge if value.eq_ignore_ascii_case("all") => true,
+            _ => {
+                expanded.push((itype.clone(), value.clone()));
+                continue;
+            }
+        };
+        let mut prs = repo.with_ref_state(|s| {
+            s.refs_to_snaps
+                .iter()
+                .filter_map(|(kind, commits)| match kind {
+                    RefKind::PrMerge(n) if !commits.is_empty() => Some(n.clone()),
+                    _ => None,
+                })
+                .filter_map(|n| {
+                    // The latest commit of the PR, or the merge commit if the PR head is not tracked
+                    let changed = s
+                        .refs_to_snaps
+                        .get(&RefKind::Pr(n.clone()))
+                        .filter(|c| !c.is_empty())
+                        .or_else(|| s.refs_to_snaps.get(&RefKind::PrMerge(n.clone())))?
+                        .iter()
+                        .map(|(time, _)| *time)
+                        .max()?;
+                    filter.matches(&n, changed).then_some(n)
+                })
+                .collect::<Vec<String>>()
+        });
+        if prs.is_empty() {
+            return Err(ChaseGitError::NoOpenPrs);
+        }
+        prs.sort_unstable_by_key(|n| (n.parse::<u64>().unwrap_or(u64::MAX), n.clone()));
+        let itype = if merge {
+            InputTypes::PrMerge
+        } else {
+            InputTypes::Pr
+        };
+        expanded.extend(prs.into_iter().map(|n| (itype.clone(), n)));
+    }
+    Ok(expanded)
+}
+
+/// The sort key of each commit: the commit time, or the position in a topological walk (parents first)
+fn commit_ranks(
+    r: &git2::Repository,
+    commits: &HashSet<Oid>,
+    order: &ChaseOrder,
+) -> GuseGitResult<HashMap<Oid, i64>> {
+    let mut rank = HashMap::with_capacity(commits.len());
+    if *order == ChaseOrder::Date {
+        for oid in commits {
+            let commit = r
+                .find_commit(*oid)
+                .map_err(|e| map_git_error(&oid.to_string(), e))?;
+            rank.insert(*oid, commit.time().seconds());
+        }
+        return Ok(rank);
+    }
+    let mut walk = r.revwalk().map_err(map_walk_error)?;
+    walk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::REVERSE)
+        .map_err(map_walk_error)?;
+    for oid in commits {
+        walk.push(*oid).map_err(map_walk_error)?;
+    }
+    for (pos, oid) in walk.enumerate() {
+        let oid = oid.map_err(map_walk_error)?;
+        if commits.contains(&oid) {
+            rank.insert(oid, pos as i64);
+            if rank.len() == commits.len() {
+                break;
+            }
+        }
+    }
+    Ok(rank)
+}
+
+/// Finds the inode of a repo by its name
+pub fn find_repo_ino(fs: &GitFs, repo_name: &str) -> GuseGitResult<u64> {
+    let Some(repo_entry) = fs.repos_map.get(repo_name) else {
+        return Err(ChaseGitError::RepoNotFound {
+            repo: repo_name.to_string(),
+        });
+    };
+    Ok(GitFs::repo_id_to_ino(*repo_entry.value()))
+}
+
+/// Finds the commit of a secondary repo to be used with each of the chased commits
+///
+/// Returns the inode of the secondary repo and a map of chased commit -> pinned commit
+pub fn pin_secondary_commits(
+    fs: &GitFs,
+    repo_ino: u64,
+    commits: &VecDeque<Oid>,
+    input: &RepoInput,
+) -> GuseGitResult<(u64, HashMap<Oid, Oid>)> {
+    let sec_ino = find_repo_ino(fs, &input.repo)?;
+
+    let mut pins = HashMap::new();
+    match &input.pin {
+        RepoPin::Commit(commit) => {
+            let pinned =
+                validate_commits(fs, sec_ino, &[(InputTypes::Commit, commit.clone())], false)?;
+            let Some(&pinned) = pinned.front() else {
+                return Err(ChaseGitError::CommitNotFound {
+                    commit: commit.clone(),
+                });
+            };
+            for &oid in commits {
+                pins.insert(oid, pinned);
+            }
+        }
+        RepoPin::Before(branch) => {
+            let Ok(repo) = fs.get_repo(repo_ino) else {
+                return Err(ChaseGitError::FsError {
+                    msg: "Repo not found. Try restarting the session".to_string(),
+                });
+            };
+            let Ok(sec_repo) = fs.get_repo(sec_ino) else {
+                return Err(ChaseGitError::FsError {
+                    msg: "Repo not found. Try restarting the session".to_string(),
+                });
+            };
+            for &oid in commits {
+                let c_time = repo.with_repo(|r| -> GuseGitResult<i64> {
+                    let commit = r
+                        .find_commit(oid)
+                        .map_err(|e| map_git_error(&oid.to_string(), e))?;
+                    Ok(commit.time().seconds())
+                })?;
+                let pinned = sec_repo
+                    .with_ref_state(|s| pin_before(&s.refs_to_snaps, branch.as_deref(), c_time));
+                let Some(pinned) = pinned else {
+                    return Err(ChaseGitError::NoPinnedCommit {
+                        repo: input.repo.clone(),
+                        commit: oid.to_string(),
+                    });
+                };
+                pins.insert(oid, pinned);
+            }
+        }
+    }
+    Ok((sec_ino, pins))
+}
+
+/// The latest snap made at or before `c_time` on `branch`.
+///
+/// Without a branch, the snaps of main are used, or those of HEAD when there is no main.
+fn pin_before(
+    refs_to_snaps: &HashMap<RefKind, Vec<(i64, Oid)>>,
+    branch: Option<&str>,
+    c_time: i64,
+) -> Option<Oid> {
+    let has_main = refs_to_snaps.keys().any(|k| matches!(k, RefKind::Main(_)));
+    refs_to_snaps
+        .iter()
+        .filter(|(kind, _)| match (kind, branch) {
+            (RefKind::Main(n) | RefKind::Branch(n), Some(branch)) => n == branch,
+            (RefKind::Main(_), None) => true,
+            (RefKind::Head(_), None) => !has_main,
+            _ => false,
+        })
+        .flat_map(|(_, snaps)| snaps)
+        .filter(|(secs, _)| *secs <= c_time)
+        .max_by_key(|(secs, _)| *secs)
+        .map(|(_, oid)| *oid)
+}
+
 pub fn validate_commit_refs(
     fs: &GitFs,
     repo_ino: u64,
@@ -188,6 +460,12 @@ pub fn validate_commit_refs(
         }
         Ok(())
     })?;
+    // The refs are read on the first listing of the repo. Commits missing from them get a detached
+    // Snap folder, so read the refs first or every commit would be detached.
+    if repo.with_ref_state(|s| s.refs_to_snaps.is_empty()) {
+        repo.refresh_refs()
+            .map_err(|_| ChaseGitError::NoRefKindsFound)?;
+    }
     let kinds = repo.with_ref_state(|s| -> GuseGitResult<Vec<(Oid, BTreeSet<RefKind>, i64)>> {
         let mut kinds = vec![];
         for (c, time) in c_oids {
@@ -206,9 +484,6 @@ pub fn validate_commit_refs(
         }
         Ok(kinds)
     })?;
-    if kinds.is_empty() {
-        return Err(ChaseGitError::NoRefKindsFound);
-    }
     Ok(kinds)
 }
 
@@ -269,6 +544,69 @@ pub fn resolve_path_for_refs(
     Ok(out)
 }
 
+/// Creates a Snap folder on demand for the commits not found in any ref
+///
+/// The folders are listed in the Commits folder, inside the repo root.
+/// Returns the commits that received one.
+pub fn resolve_detached_snaps(
+    fs: &GitFs,
+    repo_ino: u64,
+    commits: &[&Oid],
+    paths: &mut HashMap<Oid, (PathBuf, u64)>,
+) -> GuseFsResult<Vec<Oid>> {
+    let detached = commits
+        .iter()
+        .filter(|oid| !paths.contains_key(oid))
+        .map(|oid| **oid)
+        .collect::<Vec<Oid>>();
+    if detached.is_empty() {
+        return Ok(detached);
+    }
+    let Ok(repo) = fs.get_repo(repo_ino) else {
+        return Err(ChaseFsError::FsError {
+            msg: "Repo not found. Try restarting the session".to_string(),
+        });
+    };
+    for &oid in &detached {
+        let c_time = repo
+            .with_repo(|r| r.find_commit(oid).map(|c| c.time().seconds()))
+            .map_err(|e| ChaseFsError::FsError {
+                msg: format!("Commit {oid} not found: {}", e.message()),
+            })?;
+        repo.add_detached_snap(oid, c_time);
+    }
+
+    // Listing the repo root creates the Commits folder
+    let Ok(root_entries) = fs.readdir(repo_ino) else {
+        return Err(ChaseFsError::NoneFound {
+            target: OsString::from(fs::COMMITS_FOLDER),
+        });
+    };
+    let Some(commits_root) = root_entries
+        .into_iter()
+        .find(|e| e.name == fs::COMMITS_FOLDER)
+    else {
+        return Err(ChaseFsError::NoneFound {
+            target: OsString::from(fs::COMMITS_FOLDER),
+        });
+    };
+    let Ok(entries) = fs.readdir(commits_root.ino) else {
+        return Err(ChaseFsError::SnapNotFound {
+            msg: "No entries found inside the Commits folder".to_string(),
+        });
+    };
+    let root = fs.mount_point.join(&repo.repo_dir).join(fs::COMMITS_FOLDER);
+    for &oid in &detached {
+        let Some(snap_folder) = entries.iter().find(|e| e.oid == oid) else {
+            return Err(ChaseFsError::SnapNotFound {
+                msg: format!("Commit {oid} not found in the Commits folder"),
+            });
+        };
+        paths.insert(oid, (root.join(&snap_folder.name), snap_folder.ino));
+    }
+    Ok(detached)
+}
+
 fn find_path_in_main(
     fs: &GitFs,
     repo_ino: u64,
@@ -455,34 +793,61 @@ pub fn cleanup_builds(fs: &GitFs, repo_ino: u64, chase: &Chase) -> anyhow::Resul
     let repo = fs.get_repo(repo_ino)?;
     for oid in chase.commits.iter() {
         let guard = repo.inostate.read();
-        let exists = guard.build_sessions.contains_key(oid);
+        let session = guard.build_sessions.get(oid).cloned();
         drop(guard);
-        if exists {
-            let Some(&(_, parent)) = chase.commit_paths.get(oid) else {
-                continue;
-            };
-            let Ok(entries) = fs.readdir(parent) else {
-                continue;
-            };
-            for e in entries {
-                if !fs.is_in_build(e.ino.into())? {
-                    continue;
-                };
-                match e.kind {
-                    FileType::Directory => {
-                        remove_dir_all(fs, e.ino)?;
-                    }
-                    _ => {
-                        fs.unlink(parent, &e.name)?;
-                    }
-                }
-            }
+        let Some(session) = session else {
+            continue;
         };
+        // Pinned sessions are kept until released by the user
+        if session.pinned.load(std::sync::atomic::Ordering::Relaxed) {
+            continue;
+        }
+        let Some(&(_, parent)) = chase.commit_paths.get(oid) else {
+            continue;
+        };
+        remove_build_files(fs, parent)?;
+    }
+    Ok(())
+}
+
+/// Removes all the files and folders created inside a Snap folder
+pub fn remove_build_files(fs: &GitFs, snap_ino: u64) -> anyhow::Result<()> {
+    let Ok(entries) = fs.readdir(snap_ino) else {
+        return Ok(());
+    };
+    for e in entries {
+        if !fs.is_in_build(e.ino.into())? {
+            continue;
+        };
+        match e.kind {
+            FileType::Directory => {
+                remove_dir_all(fs, e.ino)?;
+            }
+            _ => {
+                fs.unlink(snap_ino, &e.name)?;
+            }
+        }
     }
     Ok(())
 }
 
 /// Used to convert git2 errors to ChaseGitError used by the Reported in a GUSE chase
+fn map_walk_error(e: git2::Error) -> ChaseGitError {
+    ChaseGitError::GitError {
+        message: format!("Error walking the commits: {}", e.message()),
+        source: e,
+    }
+}
+
+/// Resolves a revspec (hash, branch, tag, main~10...) to a commit
+fn peel_commit(r: &git2::Repository, spec: &str) -> GuseGitResult<Oid> {
+    let obj = r
+        .revparse_single(spec)
+        .map_err(|e| map_git_error(spec, e))?;
+    let commit = obj.peel_to_commit().map_err(|e| map_git_error(spec, e))?;
+    Ok(commit.id())
+}
+
 fn map_git_error(commit: &str, e: git2::Error) -> ChaseGitError {
     match e.code() {
         git2::ErrorCode::Ambiguous => ChaseGitError::GitAmbiguousCommit {
@@ -526,3 +891,205 @@ fn remove_dir_all(fs: &GitFs, parent: u64) -> anyhow::Result<()> {
     fs.rmdir(par_parent, &par_name)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_setup::{GitFsTestSetup, get_fs, local_repo_with_commits, run_git_fs_test};
+
+    fn oid(n: u8) -> Oid {
+        Oid::from_bytes(&[n; 20]).unwrap()
+    }
+
+    #[test]
+    fn test_pin_before() {
+        let mut refs = HashMap::new();
+        refs.insert(
+            RefKind::Main("main".into()),
+            vec![(10, oid(1)), (30, oid(3))],
+        );
+        refs.insert(RefKind::Branch("release".into()), vec![(20, oid(2))]);
+        refs.insert(RefKind::Head("HEAD".into()), vec![(25, oid(4))]);
+
+        assert_eq!(pin_before(&refs, None, 29), Some(oid(1)));
+        assert_eq!(pin_before(&refs, None, 30), Some(oid(3)));
+        assert_eq!(pin_before(&refs, None, 9), None);
+        assert_eq!(pin_before(&refs, Some("release"), 29), Some(oid(2)));
+        assert_eq!(pin_before(&refs, Some("main"), 15), Some(oid(1)));
+        assert_eq!(pin_before(&refs, Some("nope"), 40), None);
+
+        refs.remove(&RefKind::Main("main".into()));
+        assert_eq!(pin_before(&refs, None, 40), Some(oid(4)));
+    }
+
+    #[test]
+    fn test_resolve_chase_commits_dedup() -> anyhow::Result<()> {
+        run_git_fs_test(
+            GitFsTestSetup {
+                key: "test_resolve_chase_commits_dedup",
+                read_only: false,
+            },
+            |_| -> anyhow::Result<()> {
+                let fs = get_fs();
+                let (repo_ino, snaps) = local_repo_with_commits("local", &["a", "b", "c"])?;
+                let (a, b, c) = (snaps[0].0, snaps[1].0, snaps[2].0);
+                let cfg = LuaConfig {
+                    commits: vec![
+                        (InputTypes::Commit, b.to_string()),
+                        (InputTypes::Range, format!("{a}..{c}")),
+                        (InputTypes::Rev, "HEAD".to_string()),
+                    ],
+                    ..Default::default()
+                };
+                let (commits, matched) = resolve_chase_commits(&fs, repo_ino, &cfg)
+                    .map_err(|_| anyhow::anyhow!("Could not resolve the commits"))?;
+                assert_eq!(commits, [b, a, c]);
+                assert_eq!(matched[&a].len(), 1);
+                assert_eq!(matched[&b].len(), 2);
+                assert_eq!(matched[&c].len(), 2);
+                Ok(())
+            },
+        )
+    }
+
+    #[test]
+    fn test_validate_commits_revwalk() -> anyhow::Result<()> {
+        run_git_fs_test(
+            GitFsTestSetup {
+                key: "test_validate_commits_revwalk",
+                read_only: false,
+            },
+            |_| -> anyhow::Result<()> {
+                let fs = get_fs();
+                let (repo_ino, snaps) = local_repo_with_commits("local", &["a", "b", "c"])?;
+                let (a, b, c) = (snaps[0].0, snaps[1].0, snaps[2].0);
+                // a - b - c - m
+                //  \         /
+                //   x ------
+                let (x, m) = fs.get_repo(repo_ino)?.with_repo(|r| -> anyhow::Result<_> {
+                    let sig = git2::Signature::now("guse", "guse@localhost")?;
+                    let a = r.find_commit(a)?;
+                    let c = r.find_commit(c)?;
+                    let x = r.commit(None, &sig, &sig, "x", &a.tree()?, &[&a])?;
+                    let x = r.find_commit(x)?;
+                    let m = r.commit(None, &sig, &sig, "m", &c.tree()?, &[&c, &x])?;
+                    Ok((x.id(), m))
+                })?;
+                let resolve = |input: InputTypes, spec: String, first_parent: bool| {
+                    validate_commits(&fs, repo_ino, &[(input, spec)], first_parent)
+                        .ok()
+                        .map(Vec::from)
+                };
+                let range = |spec: String, first_parent: bool| {
+                    resolve(InputTypes::Range, spec, first_parent)
+                };
+
+                assert_eq!(range(format!("{a}..{c}"), false), Some(vec![a, b, c]));
+                assert_eq!(range(format!("{c}..{a}"), false), Some(vec![a, b, c]));
+                assert_eq!(range(format!("{b}..{b}"), false), Some(vec![b]));
+                assert!(range(format!("{b}..{x}"), false).is_none());
+                assert!(range(format!("{a}.."), false).is_none());
+                assert!(range(a.to_string(), false).is_none());
+
+                let mut merged = range(format!("{a}..{m}"), false).unwrap();
+                assert_eq!((merged[0], merged[4]), (a, m));
+                merged.sort();
+                let mut all = vec![a, b, c, x, m];
+                all.sort();
+                assert_eq!(merged, all);
+                assert_eq!(range(format!("{a}..{m}"), true), Some(vec![a, b, c, m]));
+
+                // The symmetric difference leaves out the merge base
+                let mut sides = range(format!("{c}...{x}"), false).unwrap();
+                sides.sort();
+                let mut expected = vec![b, c, x];
+                expected.sort();
+                assert_eq!(sides, expected);
+                assert_eq!(range(format!("{a}...{c}"), false), Some(vec![b, c]));
+
+                let window = |since: Option<&str>, until: Option<&str>, branch: Option<String>| {
+                    let input = InputTypes::Window {
+                        since: since.map(str::to_string),
+                        until: until.map(str::to_string),
+                        branch,
+                    };
+                    resolve(input, String::new(), true)
+                };
+                assert_eq!(
+                    window(None, None, Some(m.to_string())),
+                    Some(vec![a, b, c, m])
+                );
+                assert_eq!(window(None, None, None), Some(vec![a, b, c]));
+                assert_eq!(
+                    window(Some("2000-01-01"), Some("2001-01-01"), None),
+                    Some(vec![])
+                );
+                assert!(window(None, None, Some("nope".to_string())).is_none());
+                Ok(())
+            },
+        )
+    }
+
+    #[test]
+    fn test_resolve_detached_snaps() -> anyhow::Result<()> {
+        run_git_fs_test(
+            GitFsTestSetup {
+                key: "test_resolve_detached_snaps",
+                read_only: false,
+            },
+            |_| -> anyhow::Result<()> {
+                let fs = get_fs();
+                let (repo_ino, snaps) = local_repo_with_commits("local", &["a", "b"])?;
+                let b = snaps[1].0;
+                // Only reachable by its oid
+                let x = fs
+                    .get_repo(repo_ino)?
+                    .with_repo(|r| -> anyhow::Result<Oid> {
+                        let sig = git2::Signature::now("guse", "guse@localhost")?;
+                        let b = r.find_commit(b)?;
+                        Ok(r.commit(None, &sig, &sig, "x", &b.tree()?, &[&b])?)
+                    })?;
+
+                let oids = [&b, &x];
+                let refs = validate_commit_refs(&fs, repo_ino, &oids)
+                    .map_err(|_| anyhow::anyhow!("Could not read the refs"))?;
+                // The refs are read on demand, so b is found on a ref
+                assert_eq!(refs.len(), 1);
+                assert_eq!(refs[0].0, b);
+
+                // The month folders are looked up in the listing of the repo root
+                fs.readdir(repo_ino)?;
+                let mut paths = resolve_path_for_refs(&fs, repo_ino, refs)
+                    .map_err(|_| anyhow::anyhow!("Could not find the Snap folders"))?;
+                let detached = resolve_detached_snaps(&fs, repo_ino, &oids, &mut paths)
+                    .map_err(|_| anyhow::anyhow!("Could not create the Snap folders"))?;
+                assert_eq!(detached, [x]);
+                let (path, snap_ino) = &paths[&x];
+                let name = format!("Snap_{x:.10}");
+                assert!(path.ends_with(std::path::Path::new(fs::COMMITS_FOLDER).join(&name)));
+
+                let commits_root = fs
+                    .lookup(repo_ino, OsStr::new(fs::COMMITS_FOLDER))?
+                    .expect("Commits folder");
+                let snap = fs
+                    .lookup(commits_root.ino, OsStr::new(&name))?
+                    .expect("Snap");
+                assert_eq!(snap.ino, *snap_ino);
+                let mut files = fs
+                    .readdir(*snap_ino)?
+                    .into_iter()
+                    .map(|e| e.name)
+                    .collect::<Vec<_>>();
+                files.sort();
+                assert!(files.contains(&OsString::from("a")));
+                assert!(files.contains(&OsString::from("b")));
+
+                // Created once
+                let detached = resolve_detached_snaps(&fs, repo_ino, &oids, &mut paths)
+                    .map_err(|_| anyhow::anyhow!("Could not create the Snap folders"))?;
+                assert!(detached.is_empty());
+                Ok(())
+            },
+        )
+    }
+}
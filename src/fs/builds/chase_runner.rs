@@ -1,18 +1,32 @@
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::os::fd::AsRawFd;
 use std::path::Path;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 
 use git2::Oid;
 
 use crate::fs::builds::chase_handle::{ChaseHandle, ChaseState};
-use crate::fs::builds::logger::CmdResult;
-use crate::fs::builds::runtime::ChaseRunMode;
+use crate::fs::builds::chase_hook::{ChaseOutcome, ChaseReport, HOOK_TIMEOUT, HOOKS_LOG, HookRun};
+use crate::fs::builds::chase_live::{is_live, live_oid};
+use crate::fs::builds::chase_log::ChaseLog;
+use crate::fs::builds::chase_record::{ChaseRecord, ResultRecord};
+use crate::fs::builds::chase_repro::{ArtifactDiff, ArtifactHashes, ReproMode, previous_hashes};
+use crate::fs::builds::chase_resolver::remove_build_files;
+use crate::fs::builds::chase_tests::{TestMatrix, TestStatus};
+use crate::fs::builds::logger::{CmdResult, JobMode, KeptJob};
+use crate::fs::builds::runtime::{ChaseCommand, ChaseRunMode, ChaseStopMode};
 use crate::fs::{
     self,
     builds::reporter::{Reporter, Updater, color_red},
 };
-use crate::fs::{GitFs, builds::chase::Chase};
+use crate::fs::{
+    GitFs,
+    builds::chase::{Chase, ChaseArgs, KeepMode},
+    fileattr::FileType,
+};
 
 // Holds the inode of the target Snap folder
 struct ChaseTarget(u64);
@@ -23,6 +37,58 @@ impl ChaseTarget {
     }
 }
 
+/// How the build files of a commit are carried to the next one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CarryMode {
+    Move,
+    Clone,
+}
+
+impl CarryMode {
+    /// Files are cloned when the previous session can outlive the chase:
+    /// with --clone or --keep, or when an earlier chase pinned it.
+    fn new(fs: &GitFs, args: &ChaseArgs, prev_oid: Oid, prev: &ChaseTarget) -> Self {
+        let pinned = fs
+            .get_repo(prev.0)
+            .is_ok_and(|repo| repo.is_build_session_pinned(prev_oid));
+        if args.clone || args.keep != KeepMode::None || pinned {
+            Self::Clone
+        } else {
+            Self::Move
+        }
+    }
+
+    fn carry(self, fs: &GitFs, old: &ChaseTarget, new: &ChaseTarget) -> anyhow::Result<()> {
+        match self {
+            Self::Move => move_chase_target(fs, old, new),
+            Self::Clone => clone_chase_target(fs, old, new),
+        }
+    }
+
+    const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Move => "move",
+            Self::Clone => "clone",
+        }
+    }
+}
+
+/// Setup and teardown steps run once per commit, or once for the whole chase
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StepScope {
+    Commit,
+    Chase,
+}
+
+impl StepScope {
+    const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Commit => "commit",
+            Self::Chase => "chase",
+        }
+    }
+}
+
 /// Used to sumarize the success/failure of each commit after a chase
 #[derive(Debug, Clone)]
 pub struct ChaseResult<T> {
@@ -48,9 +114,22 @@ pub struct ChaseRunner<'a, R: Updater> {
     fs: &'a GitFs,
     pub reporter: &'a mut R,
     pub chase: Chase,
-    pub curr_log_file: Option<std::fs::File>,
+    pub curr_log: Option<ChaseLog>,
+    // Env variables holding the Snap paths of the secondary repos for the current commit
+    pub curr_envs: Vec<(String, PathBuf)>,
     results: Vec<ChaseResult<()>>,
+    // The results of single tests, for each commit
+    tests: Vec<(String, Vec<(String, TestStatus)>)>,
+    // The hashes of the artifacts, for each commit, when the script checks reproducibility
+    artifacts: Vec<(String, ArtifactHashes)>,
     pub handle: Arc<ChaseHandle>,
+    // Saved in the run folder after every commit, used to resume the chase
+    record: Option<ChaseRecord>,
+    // Commits completed in a previous run of the same chase
+    completed: HashSet<Oid>,
+    // Processes left running by the setup steps, terminated after the teardown
+    commit_jobs: Vec<KeptJob>,
+    chase_jobs: Vec<KeptJob>,
 }
 
 impl<'a, R: Updater> ChaseRunner<'a, R> {
@@ -61,29 +140,91 @@ impl<'a, R: Updater> ChaseRunner<'a, R> {
         mut chase: Chase,
         handle: Arc<ChaseHandle>,
     ) -> Self {
-        // Folder where the logs will be saved
-        if chase.args.log && std::fs::create_dir(dir).is_err() {
-            // If the folder can't be created, don't try to log
-            let _ = reporter.update(&color_red("COULD NOT CREATE LOGGING DIRECTORY.\n"));
+        // Folder where the chase record and the logs will be saved
+        if let Err(e) = std::fs::create_dir_all(dir) {
+            // If the folder can't be created, the chase can't be resumed or logged
+            let _ = reporter.update(&color_red(&format!(
+                "Could not create the run folder, the chase will not be resumable: {e}\n"
+            )));
             chase.args.log = false;
         }
+        let record = match ChaseRecord::new(fs, &chase) {
+            Ok(record) if dir.is_dir() => Some(record),
+            Ok(_) => None,
+            Err(e) => {
+                let _ = reporter.update(&color_red(&format!(
+                    "Could not create the chase record, the chase will not be resumable: {e}\n"
+                )));
+                None
+            }
+        };
         Self {
             dir_path: dir.to_path_buf(),
             fs,
             reporter,
             chase,
-            curr_log_file: None,
+            curr_log: None,
+            curr_envs: Vec::new(),
             results: Vec::new(),
+            tests: Vec::new(),
+            artifacts: Vec::new(),
             handle,
+            record,
+            completed: HashSet::new(),
+            commit_jobs: Vec::new(),
+            chase_jobs: Vec::new(),
         }
     }
 
+    /// Loads the results of a previous run. The commits it completed will be skipped.
+    pub fn resume_from(mut self, record: ChaseRecord) -> anyhow::Result<Self> {
+        self.completed = record.completed_oids()?.into_iter().collect();
+        for res in &record.results {
+            let oid = Oid::from_str(&res.oid)?;
+            self.results
+                .push(ChaseResult::new(res.pos, oid, (&res.result).into()));
+        }
+        self.tests = record.tests.clone();
+        self.artifacts = record.artifacts.clone();
+        if self.record.is_some() {
+            self.record = Some(record);
+        }
+        Ok(self)
+    }
+
     pub fn run(&mut self) -> anyhow::Result<()> {
-        let mut prev_target: Option<ChaseTarget> = None;
+        // The chase steps run in the script folder
+        let script_path = self
+            .dir_path
+            .parent()
+            .unwrap_or(&self.dir_path)
+            .to_path_buf();
+        let setup = self.run_setup(StepScope::Chase, &script_path);
+        let res = match setup {
+            Ok(CmdResult::Ok(_)) => self.run_commits(),
+            Ok(_) => {
+                let _ = self.report(&color_red(
+                    "The chase setup failed, no commits were chased.\n",
+                ));
+                Err(anyhow::anyhow!("The chase setup failed"))
+            }
+            Err(e) => Err(e),
+        };
+        self.run_teardown(StepScope::Chase, &script_path);
+        self.run_hooks(res.is_err());
+        res
+    }
+
+    fn run_commits(&mut self) -> anyhow::Result<()> {
+        let mut prev_target: Option<(Oid, ChaseTarget)> = None;
         let mut curr_run: usize = 0;
-        let total = self.chase.commits.len();
 
         let mut commit_list = self.chase.commits.clone();
+        if self.chase.live.is_some() {
+            commit_list.push_back(live_oid());
+        }
+        let total = commit_list.len();
+        self.save_record();
 
         // RUN THROUGH EACH COMMIT
         while let Some(oid) = commit_list.pop_front() {
@@ -96,71 +237,567 @@ impl<'a, R: Updater> ChaseRunner<'a, R> {
             }
             curr_run += 1;
 
+            if self.completed.contains(&oid) {
+                self.reporter.update(&format!(
+                    "==> Skipping commit {} ({}/{}), completed in a previous run\n",
+                    oid, curr_run, total
+                ))?;
+                continue;
+            }
+
             self.update_curr_log_file(curr_run, oid);
 
-            self.report(&format!(
-                "==> Starting chase for commit {} ({}/{})\n",
-                oid, curr_run, total
-            ))?;
+            if let Some(live) = self.chase.live.as_ref().filter(|_| is_live(&oid)) {
+                let msg = format!(
+                    "==> Starting chase for the live folder, {} ({}/{})\n",
+                    live.state.describe(),
+                    curr_run,
+                    total
+                );
+                self.report(&msg)?;
+            } else {
+                self.report(&format!(
+                    "==> Starting chase for commit {} ({}/{})\n",
+                    oid, curr_run, total
+                ))?;
+            }
+            if let Some(inputs) = self.chase.matched.get(&oid).cloned() {
+                self.report(&format!("    matched by: {}\n", inputs.join(", ")))?;
+            }
 
-            let Some((cur_path, cur_ino)) = self.chase.commit_paths.get(&oid).cloned() else {
+            let Some((cur_path, cur_ino)) = self.target_path(oid) else {
                 continue;
             };
 
-            // MOVE build contents from previous commit
-            let cur_target: ChaseTarget = ChaseTarget::new(cur_ino);
-            if let Some(ref prev_target) = prev_target
-                && !self.chase.args.no_move
-                && let Err(e) = move_chase_target(self.fs, prev_target, &cur_target)
-            {
+            if let Err(e) = self.update_secondary_repos(oid) {
                 self.report(&format!(
-                    "Could not move target contents due to error:\n{e}"
+                    "Could not resolve the secondary repos due to error:\n{e}\n"
                 ))?;
+                continue;
             }
 
-            let mut commands = self.chase.commands.clone();
+            // MOVE (or CLONE) build contents from previous commit
+            let cur_target: ChaseTarget = ChaseTarget::new(cur_ino);
+            // Build files are never carried in or out of the live folder
+            if let Some((prev_oid, ref prev_target)) = prev_target
+                && !self.chase.args.no_move
+                && !is_live(&oid)
+            {
+                let mode = CarryMode::new(self.fs, &self.chase.args, prev_oid, prev_target);
+                if let Err(e) = mode.carry(self.fs, prev_target, &cur_target) {
+                    self.report(&format!(
+                        "Could not {} target contents due to error:\n{e}",
+                        mode.as_str()
+                    ))?;
+                }
+            }
 
-            // RUN COMMANDS
-            while let Some(command) = commands.pop_front() {
-                if self
-                    .handle
-                    .stop_flag
-                    .load(std::sync::atomic::Ordering::Relaxed)
+            let finished = match self.run_setup(StepScope::Commit, &cur_path) {
+                Ok(CmdResult::Ok(_)) => self.run_commands(oid, &cur_path, curr_run, total),
+                Ok(failed) => {
+                    // The commands are skipped, the commit is done
+                    self.results.push(ChaseResult::new(curr_run, oid, failed));
+                    Ok(true)
+                }
+                Err(e) => Err(e),
+            };
+            // Always ran, even when the commands failed or the chase was stopped
+            self.run_teardown(StepScope::Commit, &cur_path);
+            let finished = match finished {
+                Ok(true)
+                    if self.chase.repro.is_some() && !is_live(&oid) && !self.commit_failed(oid) =>
                 {
-                    break;
+                    self.check_repro(oid, &cur_path, cur_ino, curr_run, total)
                 }
+                other => other,
+            };
+            if !is_live(&oid) {
+                prev_target = Some((oid, cur_target));
+            }
+            self.finish_curr_log();
+
+            let stopped = self
+                .handle
+                .stop_flag
+                .load(std::sync::atomic::Ordering::Relaxed);
+            // The failure that stops the chase is the result of the commit, a resume starts after it
+            let first_failure =
+                self.chase.stop_mode == ChaseStopMode::FirstFailure && self.commit_failed(oid);
+            if (finished? && !stopped) || first_failure {
+                self.mark_completed(oid);
+            }
+        }
+
+        self.print_chase_results();
+        Ok(())
+    }
+
+    /// Runs the commands of a commit. False if the chase was stopped before all of them ran.
+    fn run_commands(
+        &mut self,
+        oid: Oid,
+        cur_path: &Path,
+        curr_run: usize,
+        total: usize,
+    ) -> anyhow::Result<bool> {
+        let mut commands = self.chase.commands.clone();
+        while let Some(command) = commands.pop_front() {
+            if self
+                .handle
+                .stop_flag
+                .load(std::sync::atomic::Ordering::Relaxed)
+            {
+                return Ok(false);
+            }
+            self.report(&format!(
+                "==> Running command {:?} for {} ({}/{})\n",
+                command.as_str(),
+                oid,
+                curr_run,
+                total
+            ))?;
+            let dir = match &command.cwd {
+                Some(cwd) => cur_path.join(cwd),
+                None => cur_path.to_path_buf(),
+            };
+            let existing = command
+                .parser
+                .as_ref()
+                .map(|p| p.existing_files(&dir))
+                .unwrap_or_default();
+            let output = self.run_command_on_snap(cur_path, &command);
+            if let Some(parser) = &command.parser {
+                match parser.parse(&output.lines, &dir, &existing) {
+                    Ok(tests) => self.add_tests(oid, tests),
+                    Err(e) => self.report(&color_red(&format!(
+                        "Could not read the test results: {e:#}\n"
+                    )))?,
+                }
+            }
+            if let Ok(cmd_res) = output.result.egress(self) {
+                self.results.push(ChaseResult::new(curr_run, oid, cmd_res));
+            }
+            self.report(&format!(
+                "--> FINISHED command {} for {}\n",
+                command.as_str(),
+                oid
+            ))?;
+        }
+        Ok(true)
+    }
+
+    /// Hashes the artifacts of a commit that built, and compares them with a second build or a previous run.
+    ///
+    /// A mismatch is added to the results as a failure. False if the chase was stopped during the second build.
+    fn check_repro(
+        &mut self,
+        oid: Oid,
+        cur_path: &Path,
+        cur_ino: u64,
+        curr_run: usize,
+        total: usize,
+    ) -> anyhow::Result<bool> {
+        let Some(repro) = self.chase.repro.clone() else {
+            return Ok(true);
+        };
+        self.report(&format!("==> Hashing the artifacts of {oid}\n"))?;
+        let first = match repro.hash_artifacts(cur_path) {
+            Ok(hashes) => hashes,
+            Err(e) => {
+                self.repro_failed(curr_run, oid, format!("{e:#}"))?;
+                return Ok(true);
+            }
+        };
+        self.artifacts.push((oid.to_string(), first.clone()));
+
+        let (against, second) = match repro.mode {
+            ReproMode::Twice => {
                 self.report(&format!(
-                    "==> Running command {:?} for {} ({}/{})\n",
-                    command, oid, curr_run, total
+                    "==> Building {oid} again from a clean Snap folder ({curr_run}/{total})\n"
                 ))?;
-                if let Ok(cmd_res) = self.run_command_on_snap(&cur_path, &command).egress(self) {
-                    self.results.push(ChaseResult::new(curr_run, oid, cmd_res));
+                if let Err(e) = remove_build_files(self.fs, cur_ino) {
+                    self.repro_failed(
+                        curr_run,
+                        oid,
+                        format!("could not remove the build files: {e:#}"),
+                    )?;
+                    return Ok(true);
+                }
+                // The second build only adds its failures, its tests were read from the first one
+                let results_len = self.results.len();
+                let tests = self.tests.clone();
+                let finished = match self.run_setup(StepScope::Commit, cur_path) {
+                    Ok(CmdResult::Ok(_)) => self.run_commands(oid, cur_path, curr_run, total),
+                    Ok(failed) => {
+                        self.results.push(ChaseResult::new(curr_run, oid, failed));
+                        Ok(true)
+                    }
+                    Err(e) => Err(e),
+                };
+                self.run_teardown(StepScope::Commit, cur_path);
+                self.tests = tests;
+                let failures = self
+                    .results
+                    .drain(results_len..)
+                    .filter(|r| r.result.is_err())
+                    .collect::<Vec<_>>();
+                self.results.extend(failures);
+                if !finished? {
+                    return Ok(false);
+                }
+                // A failed second build is already in the results
+                if self.commit_failed(oid) {
+                    return Ok(true);
+                }
+                (
+                    "the second build".to_string(),
+                    repro.hash_artifacts(cur_path),
+                )
+            }
+            ReproMode::Previous => match previous_hashes(&self.dir_path, &oid.to_string()) {
+                Some((run, hashes)) => (format!("run {run}"), Ok(hashes)),
+                None => {
+                    self.report(&format!(
+                        "    no previous run built {oid}, its artifacts are saved for the next one\n"
+                    ))?;
+                    return Ok(true);
                 }
-                self.report(&format!("--> FINISHED command {} for {}\n", command, oid))?;
+            },
+        };
+        let second = match second {
+            Ok(hashes) => hashes,
+            Err(e) => {
+                self.repro_failed(curr_run, oid, format!("{e:#}"))?;
+                return Ok(true);
             }
-            prev_target = Some(cur_target);
-            self.curr_log_file = None;
+        };
+
+        let diff = ArtifactDiff::new(&first, &second);
+        if diff.is_empty() {
+            self.report(&format!(
+                "--> {} artifact files of {oid} match {against}\n",
+                first.len()
+            ))?;
+            return Ok(true);
+        }
+        self.report(&color_red(&format!(
+            "{oid} is not reproducible, {} files differ from {against}:\n",
+            diff.len()
+        )))?;
+        for line in diff.lines() {
+            self.report(&format!("{line}\n"))?;
         }
+        self.results.push(ChaseResult::new(
+            curr_run,
+            oid,
+            CmdResult::Err(format!(
+                "Not reproducible, {} files differ from {against}\n",
+                diff.len()
+            )),
+        ));
+        Ok(true)
+    }
 
-        self.print_chase_results();
+    fn repro_failed(&mut self, curr_run: usize, oid: Oid, error: String) -> anyhow::Result<()> {
+        self.report(&color_red(&format!(
+            "Reproducibility check of {oid} failed: {error}\n"
+        )))?;
+        self.results.push(ChaseResult::new(
+            curr_run,
+            oid,
+            CmdResult::Err(format!("Reproducibility check failed: {error}\n")),
+        ));
         Ok(())
     }
 
+    /// The Snap folder of a commit, or the folder the live folder is chased in
+    fn target_path(&self, oid: Oid) -> Option<(PathBuf, u64)> {
+        match &self.chase.live {
+            Some(live) if is_live(&oid) => Some((live.path.clone(), 0)),
+            _ => self.chase.commit_paths.get(&oid).cloned(),
+        }
+    }
+
+    fn commit_failed(&self, oid: Oid) -> bool {
+        self.results
+            .iter()
+            .any(|r| r.oid == oid && r.result.is_err())
+    }
+
+    fn add_tests(&mut self, oid: Oid, tests: Vec<(String, TestStatus)>) {
+        let oid = oid.to_string();
+        match self.tests.iter_mut().find(|(o, _)| *o == oid) {
+            Some((_, commit_tests)) => commit_tests.extend(tests),
+            None => self.tests.push((oid, tests)),
+        }
+    }
+
+    /// Runs the setup steps in order and returns the result of the first one that failed
+    fn run_setup(&mut self, scope: StepScope, path: &Path) -> anyhow::Result<CmdResult<()>> {
+        let steps = match scope {
+            StepScope::Commit => self.chase.steps.setup.clone(),
+            StepScope::Chase => self.chase.steps.chase_setup.clone(),
+        };
+        for step in steps {
+            if self
+                .handle
+                .stop_flag
+                .load(std::sync::atomic::Ordering::Relaxed)
+            {
+                break;
+            }
+            self.report(&format!(
+                "==> Running {} setup {:?}\n",
+                scope.as_str(),
+                step.as_str()
+            ))?;
+            let output = self.run_job(path, &step, JobMode::Setup);
+            if let Some(job) = output.kept {
+                self.report(&format!(
+                    "    processes started by {:?} are kept until the {} teardown\n",
+                    step.as_str(),
+                    scope.as_str()
+                ))?;
+                match scope {
+                    StepScope::Commit => self.commit_jobs.push(job),
+                    StepScope::Chase => self.chase_jobs.push(job),
+                }
+            }
+            let res = output.result.egress(self)?;
+            if res.is_err() {
+                return Ok(res);
+            }
+        }
+        Ok(CmdResult::Ok(()))
+    }
+
+    /// Runs every teardown step, even if one fails, then terminates what the setup steps left running
+    fn run_teardown(&mut self, scope: StepScope, path: &Path) {
+        let steps = match scope {
+            StepScope::Commit => self.chase.steps.teardown.clone(),
+            StepScope::Chase => self.chase.steps.chase_teardown.clone(),
+        };
+        for step in steps {
+            let _ = self.report(&format!(
+                "==> Running {} teardown {:?}\n",
+                scope.as_str(),
+                step.as_str()
+            ));
+            let res = self.run_job(path, &step, JobMode::Teardown).result;
+            if res.is_err() {
+                let _ = self.report(&color_red(&format!(
+                    "Teardown {:?} failed: {res}\n",
+                    step.as_str()
+                )));
+            }
+        }
+        let jobs = match scope {
+            StepScope::Commit => std::mem::take(&mut self.commit_jobs),
+            StepScope::Chase => std::mem::take(&mut self.chase_jobs),
+        };
+        for job in jobs {
+            job.terminate();
+        }
+    }
+
+    /// Adds the results of a commit to the chase record and saves it to disk
+    fn mark_completed(&mut self, oid: Oid) {
+        self.completed.insert(oid);
+        let Some(record) = self.record.as_mut() else {
+            return;
+        };
+        record.completed.push(oid.to_string());
+        record.results.extend(
+            self.results
+                .iter()
+                .filter(|r| r.oid == oid)
+                .map(|r| ResultRecord {
+                    pos: r.pos,
+                    oid: r.oid.to_string(),
+                    result: (&r.result).into(),
+                }),
+        );
+        let oid = oid.to_string();
+        record
+            .tests
+            .extend(self.tests.iter().filter(|(o, _)| *o == oid).cloned());
+        record
+            .artifacts
+            .extend(self.artifacts.iter().filter(|(o, _)| *o == oid).cloned());
+        self.save_record();
+    }
+
+    /// Saves the report of the chase and hands it to the hooks of the daemon and of the script
+    fn run_hooks(&mut self, errored: bool) {
+        let stopped = self
+            .handle
+            .stop_flag
+            .load(std::sync::atomic::Ordering::Relaxed);
+        let failed = self.failed_commits();
+        let outcome = if stopped {
+            ChaseOutcome::Cancelled
+        } else if errored || !failed.is_empty() {
+            ChaseOutcome::Failed
+        } else {
+            ChaseOutcome::Completed
+        };
+        let repo = self
+            .chase
+            .commit_paths
+            .values()
+            .next()
+            .and_then(|(_, ino)| self.fs.get_repo(*ino).ok())
+            .map(|repo| repo.repo_dir.clone())
+            .unwrap_or_default();
+        let script = self
+            .dir_path
+            .parent()
+            .and_then(|p| p.file_name())
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let report = ChaseReport {
+            repo,
+            script,
+            run_dir: self.dir_path.clone(),
+            outcome,
+            total: self.chase.commits.len() + self.chase.live.is_some() as usize,
+            completed: self.completed.len(),
+            failed: failed.iter().map(|oid| oid.to_string()).collect(),
+            live: self.chase.live.as_ref().map(|l| l.state.clone()),
+            results: self
+                .results
+                .iter()
+                .map(|r| ResultRecord {
+                    pos: r.pos,
+                    oid: r.oid.to_string(),
+                    result: (&r.result).into(),
+                })
+                .collect(),
+        };
+        let mut hooks = self.fs.chase_hooks.clone();
+        hooks.merge(&self.chase.hooks);
+        // Without a run folder, the hooks get a copy of the report in a temp folder
+        let tmp_dir = match self.dir_path.is_dir() {
+            true => None,
+            false if hooks.is_empty() => return,
+            false => match tempfile::tempdir() {
+                Ok(dir) => Some(dir),
+                Err(e) => {
+                    let _ = self.reporter.update(&color_red(&format!(
+                        "Could not save the chase report: {e}\n"
+                    )));
+                    return;
+                }
+            },
+        };
+        let report_dir = tmp_dir
+            .as_ref()
+            .map_or(self.dir_path.as_path(), |d| d.path());
+        let report_path = match report.save(report_dir) {
+            Ok(path) => path,
+            Err(e) => {
+                let _ = self.reporter.update(&color_red(&format!(
+                    "Could not save the chase report: {e}\n"
+                )));
+                return;
+            }
+        };
+
+        if hooks.is_empty() {
+            return;
+        }
+        let _ = self.reporter.update("==> Running the chase hooks\n");
+        let log = self.dir_path.join(HOOKS_LOG);
+        let run = HookRun {
+            log: self.chase.args.log.then_some(log.as_path()),
+            grace: Duration::from_secs(self.chase.args.kill_grace),
+            timeout: Duration::from_secs(HOOK_TIMEOUT),
+        };
+        for e in hooks.notify(&report, &report_path, run) {
+            let _ = self.reporter.update(&color_red(&format!("{e}\n")));
+        }
+    }
+
+    fn save_record(&mut self) {
+        if let Some(record) = &self.record
+            && let Err(e) = record.save(&self.dir_path)
+        {
+            let _ = self.reporter.update(&color_red(&format!(
+                "Could not save the chase record: {e}\n"
+            )));
+        }
+    }
+
     fn update_curr_log_file(&mut self, curr_run: usize, oid: Oid) {
+        self.finish_curr_log();
         if self.chase.args.log {
-            let name = format!("{:02}_{oid:.7}", curr_run);
-            if let Ok(file) = std::fs::OpenOptions::new()
-                .write(true)
-                .truncate(true)
-                .create(true)
-                .open(self.dir_path.join(name))
+            let name = if is_live(&oid) {
+                format!("{curr_run:02}_live")
+            } else {
+                format!("{curr_run:02}_{oid:.7}")
+            };
+            if let Ok(chase_log) =
+                ChaseLog::create(&self.dir_path, &name, self.chase.args.log_limit)
             {
-                self.curr_log_file = Some(file)
+                self.curr_log = Some(chase_log)
             };
         }
     }
 
-    fn prepare_commit_list(&self) -> VecDeque<String> {
+    /// Compresses the log of the commit that just finished
+    fn finish_curr_log(&mut self) {
+        if let Some(chase_log) = self.curr_log.take()
+            && let Err(e) = chase_log.finish()
+        {
+            let _ = self
+                .reporter
+                .update(&color_red(&format!("Could not compress the log: {e}\n")));
+        }
+    }
+
+    /// Finds the Snap folders of the secondary repos pinned for this commit
+    fn update_secondary_repos(&mut self, oid: Oid) -> anyhow::Result<()> {
+        self.curr_envs.clear();
+        let mut envs = Vec::with_capacity(self.chase.repos.len());
+        for sec in &self.chase.repos {
+            let sec_repo = self.fs.get_repo(sec.repo_ino)?;
+            let Some(pinned) = sec.pins.get(&oid) else {
+                anyhow::bail!("No commit pinned in repo {}", sec_repo.repo_dir);
+            };
+            let Some((sec_path, _)) = sec.commit_paths.get(pinned) else {
+                anyhow::bail!(
+                    "Snap folder for commit {pinned} not found in repo {}",
+                    sec_repo.repo_dir
+                );
+            };
+            envs.push((
+                sec.env_name(),
+                sec_path.clone(),
+                sec_repo.repo_dir.clone(),
+                *pinned,
+            ));
+        }
+        for (env_name, path, repo_name, pinned) in envs {
+            self.report(&format!(
+                "==> Using {repo_name} at commit {pinned} as {env_name}\n"
+            ))?;
+            self.curr_envs.push((env_name, path));
+        }
+        Ok(())
+    }
+
+    /// Commits with at least one failed command
+    pub fn failed_commits(&self) -> Vec<Oid> {
+        let mut failed: Vec<Oid> = Vec::new();
+        for res in &self.results {
+            if res.result.is_err() && !failed.contains(&res.oid) {
+                failed.push(res.oid);
+            }
+        }
+        failed
+    }
+
+    fn prepare_commit_list(&self) -> VecDeque<ChaseCommand> {
         match self.chase.run_mode {
             ChaseRunMode::Continuous => self.chase.commands.clone(),
             ChaseRunMode::Binary => todo!(),
@@ -172,15 +809,113 @@ impl<'a, R: Updater> ChaseRunner<'a, R> {
             .reporter
             .update("GUSE chase completed. Results for each commit:\n");
         for res in &self.results {
+            let commit = if is_live(&res.oid) {
+                "live".to_string()
+            } else {
+                res.oid.to_string()
+            };
             let _ = self
                 .reporter
-                .update(&format!("pos.{}-{}-{}\n", res.pos, res.oid, res.result));
+                .update(&format!("pos.{}-{}-{}\n", res.pos, commit, res.result));
+        }
+        self.print_pr_results();
+        self.print_test_changes();
+    }
+
+    /// Groups the commits by the PR that matched them, when the script chased PRs
+    fn print_pr_results(&mut self) {
+        let mut groups: BTreeMap<(u64, String), Vec<Oid>> = BTreeMap::new();
+        for oid in &self.chase.commits {
+            let Some(inputs) = self.chase.matched.get(oid) else {
+                continue;
+            };
+            for input in inputs {
+                let Some(number) = input
+                    .strip_prefix("Pr ")
+                    .or_else(|| input.strip_prefix("PrMerge "))
+                else {
+                    continue;
+                };
+                let key = (number.parse().unwrap_or(u64::MAX), input.clone());
+                let commits = groups.entry(key).or_default();
+                if !commits.contains(oid) {
+                    commits.push(*oid);
+                }
+            }
+        }
+        if groups.is_empty() {
+            return;
+        }
+        let failed = self.failed_commits();
+        let _ = self.reporter.update("Results for each pull request:\n");
+        for ((_, input), commits) in groups {
+            let passed = commits
+                .iter()
+                .filter(|oid| self.completed.contains(oid) && !failed.contains(oid))
+                .count();
+            let failed = commits
+                .iter()
+                .filter(|oid| failed.contains(oid))
+                .map(|oid| format!("{oid:.7}"))
+                .collect::<Vec<String>>();
+            let status = if !failed.is_empty() {
+                color_red("FAIL")
+            } else if passed == commits.len() {
+                "PASS".to_string()
+            } else {
+                "INCOMPLETE".to_string()
+            };
+            let mut line = format!("{input}: {status} {passed}/{} passed", commits.len());
+            if !failed.is_empty() {
+                line.push_str(&format!(", failed: {}", failed.join(" ")));
+            }
+            let _ = self.reporter.update(&format!("{line}\n"));
+        }
+    }
+
+    /// Lists the tests that changed state and saves the matrix of all the tests in the run folder
+    fn print_test_changes(&mut self) {
+        let mut commits = self
+            .chase
+            .commits
+            .iter()
+            .map(|oid| oid.to_string())
+            .collect::<Vec<String>>();
+        if self.chase.live.is_some() {
+            commits.push(live_oid().to_string());
+        }
+        let matrix = TestMatrix::new(&commits, &self.tests);
+        if matrix.is_empty() {
+            return;
+        }
+        let changes = matrix.changes();
+        let _ = self.reporter.update(&format!(
+            "{} tests ran, {} changed state:\n",
+            matrix.tests.len(),
+            changes.len()
+        ));
+        for change in changes {
+            let _ = self.reporter.update(&format!(
+                "{} {} -> {} at {}\n",
+                change.test,
+                change.from.as_str(),
+                change.to.as_str(),
+                change.commit
+            ));
+        }
+        if self.chase.args.log
+            && let Err(e) = matrix.save(&self.dir_path)
+        {
+            let _ = self.reporter.update(&color_red(&format!(
+                "Could not save the test matrix: {e}\n"
+            )));
         }
     }
 }
 
 impl<'a, R: Updater> Drop for ChaseRunner<'a, R> {
     fn drop(&mut self) {
+        self.finish_curr_log();
         let mut state = self.handle.state.lock();
         *state = ChaseState::Stopped;
         self.handle.cv.notify_all();
@@ -197,3 +932,165 @@ fn move_chase_target(fs: &GitFs, old: &ChaseTarget, new: &ChaseTarget) -> anyhow
     }
     Ok(())
 }
+
+/// Copies the build contents of the previous target, leaving them in place.
+///
+/// Every file is cloned with clone_file, so each commit keeps its own outputs.
+fn clone_chase_target(fs: &GitFs, old: &ChaseTarget, new: &ChaseTarget) -> anyhow::Result<()> {
+    let mut stack: Vec<(u64, u64)> = vec![(old.0, new.0)];
+    while let Some((src_parent, dst_parent)) = stack.pop() {
+        for e in fs.readdir(src_parent)? {
+            if !fs.is_in_build(e.ino.into())? {
+                continue;
+            };
+            match e.kind {
+                FileType::Directory => {
+                    let dst_dir = match fs.lookup(dst_parent, &e.name)? {
+                        Some(attr) => attr,
+                        None => fs.mkdir(dst_parent, &e.name)?,
+                    };
+                    stack.push((e.ino, dst_dir.ino));
+                }
+                FileType::RegularFile => {
+                    if fs.lookup(dst_parent, &e.name)?.is_some() {
+                        fs.unlink(dst_parent, &e.name)?;
+                    }
+                    let (dst_attr, fh) = fs.create(dst_parent, &e.name, true, true)?;
+                    fs.release(fh)?;
+                    let src_path = build_path(fs, e.ino)?;
+                    let dst_path = build_path(fs, dst_attr.ino)?;
+                    let size = clone_file(&src_path, &dst_path)?;
+                    fs.update_size_in_storage(dst_attr.ino.into(), size)?;
+                }
+                FileType::Symlink => {
+                    if fs.lookup(dst_parent, &e.name)?.is_some() {
+                        fs.unlink(dst_parent, &e.name)?;
+                    }
+                    let target = std::fs::read_link(build_path(fs, e.ino)?)?;
+                    fs.symlink(dst_parent, &e.name, &target)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The real path of an entry inside a build folder
+fn build_path(fs: &GitFs, ino: u64) -> anyhow::Result<PathBuf> {
+    let commit_oid = fs.get_oid_from_db(ino)?;
+    let repo = fs.get_repo(ino)?;
+    let session = repo.get_or_init_build_session(commit_oid, &repo.build_dir)?;
+    session.finish_path(fs, ino.into())
+}
+
+/// Clones a file with a reflink (FICLONE) when the filesystem supports it, otherwise copies it.
+///
+/// Returns the size of the file.
+pub fn clone_file(src: &Path, dst: &Path) -> std::io::Result<u64> {
+    let src_file = File::open(src)?;
+    let metadata = src_file.metadata()?;
+    let dst_file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(dst)?;
+    let res = unsafe { libc::ioctl(dst_file.as_raw_fd(), libc::FICLONE, src_file.as_raw_fd()) };
+    if res == 0 {
+        dst_file.set_permissions(metadata.permissions())?;
+        return Ok(metadata.len());
+    }
+    drop(dst_file);
+    std::fs::copy(src, dst)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{ffi::OsStr, os::unix::fs::MetadataExt, os::unix::fs::PermissionsExt};
+
+    use super::*;
+    use crate::test_setup::{GitFsTestSetup, get_fs, local_repo_with_commits, run_git_fs_test};
+
+    #[test]
+    fn test_clone_chase_target() -> anyhow::Result<()> {
+        run_git_fs_test(
+            GitFsTestSetup {
+                key: "test_clone_chase_target",
+                read_only: false,
+            },
+            |_| -> anyhow::Result<()> {
+                let fs = get_fs();
+                let (_, snaps) = local_repo_with_commits("local", &["a.txt", "b.txt"])?;
+                let (old, new) = (snaps[0].1, snaps[1].1);
+
+                let target = fs.mkdir(old, OsStr::new("target"))?;
+                let (out, fh) = fs.create(target.ino, OsStr::new("out.bin"), true, true)?;
+                fs.write(out.ino, 0, b"built", fh)?;
+                fs.release(fh)?;
+                let out_path = build_path(&fs, out.ino)?;
+                std::fs::set_permissions(&out_path, std::fs::Permissions::from_mode(0o444))?;
+                fs.symlink(old, OsStr::new("latest"), Path::new("target/out.bin"))?;
+
+                clone_chase_target(&fs, &ChaseTarget::new(old), &ChaseTarget::new(new))?;
+
+                let new_target = fs.lookup(new, OsStr::new("target"))?.unwrap();
+                let cloned = fs.lookup(new_target.ino, OsStr::new("out.bin"))?.unwrap();
+                let cloned_path = build_path(&fs, cloned.ino)?;
+                assert_eq!(std::fs::read(&cloned_path)?, b"built");
+                // A copy, not a hardlink of the read-only file
+                assert_ne!(cloned_path.metadata()?.ino(), out_path.metadata()?.ino());
+                assert_eq!(cloned_path.metadata()?.permissions().mode() & 0o777, 0o444);
+
+                let link = fs.lookup(new, OsStr::new("latest"))?.unwrap();
+                assert_eq!(link.kind, FileType::Symlink);
+                assert_eq!(fs.readlink(link.ino)?, b"target/out.bin");
+                // The previous target keeps its build files
+                assert!(fs.lookup(target.ino, OsStr::new("out.bin"))?.is_some());
+                Ok(())
+            },
+        )
+    }
+
+    #[test]
+    fn test_kept_commit_keeps_build_files() -> anyhow::Result<()> {
+        run_git_fs_test(
+            GitFsTestSetup {
+                key: "test_kept_commit_keeps_build_files",
+                read_only: false,
+            },
+            |_| -> anyhow::Result<()> {
+                let fs = get_fs();
+                let (repo_ino, snaps) =
+                    local_repo_with_commits("local", &["a.txt", "b.txt", "c.txt"])?;
+                let targets = snaps
+                    .iter()
+                    .map(|(_, ino)| ChaseTarget::new(*ino))
+                    .collect::<Vec<_>>();
+                let (failed_oid, _) = snaps[0];
+                let (out, fh) = fs.create(targets[0].0, OsStr::new("out.log"), true, true)?;
+                fs.write(out.ino, 0, b"failed build", fh)?;
+                fs.release(fh)?;
+
+                let mut args = ChaseArgs::watch();
+                assert_eq!(
+                    CarryMode::new(&fs, &args, failed_oid, &targets[0]),
+                    CarryMode::Move
+                );
+                args.keep = KeepMode::Failed;
+                let mode = CarryMode::new(&fs, &args, failed_oid, &targets[0]);
+                assert_eq!(mode, CarryMode::Clone);
+                mode.carry(&fs, &targets[0], &targets[1])?;
+                assert!(fs.lookup(targets[0].0, OsStr::new("out.log"))?.is_some());
+                assert!(fs.lookup(targets[1].0, OsStr::new("out.log"))?.is_some());
+
+                // A session pinned by an earlier chase is not emptied by a later one
+                let repo = fs.get_repo(repo_ino)?;
+                assert!(repo.set_build_session_pinned(failed_oid, true));
+                let mode = CarryMode::new(&fs, &ChaseArgs::watch(), failed_oid, &targets[0]);
+                assert_eq!(mode, CarryMode::Clone);
+                mode.carry(&fs, &targets[0], &targets[2])?;
+                assert!(fs.lookup(targets[0].0, OsStr::new("out.log"))?.is_some());
+                Ok(())
+            },
+        )
+    }
+}
@@ -0,0 +1,439 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::{Context, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::fs::builds::{chase_log::LogStream, logger::LogLine};
+
+/// Name of the file holding the TestMatrix, inside the folder of each run
+pub const TESTS_FILE: &str = "tests.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TestStatus {
+    Pass,
+    Fail,
+    Skip,
+}
+
+impl TestStatus {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pass => "pass",
+            Self::Fail => "fail",
+            Self::Skip => "skip",
+        }
+    }
+}
+
+/// Reads the results of single tests from the output of a command
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ResultParser {
+    /// `cargo test -- -Z unstable-options --format json`, on stdout
+    Libtest,
+    /// Test Anything Protocol, on stdout
+    Tap,
+    /// JUnit XML files written by the command. A file or a folder, relative to the folder the command ran in.
+    Junit { results: PathBuf },
+}
+
+impl ResultParser {
+    pub fn from_name(name: &str, results: Option<String>) -> Option<Self> {
+        match (name.to_lowercase().as_str(), results) {
+            ("libtest" | "cargo", _) => Some(Self::Libtest),
+            ("tap", _) => Some(Self::Tap),
+            ("junit", Some(results)) => Some(Self::Junit {
+                results: PathBuf::from(results),
+            }),
+            _ => None,
+        }
+    }
+
+    /// The result files already in `dir` before the command ran, with their modification time
+    pub fn existing_files(&self, dir: &Path) -> HashMap<PathBuf, SystemTime> {
+        self.result_files(dir)
+            .into_iter()
+            .filter_map(|f| {
+                let modified = std::fs::metadata(&f).and_then(|m| m.modified()).ok()?;
+                Some((f, modified))
+            })
+            .collect()
+    }
+
+    /// `dir` is the folder the command ran in. Result files it did not write are left from an earlier commit.
+    pub fn parse(
+        &self,
+        lines: &[LogLine],
+        dir: &Path,
+        existing: &HashMap<PathBuf, SystemTime>,
+    ) -> anyhow::Result<Vec<(String, TestStatus)>> {
+        let stdout = lines
+            .iter()
+            .filter(|l| l.stream == LogStream::Stdout)
+            .map(|l| String::from_utf8_lossy(&l.line));
+        match self {
+            Self::Libtest => Ok(stdout.filter_map(|l| parse_libtest(&l)).collect()),
+            Self::Tap => Ok(stdout.filter_map(|l| parse_tap(&l)).collect()),
+            Self::Junit { .. } => {
+                let mut tests = Vec::new();
+                for file in self.result_files(dir) {
+                    let modified = std::fs::metadata(&file).and_then(|m| m.modified());
+                    let Ok(modified) = modified else {
+                        continue;
+                    };
+                    if existing.get(&file) == Some(&modified) {
+                        continue;
+                    }
+                    let xml = std::fs::read_to_string(&file)
+                        .with_context(|| format!("Could not read {}", file.display()))?;
+                    tests.extend(
+                        parse_junit(&xml)
+                            .with_context(|| format!("Bad JUnit file {}", file.display()))?,
+                    );
+                }
+                Ok(tests)
+            }
+        }
+    }
+
+    fn result_files(&self, dir: &Path) -> Vec<PathBuf> {
+        let Self::Junit { results } = self else {
+            return vec![];
+        };
+        let path = dir.join(results);
+        let Ok(entries) = std::fs::read_dir(&path) else {
+            return vec![path];
+        };
+        let mut files = entries
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.extension().is_some_and(|e| e == "xml"))
+            .collect::<Vec<PathBuf>>();
+        files.sort_unstable();
+        files
+    }
+}
+
+/// `{ "type": "test", "event": "ok", "name": "..." }`
+fn parse_libtest(line: &str) -> Option<(String, TestStatus)> {
+    let event: serde_json::Value = serde_json::from_str(line.trim()).ok()?;
+    if event.get("type")?.as_str()? != "test" {
+        return None;
+    }
+    let status = match event.get("event")?.as_str()? {
+        "ok" => TestStatus::Pass,
+        "failed" | "timeout" => TestStatus::Fail,
+        "ignored" => TestStatus::Skip,
+        _ => return None,
+    };
+    Some((event.get("name")?.as_str()?.to_string(), status))
+}
+
+/// `ok 1 - name`, `not ok 2 name # TODO`. Indented lines belong to subtests and are skipped.
+fn parse_tap(line: &str) -> Option<(String, TestStatus)> {
+    let line = line.trim_end();
+    let (failed, rest) = if let Some(rest) = line.strip_prefix("not ok") {
+        (true, rest)
+    } else {
+        (false, line.strip_prefix("ok")?)
+    };
+    if !rest.is_empty() && !rest.starts_with(' ') {
+        return None;
+    }
+    let rest = rest.trim_start();
+    let num_len = rest
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rest.len());
+    let (num, desc) = rest.split_at(num_len);
+    let (desc, directive) = match desc.split_once(" # ").or_else(|| desc.split_once("# ")) {
+        Some((desc, directive)) => (desc, directive.to_lowercase()),
+        None => (desc, String::new()),
+    };
+    let desc = desc.trim().trim_start_matches("- ").trim();
+    let name = if desc.is_empty() { num } else { desc };
+    if name.is_empty() {
+        return None;
+    }
+    let status = if directive.starts_with("skip") || directive.starts_with("todo") {
+        TestStatus::Skip
+    } else if failed {
+        TestStatus::Fail
+    } else {
+        TestStatus::Pass
+    };
+    Some((name.to_string(), status))
+}
+
+/// Only the testcase elements are read. The name is `classname.name`.
+fn parse_junit(xml: &str) -> anyhow::Result<Vec<(String, TestStatus)>> {
+    let mut tests = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find("<testcase") {
+        rest = &rest[start + "<testcase".len()..];
+        let Some(tag_end) = rest.find('>') else {
+            bail!("Unclosed testcase element");
+        };
+        let attrs = &rest[..tag_end];
+        let self_closing = attrs.ends_with('/');
+        let Some(name) = xml_attr(attrs, "name") else {
+            bail!("Testcase without a name");
+        };
+        let name = match xml_attr(attrs, "classname") {
+            Some(class) if !class.is_empty() => format!("{class}.{name}"),
+            _ => name,
+        };
+        rest = &rest[tag_end + 1..];
+        let body = if self_closing {
+            ""
+        } else {
+            let Some(end) = rest.find("</testcase>") else {
+                bail!("Testcase {name:?} is not closed");
+            };
+            let body = &rest[..end];
+            rest = &rest[end..];
+            body
+        };
+        let status = if body.contains("<failure") || body.contains("<error") {
+            TestStatus::Fail
+        } else if body.contains("<skipped") {
+            TestStatus::Skip
+        } else {
+            TestStatus::Pass
+        };
+        tests.push((name, status));
+    }
+    Ok(tests)
+}
+
+fn xml_attr(attrs: &str, key: &str) -> Option<String> {
+    let mut rest = attrs;
+    loop {
+        let pos = rest.find(key)?;
+        let before = rest[..pos].chars().next_back();
+        let after = rest[pos + key.len()..].trim_start();
+        rest = &rest[pos + key.len()..];
+        if !before.is_some_and(char::is_whitespace) {
+            continue;
+        }
+        let Some(value) = after.strip_prefix('=') else {
+            continue;
+        };
+        let value = value.trim_start();
+        let quote = value.chars().next()?;
+        if quote != '"' && quote != '\'' {
+            return None;
+        }
+        let value = &value[1..];
+        let end = value.find(quote)?;
+        return Some(
+            value[..end]
+                .replace("&lt;", "<")
+                .replace("&gt;", ">")
+                .replace("&quot;", "\"")
+                .replace("&apos;", "'")
+                .replace("&amp;", "&"),
+        );
+    }
+}
+
+/// A test that changed state between two chased commits
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TestChange {
+    pub test: String,
+    pub from: TestStatus,
+    pub to: TestStatus,
+    /// The first commit with the new state
+    pub commit: String,
+}
+
+/// The status of every test, for each chased commit in the chase order
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct TestMatrix {
+    pub commits: Vec<String>,
+    /// None when the test did not run on that commit
+    pub tests: BTreeMap<String, Vec<Option<TestStatus>>>,
+}
+
+impl TestMatrix {
+    pub fn new(commits: &[String], results: &[(String, Vec<(String, TestStatus)>)]) -> Self {
+        let mut tests: BTreeMap<String, Vec<Option<TestStatus>>> = BTreeMap::new();
+        for (oid, commit_tests) in results {
+            let Some(pos) = commits.iter().position(|c| c == oid) else {
+                continue;
+            };
+            for (name, status) in commit_tests {
+                let row = tests
+                    .entry(name.clone())
+                    .or_insert_with(|| vec![None; commits.len()]);
+                // A test ran by two commands only passes if both runs passed
+                if row[pos] != Some(TestStatus::Fail) {
+                    row[pos] = Some(*status);
+                }
+            }
+        }
+        Self {
+            commits: commits.to_vec(),
+            tests,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tests.is_empty()
+    }
+
+    /// Commits where a test did not run are skipped, a change is between two commits where it ran
+    pub fn changes(&self) -> Vec<TestChange> {
+        let mut changes = Vec::new();
+        for (test, row) in &self.tests {
+            let mut prev: Option<TestStatus> = None;
+            for (pos, status) in row.iter().enumerate() {
+                let Some(status) = status else {
+                    continue;
+                };
+                if let Some(from) = prev
+                    && from != *status
+                {
+                    changes.push(TestChange {
+                        test: test.clone(),
+                        from,
+                        to: *status,
+                        commit: self.commits[pos].clone(),
+                    });
+                }
+                prev = Some(*status);
+            }
+        }
+        changes
+    }
+
+    pub fn save(&self, dir: &Path) -> anyhow::Result<PathBuf> {
+        let path = dir.join(TESTS_FILE);
+        let data = serde_json::to_vec_pretty(&serde_json::json!({
+            "commits": self.commits,
+            "tests": self.tests,
+            "changes": self.changes(),
+        }))?;
+        std::fs::write(&path, data)?;
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_result_parsers_and_matrix() -> anyhow::Result<()> {
+        let libtest = [
+            r#"{ "type": "suite", "event": "started", "test_count": 3 }"#,
+            r#"{ "type": "test", "event": "started", "name": "a::one" }"#,
+            r#"{ "type": "test", "name": "a::one", "event": "ok" }"#,
+            r#"{ "type": "test", "name": "a::two", "event": "failed", "stdout": "boom" }"#,
+            r#"{ "type": "test", "name": "a::three", "event": "ignored" }"#,
+            "running 3 tests",
+        ]
+        .map(|l| LogLine::new(format!("{l}\n").as_bytes(), LogStream::Stdout));
+        let dir = tempfile::tempdir()?;
+        let none = HashMap::new();
+        assert_eq!(
+            ResultParser::Libtest.parse(&libtest, dir.path(), &none)?,
+            vec![
+                ("a::one".to_string(), TestStatus::Pass),
+                ("a::two".to_string(), TestStatus::Fail),
+                ("a::three".to_string(), TestStatus::Skip),
+            ]
+        );
+
+        let tap = "TAP version 13\n1..4\nok 1 - parses input\nnot ok 2 - handles errors\nok 3 # SKIP no network\nnot ok 4 flaky # TODO fix\n    ok 1 - subtest\n";
+        let tap = tap
+            .lines()
+            .map(|l| LogLine::new(l.as_bytes(), LogStream::Stdout))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ResultParser::Tap.parse(&tap, dir.path(), &none)?,
+            vec![
+                ("parses input".to_string(), TestStatus::Pass),
+                ("handles errors".to_string(), TestStatus::Fail),
+                ("3".to_string(), TestStatus::Skip),
+                ("flaky".to_string(), TestStatus::Skip),
+            ]
+        );
+
+        std::fs::create_dir(dir.path().join("reports"))?;
+        std::fs::write(
+            dir.path().join("reports/TEST-a.xml"),
+            r#"<?xml version="1.0"?>
+<testsuite name="a" tests="3">
+  <testcase classname="pkg.A" name="ok &amp; fine" time="0.1"/>
+  <testcase classname="pkg.A" name="broken"><failure message="x">trace</failure></testcase>
+  <testcase name="later"><skipped/></testcase>
+</testsuite>"#,
+        )?;
+        let junit = ResultParser::from_name("junit", Some("reports".to_string())).unwrap();
+        assert_eq!(
+            junit.parse(&[], dir.path(), &none)?,
+            vec![
+                ("pkg.A.ok & fine".to_string(), TestStatus::Pass),
+                ("pkg.A.broken".to_string(), TestStatus::Fail),
+                ("later".to_string(), TestStatus::Skip),
+            ]
+        );
+        // Left from an earlier commit
+        let existing = junit.existing_files(dir.path());
+        assert!(junit.parse(&[], dir.path(), &existing)?.is_empty());
+        assert!(ResultParser::from_name("junit", None).is_none());
+
+        let commits = ["c1", "c2", "c3", "c4"].map(String::from);
+        let results = vec![
+            (
+                "c1".to_string(),
+                vec![
+                    ("t1".to_string(), TestStatus::Pass),
+                    ("t2".to_string(), TestStatus::Fail),
+                ],
+            ),
+            ("c2".to_string(), vec![("t1".to_string(), TestStatus::Pass)]),
+            (
+                "c3".to_string(),
+                vec![
+                    ("t1".to_string(), TestStatus::Fail),
+                    ("t2".to_string(), TestStatus::Pass),
+                ],
+            ),
+            (
+                "c4".to_string(),
+                vec![
+                    ("t1".to_string(), TestStatus::Fail),
+                    ("t1".to_string(), TestStatus::Pass),
+                ],
+            ),
+        ];
+        let matrix = TestMatrix::new(&commits, &results);
+        assert_eq!(
+            matrix.tests["t2"],
+            vec![Some(TestStatus::Fail), None, Some(TestStatus::Pass), None]
+        );
+        assert_eq!(
+            matrix.changes(),
+            vec![
+                TestChange {
+                    test: "t1".to_string(),
+                    from: TestStatus::Pass,
+                    to: TestStatus::Fail,
+                    commit: "c3".to_string(),
+                },
+                TestChange {
+                    test: "t2".to_string(),
+                    from: TestStatus::Fail,
+                    to: TestStatus::Pass,
+                    commit: "c3".to_string(),
+                },
+            ]
+        );
+        Ok(())
+    }
+}
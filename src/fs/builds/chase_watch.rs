@@ -0,0 +1,384 @@
+use std::{
+    collections::HashMap,
+    io::{BufReader, Write},
+    os::unix::net::UnixStream,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+    thread::JoinHandle,
+};
+
+use crossbeam_channel::Sender;
+use git2::Oid;
+use serde_json::Deserializer;
+
+use crate::{
+    fs::{
+        GitFs,
+        builds::{
+            chase::{ChaseArgs, chase_commits},
+            chase_handle::ChaseHandle,
+            chase_resolver::MatchedInputs,
+            logger::CmdResult,
+            reporter::{ErrorResolver, Updater},
+            runtime::LuaConfig,
+        },
+        repo::{RefChanges, RefKind},
+    },
+    internals::sock::ControlRes,
+    mount::GitFsAdapter,
+};
+
+/// Output of the chases started by the watch rules, next to the chase.lua of the script
+pub const WATCH_LOG: &str = "watch.log";
+
+static WATCH_QUEUE: OnceLock<Sender<WatchFetch>> = OnceLock::new();
+
+/// The refs watched by a script, set with cfg.add_watch
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WatchRule {
+    /// A branch, "main" or "master" also match the main branch
+    Branch(String),
+    /// The commits of a PR, or of every PR with "all"
+    Pr(String),
+    /// The merge commit of a PR, or of every PR with "all"
+    PrMerge(String),
+    /// A tag, or any new tag with "all"
+    Tag(String),
+}
+
+impl WatchRule {
+    pub fn from_str(kind: &str, name: &str) -> Option<Self> {
+        let name = name.to_string();
+        match kind.to_lowercase().as_str() {
+            "branch" => Some(Self::Branch(name)),
+            "pr" => Some(Self::Pr(name)),
+            "prmerge" => Some(Self::PrMerge(name)),
+            "tag" => Some(Self::Tag(name)),
+            _ => None,
+        }
+    }
+
+    pub fn matches(&self, rf: &RefKind) -> bool {
+        let is = |rule: &str, name: &str| rule == "all" || rule == name;
+        match (self, rf) {
+            (Self::Branch(rule), RefKind::Branch(name)) => rule == name,
+            (Self::Branch(rule), RefKind::Main(name)) => {
+                rule == name || rule == "main" || rule == "master"
+            }
+            (Self::Pr(rule), RefKind::Pr(name)) => is(rule, name),
+            (Self::PrMerge(rule), RefKind::PrMerge(name)) => is(rule, name),
+            (Self::Tag(rule), RefKind::Tag(name)) => is(rule, name),
+            _ => false,
+        }
+    }
+}
+
+/// The refs of a repo that changed in a fetch
+struct WatchFetch {
+    repo: String,
+    changes: RefChanges,
+}
+
+/// A chase started by the watch rules, on the new commits of the refs
+struct WatchRun {
+    repo: String,
+    script: String,
+    commits: Vec<Oid>,
+    matched: MatchedInputs,
+}
+
+/// Starts the thread reading the watch rules of the scripts after each fetch,
+/// and running their chases one at a time
+pub fn start_watch_worker(fs: GitFsAdapter) {
+    let (tx, rx) = crossbeam_channel::unbounded::<WatchFetch>();
+    if WATCH_QUEUE.set(tx).is_err() {
+        return;
+    }
+    std::thread::spawn(move || {
+        for fetch in rx {
+            let fs = fs.getfs();
+            let runs = match watch_runs(&fs, &fetch) {
+                Ok(runs) => runs,
+                Err(e) => {
+                    tracing::error!(e = %e, repo = %fetch.repo, "Could not read the watch rules");
+                    continue;
+                }
+            };
+            for run in runs {
+                if let Err(e) = run_watched(&fs, run) {
+                    tracing::error!(e = %e, "Watched chase failed");
+                }
+            }
+        }
+    });
+}
+
+/// Hands the refs that changed to the watch worker, which chases them with the scripts watching them.
+///
+/// Returns false when nothing is queued.
+pub fn queue_watch_runs(repo_name: &str, changes: RefChanges) -> anyhow::Result<bool> {
+    if changes.is_empty() {
+        return Ok(false);
+    }
+    let Some(queue) = WATCH_QUEUE.get() else {
+        return Ok(false);
+    };
+    queue.send(WatchFetch {
+        repo: repo_name.to_string(),
+        changes,
+    })?;
+    Ok(true)
+}
+
+/// Loads the scripts of the repo and keeps the ones watching the refs that changed
+///
+/// A script that does not load is reported in its watch log.
+fn watch_runs(fs: &GitFs, fetch: &WatchFetch) -> anyhow::Result<Vec<WatchRun>> {
+    let Some(repo_entry) = fs.repos_map.get(&fetch.repo) else {
+        anyhow::bail!("Repo {} not found", fetch.repo);
+    };
+    let repo = fs.get_repo(GitFs::repo_id_to_ino(*repo_entry.value()))?;
+    let Ok(scripts) = std::fs::read_dir(&repo.chase_dir) else {
+        return Ok(vec![]);
+    };
+
+    let mut runs = Vec::new();
+    for script_path in scripts.filter_map(|e| e.ok().map(|e| e.path())) {
+        if !script_path.join("chase.lua").exists() {
+            continue;
+        }
+        let Some(script) = script_path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+        else {
+            continue;
+        };
+        let cfg = match LuaConfig::read_lua(&script_path) {
+            Ok(cfg) => cfg,
+            Err(e) => {
+                let (mut stream, writer) = open_watch_log(script_path.join(WATCH_LOG))?;
+                stream.update(&format!(
+                    "==> {} the script could not be loaded to check its watch rules\n",
+                    chrono::offset::Utc::now().format("%Y-%m-%d %H:%M:%S"),
+                ))?;
+                let _ = Err::<(), _>(e).resolve(&mut stream);
+                drop(stream);
+                let _ = writer.join();
+                continue;
+            }
+        };
+        let Some((commits, matched)) = watched_commits(&cfg, &fetch.changes) else {
+            continue;
+        };
+        runs.push(WatchRun {
+            repo: fetch.repo.clone(),
+            script,
+            commits,
+            matched,
+        });
+    }
+    Ok(runs)
+}
+
+/// The new commits on the refs watched by the script, oldest first
+fn watched_commits(cfg: &LuaConfig, changes: &RefChanges) -> Option<(Vec<Oid>, MatchedInputs)> {
+    let mut commits: Vec<(i64, Oid)> = Vec::new();
+    let mut matched: MatchedInputs = HashMap::new();
+    for (rf, added) in changes {
+        if !cfg.watch.iter().any(|rule| rule.matches(rf)) {
+            continue;
+        }
+        let label = watch_label(rf);
+        for (time, oid) in added {
+            let labels = matched.entry(*oid).or_default();
+            if labels.is_empty() {
+                commits.push((*time, *oid));
+            }
+            if !labels.contains(&label) {
+                labels.push(label.clone());
+            }
+        }
+    }
+    if commits.is_empty() {
+        return None;
+    }
+    commits.sort_by_key(|(time, _)| *time);
+    let mut commits = commits
+        .into_iter()
+        .map(|(_, oid)| oid)
+        .collect::<Vec<Oid>>();
+    if let Some(limit) = &cfg.limit {
+        limit.apply(&mut commits);
+        matched.retain(|oid, _| commits.contains(oid));
+    }
+    Some((commits, matched))
+}
+
+/// Same labels as the inputs of the script, so the PR report groups them
+fn watch_label(rf: &RefKind) -> String {
+    let kind = match rf {
+        RefKind::Branch(_) | RefKind::Main(_) | RefKind::Head(_) => "Branch",
+        RefKind::Tag(_) => "Tag",
+        RefKind::Pr(_) => "Pr",
+        RefKind::PrMerge(_) => "PrMerge",
+    };
+    format!("{kind} {}", rf.get())
+}
+
+/// Runs a chase like `guse chase`, with its output appended to the watch log of the script
+fn run_watched(fs: &GitFs, run: WatchRun) -> anyhow::Result<()> {
+    let CmdResult::Ok(chase_id) = ChaseHandle::register_chase_id() else {
+        anyhow::bail!("Could not register the chase");
+    };
+    let repo_ino = GitFs::repo_id_to_ino(
+        *fs.repos_map
+            .get(&run.repo)
+            .ok_or_else(|| anyhow::anyhow!("Repo {} not found", run.repo))?
+            .value(),
+    );
+    let log_path = fs
+        .get_repo(repo_ino)?
+        .chase_dir
+        .join(&run.script)
+        .join(WATCH_LOG);
+
+    let (mut stream, writer) = open_watch_log(log_path)?;
+    stream.update(&format!(
+        "==> {} watch run {chase_id} on {} new commits. Stop it with: guse chase stop {chase_id}\n",
+        chrono::offset::Utc::now().format("%Y-%m-%d %H:%M:%S"),
+        run.commits.len()
+    ))?;
+    let res = chase_commits(
+        fs,
+        &run.repo,
+        &run.script,
+        &mut stream,
+        ChaseArgs::watch(),
+        chase_id,
+        Some((run.commits.into(), run.matched)),
+    );
+    drop(stream);
+    let _ = writer.join();
+    res
+}
+
+/// A stream whose messages are appended to the watch log, until it is dropped
+fn open_watch_log(
+    log_path: PathBuf,
+) -> anyhow::Result<(UnixStream, JoinHandle<anyhow::Result<()>>)> {
+    let (stream, output) = UnixStream::pair()?;
+    let writer = std::thread::spawn(move || write_watch_log(output, &log_path));
+    Ok((stream, writer))
+}
+
+fn write_watch_log(output: UnixStream, log_path: &Path) -> anyhow::Result<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_path)?;
+    let messages = Deserializer::from_reader(BufReader::new(output)).into_iter::<ControlRes>();
+    for res in messages {
+        match res? {
+            ControlRes::Update { message } => file.write_all(&message)?,
+            ControlRes::Draw { message } => {
+                for line in message {
+                    file.write_all(&line)?;
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fs::builds::runtime::CommitLimit;
+    use crate::test_setup::{GitFsTestSetup, get_fs, local_repo_with_commits, run_git_fs_test};
+
+    #[test]
+    fn test_watched_commits() {
+        let oid = |n: u8| Oid::from_bytes(&[n; 20]).unwrap();
+        let mut changes = RefChanges::new();
+        changes.insert(
+            RefKind::Main("main".to_string()),
+            vec![(20, oid(2)), (30, oid(3))],
+        );
+        changes.insert(RefKind::Branch("feature".to_string()), vec![(10, oid(1))]);
+        changes.insert(
+            RefKind::Pr("7".to_string()),
+            vec![(30, oid(3)), (40, oid(4))],
+        );
+        changes.insert(RefKind::Tag("v1".to_string()), vec![(50, oid(5))]);
+
+        let mut cfg = LuaConfig {
+            watch: vec![
+                WatchRule::Branch("main".to_string()),
+                WatchRule::Pr("all".to_string()),
+            ],
+            ..Default::default()
+        };
+        let (commits, matched) = watched_commits(&cfg, &changes).unwrap();
+        assert_eq!(commits, vec![oid(2), oid(3), oid(4)]);
+        let mut labels = matched[&oid(3)].clone();
+        labels.sort();
+        assert_eq!(labels, vec!["Branch main", "Pr 7"]);
+
+        cfg.limit = Some(CommitLimit::Last(1));
+        assert_eq!(watched_commits(&cfg, &changes).unwrap().0, vec![oid(4)]);
+
+        // "main" and "master" both match the main branch, whatever its name
+        let master = RefKind::Main("master".to_string());
+        assert!(WatchRule::Branch("main".to_string()).matches(&master));
+        assert!(WatchRule::Branch("master".to_string()).matches(&master));
+        assert!(!WatchRule::Branch("dev".to_string()).matches(&master));
+        assert!(
+            !WatchRule::Branch("master".to_string()).matches(&RefKind::Branch("main".to_string()))
+        );
+
+        cfg.watch = vec![WatchRule::Tag("v2".to_string())];
+        assert!(watched_commits(&cfg, &changes).is_none());
+        assert!(WatchRule::from_str("remote", "x").is_none());
+    }
+
+    #[test]
+    fn test_watch_runs_log_broken_scripts() -> anyhow::Result<()> {
+        run_git_fs_test(
+            GitFsTestSetup {
+                key: "test_watch_runs_log_broken_scripts",
+                read_only: false,
+            },
+            |_| -> anyhow::Result<()> {
+                let fs = get_fs();
+                let (repo_ino, snaps) = local_repo_with_commits("watched", &["a"])?;
+                let repo = fs.get_repo(repo_ino)?;
+                let good = repo.chase_dir.join("good");
+                let broken = repo.chase_dir.join("broken");
+                std::fs::create_dir_all(&good)?;
+                std::fs::create_dir_all(&broken)?;
+                std::fs::write(
+                    good.join("chase.lua"),
+                    "cfg.add_watch(\"Branch\", \"main\")\ncfg.add_command(\"true\")\n",
+                )?;
+                std::fs::write(broken.join("chase.lua"), "cfg.add_watch(")?;
+
+                let mut changes = RefChanges::new();
+                changes.insert(RefKind::Main("master".to_string()), vec![(0, snaps[0].0)]);
+                let fetch = WatchFetch {
+                    repo: "watched".to_string(),
+                    changes,
+                };
+                let runs = watch_runs(&fs, &fetch)?;
+                assert_eq!(runs.len(), 1);
+                assert_eq!(runs[0].script, "good");
+                assert_eq!(runs[0].commits, [snaps[0].0]);
+
+                let log = std::fs::read_to_string(broken.join(WATCH_LOG))?;
+                assert!(log.contains("could not be loaded"), "{log}");
+                assert!(!good.join(WATCH_LOG).exists());
+                Ok(())
+            },
+        )
+    }
+}
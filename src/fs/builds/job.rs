@@ -1,66 +1,496 @@
 use std::{
-    os::unix::process::CommandExt,
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::Read,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd},
+        unix::process::CommandExt,
+    },
     process::{Child, Command, Stdio},
+    sync::{
+        LazyLock, Once,
+        atomic::{AtomicU64, Ordering},
+    },
     time::{Duration, Instant},
 };
 
-use crate::fs::builds::logger::CmdResult;
+use parking_lot::Mutex;
+
+use crate::fs::builds::{logger::CmdResult, runtime::PtySize};
+
+/// Env variable set on every job. Used to tell which job an orphan adopted by the daemon comes from,
+/// unless it cleared its env.
+pub const JOB_ENV: &str = "GUSE_JOB";
+/// Seconds between SIGTERM and SIGKILL, when a job is terminated
+pub const DEFAULT_KILL_GRACE: u64 = 5;
+/// TERM of the jobs ran on a pty, unless the env of the chase sets it
+pub const PTY_TERM: &str = "xterm-256color";
+
+static JOB_ID: AtomicU64 = AtomicU64::new(1);
+/// The daemon becomes a child subreaper: the orphans of the jobs are reparented to it instead of init,
+/// so they can still be found, killed and reaped.
+static SUBREAPER: Once = Once::new();
+static REGISTRY: LazyLock<Mutex<Registry>> = LazyLock::new(Default::default);
+
+/// The running jobs and the descendants each one was seen with
+#[derive(Default)]
+struct Registry {
+    /// key: pid of a job leader, left to its `Child`
+    leaders: HashMap<libc::pid_t, Leader>,
+    /// key: pid of a descendant, value: id of its job. Forgotten once the process is reaped.
+    owners: HashMap<libc::pid_t, u64>,
+}
+
+struct Leader {
+    id: u64,
+    /// Value of JOB_ENV for the job
+    cookie: String,
+    /// Start time of the leader, in clock ticks since boot
+    start: u64,
+}
 
 pub struct Job {
     pub child: Child,
     pub pgid: libc::pid_t,
+    id: u64,
+    grace: Duration,
 }
 
 impl Job {
-    pub fn spawn(mut cmd: Command) -> CmdResult<Self> {
+    pub fn spawn(mut cmd: Command, grace: Duration) -> CmdResult<Self> {
         // https://users.rust-lang.org/t/prevent-program-from-exiting-on-child-sigint/7685/8
-        let output = unsafe {
+        unsafe {
             cmd.stdout(Stdio::piped())
                 .stderr(Stdio::piped())
                 .pre_exec(|| {
                     // Make the child process its own process group leader
                     libc::setpgid(0, 0);
                     Ok(())
-                })
-                .spawn()
+                });
+        }
+        Self::start(cmd, grace)
+    }
+
+    /// Runs the job on a new pseudo-terminal, so it behaves like in an interactive shell (colors, progress bars).
+    ///
+    /// stdout and stderr both go to the terminal. stdin is /dev/null, so a command waiting for input does not hang the chase.
+    pub fn spawn_pty(
+        mut cmd: Command,
+        grace: Duration,
+        size: PtySize,
+    ) -> CmdResult<(Self, PtyReader)> {
+        let (master, slave) = match open_pty(size) {
+            Ok(fds) => fds,
+            Err(e) => return CmdResult::Err(format!("Could not open a pty: {e}\n")),
+        };
+        let (out, err) = match (slave.try_clone(), slave.try_clone()) {
+            (Ok(out), Ok(err)) => (out, err),
+            (Err(e), _) | (_, Err(e)) => {
+                return CmdResult::Err(format!("Could not open a pty: {e}\n"));
+            }
         };
+        if !cmd.get_envs().any(|(key, _)| key == "TERM") {
+            cmd.env("TERM", PTY_TERM);
+        }
+        unsafe {
+            cmd.stdin(Stdio::null())
+                .stdout(Stdio::from(out))
+                .stderr(Stdio::from(err))
+                .pre_exec(|| {
+                    // A new session is also a new process group, the pty becomes its controlling terminal
+                    if libc::setsid() < 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    libc::ioctl(libc::STDOUT_FILENO, libc::TIOCSCTTY, 0);
+                    Ok(())
+                });
+        }
+        // Only the child keeps the slave side open, so the reader sees the end of the output
+        drop(slave);
+        match Self::start(cmd, grace) {
+            CmdResult::Ok(job) => CmdResult::Ok((job, PtyReader(File::from(master)))),
+            CmdResult::Err(e) => CmdResult::Err(e),
+            CmdResult::ExitFail(e) => CmdResult::ExitFail(e),
+        }
+    }
+
+    fn start(mut cmd: Command, grace: Duration) -> CmdResult<Self> {
+        SUBREAPER.call_once(|| unsafe {
+            libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0);
+        });
+        let id = JOB_ID.fetch_add(1, Ordering::Relaxed);
+        let cookie = format!("{}-{id}", std::process::id());
+
+        // Registered before it can exit, so the zombie of the leader is never taken for an orphan
+        let mut registry = REGISTRY.lock();
+        let output = cmd.env(JOB_ENV, &cookie).spawn();
 
         let child = match output {
             Ok(o) => o,
             Err(e) => {
+                // The Debug of cmd would also print its env
+                let program = cmd.get_program().to_string_lossy();
                 if e.kind() == std::io::ErrorKind::NotFound {
-                    return CmdResult::Err(format!("Command not found: {:?}\n", cmd));
+                    return CmdResult::Err(format!("Command not found: {program}\n"));
                 }
-                return CmdResult::Err(format!("Failed to run {:?}: {}\n", cmd, e));
+                return CmdResult::Err(format!("Failed to run {program}: {e}\n"));
             }
         };
 
-        let pgid = unsafe { libc::getpgid(child.id() as libc::pid_t) };
+        let pid = child.id() as libc::pid_t;
+        // The leader can exit before it is read, its orphans are then only claimed by their JOB_ENV
+        let start = proc_stat(pid).map_or(u64::MAX, |st| st.start);
+        registry.leaders.insert(pid, Leader { id, cookie, start });
+        drop(registry);
+
+        let pgid = unsafe { libc::getpgid(pid) };
         if pgid < 0 {
             return CmdResult::Err(format!("{}", std::io::Error::last_os_error()));
         }
 
-        CmdResult::Ok(Self { child, pgid })
+        CmdResult::Ok(Self {
+            child,
+            pgid,
+            id,
+            grace,
+        })
     }
 
+    /// Records the running descendants, and reaps the orphans that exited
+    ///
+    /// Called periodically while the job runs, so the parent of each descendant is known before it exits
+    pub fn track(&self) {
+        self.running();
+    }
+
+    /// Whether some descendants of the job are still running
+    pub fn has_running(&self) -> bool {
+        !self.running().is_empty()
+    }
+
+    /// Sends SIGTERM to the job and all its descendants, then SIGKILL after the grace period.
+    ///
+    /// Also used after the command exits, to clean up anything it left running.
     pub fn terminate(&mut self) -> CmdResult<()> {
-        let _ = unsafe { libc::kill(-(self.child.id() as i32), libc::SIGKILL) };
+        self.signal_all(libc::SIGTERM);
 
-        let stop_time = Instant::now() + Duration::from_secs(5);
+        let stop_time = Instant::now() + self.grace;
         loop {
-            match self.child.try_wait() {
-                Ok(Some(_)) => return CmdResult::Ok(()),
-                Ok(None) => {
-                    if Instant::now() >= stop_time {
-                        break;
-                    }
-                    std::thread::sleep(Duration::from_millis(50));
-                }
-                Err(_) => return CmdResult::Ok(()),
+            let exited = !matches!(self.child.try_wait(), Ok(None));
+            if exited && self.running().is_empty() {
+                break;
+            }
+            if Instant::now() >= stop_time {
+                self.signal_all(libc::SIGKILL);
+                let _ = self.child.wait();
+                break;
             }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        // Give the kernel a moment to deliver the signals, and reap the orphans
+        let stop_time = Instant::now() + Duration::from_secs(1);
+        loop {
+            self.running();
+            if self.known().is_empty() || Instant::now() >= stop_time {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
         }
-        let _ = unsafe { libc::kill(-(self.child.id() as i32), libc::SIGKILL) };
-        let _ = self.child.wait();
         CmdResult::Ok(())
     }
+
+    fn signal_all(&mut self, signal: libc::c_int) {
+        let running = self.running();
+        // Once the leader is reaped, its pid can be reused by another group.
+        // The group id is only kept while a process of the job is still in it.
+        let group_alive = matches!(self.child.try_wait(), Ok(None))
+            || running
+                .iter()
+                .any(|&pid| unsafe { libc::getpgid(pid) } == self.pgid);
+        if group_alive {
+            let _ = unsafe { libc::kill(-self.pgid, signal) };
+        }
+        // Only signal the processes found in this scan, an older pid might have been reused
+        for pid in running {
+            let _ = unsafe { libc::kill(pid, signal) };
+        }
+    }
+
+    /// The descendants of the job seen so far, until they are reaped
+    fn known(&self) -> Vec<libc::pid_t> {
+        let registry = REGISTRY.lock();
+        registry
+            .owners
+            .iter()
+            .filter(|(_, id)| **id == self.id)
+            .map(|(pid, _)| *pid)
+            .collect()
+    }
+
+    /// The running descendants of the job, whatever their env, group or session
+    ///
+    /// They are the processes below the leader or below a descendant seen before.
+    /// The orphans adopted by the daemon are claimed by their JOB_ENV variable, group or session,
+    /// or else by the last job started before them. The exited orphans are reaped on the way.
+    fn running(&self) -> Vec<libc::pid_t> {
+        let table = proc_table();
+        let daemon = std::process::id() as libc::pid_t;
+        let daemon_pgrp = unsafe { libc::getpgrp() };
+        let leader = self.child.id() as libc::pid_t;
+        let mut registry = REGISTRY.lock();
+
+        let alive = table.keys().copied().collect::<HashSet<libc::pid_t>>();
+        registry.owners.retain(|pid, _| alive.contains(pid));
+        // Children of the daemon that it did not spawn itself. Its other commands stay in its group.
+        let adopted = table
+            .values()
+            .filter(|st| {
+                st.ppid == daemon
+                    && st.pgrp != daemon_pgrp
+                    && !registry.leaders.contains_key(&st.pid)
+            })
+            .collect::<Vec<&ProcStat>>();
+        for st in &adopted {
+            if !st.is_running() {
+                unsafe { libc::waitpid(st.pid, std::ptr::null_mut(), libc::WNOHANG) };
+            } else if !registry.owners.contains_key(&st.pid)
+                && let Some(id) = adopted_owner(st, &registry)
+            {
+                registry.owners.insert(st.pid, id);
+            }
+        }
+
+        let mut members = registry
+            .owners
+            .iter()
+            .filter(|(_, id)| **id == self.id)
+            .map(|(pid, _)| *pid)
+            .collect::<HashSet<libc::pid_t>>();
+        // Once reaped, the pid of the leader can be reused
+        if table.get(&leader).is_some_and(|st| st.ppid == daemon) {
+            members.insert(leader);
+        }
+        loop {
+            let before = members.len();
+            for st in table.values() {
+                if members.contains(&st.ppid) {
+                    members.insert(st.pid);
+                }
+            }
+            if members.len() == before {
+                break;
+            }
+        }
+        members.remove(&leader);
+        for &pid in &members {
+            registry.owners.insert(pid, self.id);
+        }
+        members
+            .into_iter()
+            .filter(|pid| table[pid].is_running())
+            .collect()
+    }
+}
+
+impl Drop for Job {
+    fn drop(&mut self) {
+        let mut registry = REGISTRY.lock();
+        registry.leaders.remove(&(self.child.id() as libc::pid_t));
+        registry.owners.retain(|_, id| *id != self.id);
+    }
+}
+
+/// The master side of the pty of a job
+pub struct PtyReader(File);
+
+impl Read for PtyReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self.0.read(buf) {
+            // Linux returns EIO instead of EOF once the slave side is closed
+            Err(e) if e.raw_os_error() == Some(libc::EIO) => Ok(0),
+            res => res,
+        }
+    }
+}
+
+/// Returns the (master, slave) sides of a new pty
+fn open_pty(size: PtySize) -> std::io::Result<(OwnedFd, OwnedFd)> {
+    let (mut master, mut slave) = (-1, -1);
+    let winsize = libc::winsize {
+        ws_row: size.rows,
+        ws_col: size.cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    let res = unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            &winsize,
+        )
+    };
+    if res < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let (master, slave) = unsafe { (OwnedFd::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) };
+    unsafe {
+        // Not inherited by the other jobs
+        libc::fcntl(master.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC);
+        libc::fcntl(slave.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC);
+        // Lines end with \n instead of \r\n, like the piped output
+        let mut termios: libc::termios = std::mem::zeroed();
+        if libc::tcgetattr(slave.as_raw_fd(), &mut termios) == 0 {
+            termios.c_oflag &= !libc::ONLCR;
+            libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &termios);
+        }
+    }
+    Ok((master, slave))
+}
+
+/// The job of an orphan adopted by the daemon, from its JOB_ENV variable, its group or its session.
+///
+/// An orphan that cleared its env and left the job's session belongs to the last job started before it.
+fn adopted_owner(st: &ProcStat, registry: &Registry) -> Option<u64> {
+    let env = std::fs::read(format!("/proc/{}/environ", st.pid)).unwrap_or_default();
+    let prefix = format!("{JOB_ENV}=");
+    if let Some(cookie) = env
+        .split(|b| *b == 0)
+        .find_map(|var| var.strip_prefix(prefix.as_bytes()))
+    {
+        return registry
+            .leaders
+            .values()
+            .find(|leader| leader.cookie.as_bytes() == cookie)
+            .map(|leader| leader.id);
+    }
+    if let Some(leader) = registry
+        .leaders
+        .iter()
+        .find(|(pid, _)| **pid == st.pgrp || **pid == st.session)
+    {
+        return Some(leader.1.id);
+    }
+    registry
+        .leaders
+        .values()
+        .filter(|leader| leader.start <= st.start)
+        .max_by_key(|leader| leader.start)
+        .map(|leader| leader.id)
+}
+
+/// The fields of /proc/<pid>/stat used to find the processes of a job
+struct ProcStat {
+    pid: libc::pid_t,
+    state: char,
+    ppid: libc::pid_t,
+    pgrp: libc::pid_t,
+    session: libc::pid_t,
+    /// Clock ticks since boot
+    start: u64,
+}
+
+impl ProcStat {
+    /// Zombies have exited and only wait to be reaped
+    fn is_running(&self) -> bool {
+        !matches!(self.state, 'Z' | 'X')
+    }
+}
+
+/// Every process of the system, by pid
+fn proc_table() -> HashMap<libc::pid_t, ProcStat> {
+    let Ok(dir) = std::fs::read_dir("/proc") else {
+        return HashMap::new();
+    };
+    dir.filter_map(|e| e.ok()?.file_name().to_str()?.parse::<libc::pid_t>().ok())
+        .filter_map(|pid| Some((pid, proc_stat(pid)?)))
+        .collect()
+}
+
+fn proc_stat(pid: libc::pid_t) -> Option<ProcStat> {
+    let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    // The process name can contain spaces and parentheses, the fields come after the last ')'
+    let (_, rest) = stat.rsplit_once(')')?;
+    let fields = rest.split_whitespace().collect::<Vec<&str>>();
+    // Fields 3 (state), 4 (ppid), 5 (pgrp), 6 (session) and 22 (starttime) of proc(5)
+    Some(ProcStat {
+        pid,
+        state: fields.first()?.chars().next()?,
+        ppid: fields.get(1)?.parse().ok()?,
+        pgrp: fields.get(2)?.parse().ok()?,
+        session: fields.get(3)?.parse().ok()?,
+        start: fields.get(19)?.parse().ok()?,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_job_terminate_kills_descendants() -> anyhow::Result<()> {
+        let mut cmd = Command::new("sh");
+        // The leader ignores SIGTERM, a grandchild escapes the process group,
+        // and another one clears its env, leaves the session and gets orphaned
+        cmd.args([
+            "-c",
+            "trap '' TERM; setsid sleep 30 & env -i setsid sh -c 'sleep 30 & exit'; sleep 30",
+        ]);
+        let CmdResult::Ok(mut job) = Job::spawn(cmd, Duration::from_millis(200)) else {
+            anyhow::bail!("Could not spawn the job");
+        };
+        let start = Instant::now();
+        let mut seen = job.running();
+        while seen.len() < 3 && start.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(Duration::from_millis(20));
+            seen = job.running();
+        }
+        assert_eq!(seen.len(), 3, "{seen:?}");
+        let adopted = seen
+            .iter()
+            .filter(|&&pid| {
+                proc_stat(pid).is_some_and(|st| st.ppid == std::process::id() as libc::pid_t)
+            })
+            .count();
+        assert_eq!(adopted, 1);
+
+        // Started after the job, but not a part of it
+        let mut other = Command::new("sleep").arg("30").spawn()?;
+
+        job.terminate();
+        assert!(job.child.try_wait()?.is_some());
+        assert!(job.running().is_empty());
+        assert!(job.known().is_empty());
+        // Killed and reaped, not left as zombies
+        assert!(seen.iter().all(|&pid| proc_stat(pid).is_none()));
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert!(other.try_wait()?.is_none());
+        other.kill()?;
+        other.wait()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_job_on_pty() -> anyhow::Result<()> {
+        let mut cmd = Command::new("sh");
+        cmd.args([
+            "-c",
+            "test -t 1 && printf '\\033[31mtty\\033[0m\\n'; stty size < /dev/tty; echo $TERM",
+        ]);
+        let size = PtySize {
+            cols: 120,
+            rows: 40,
+        };
+        let CmdResult::Ok((mut job, mut pty)) =
+            Job::spawn_pty(cmd, Duration::from_millis(200), size)
+        else {
+            anyhow::bail!("Could not spawn the job");
+        };
+        let mut out = String::new();
+        pty.read_to_string(&mut out)?;
+        assert!(job.child.wait()?.success());
+        assert_eq!(out, format!("\x1b[31mtty\x1b[0m\n40 120\n{PTY_TERM}\n"));
+        Ok(())
+    }
 }
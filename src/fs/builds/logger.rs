@@ -1,39 +1,126 @@
 use std::{
     collections::VecDeque,
     fmt::Display,
-    io::{BufRead, BufReader},
+    io::{BufRead, BufReader, Read},
     ops::Deref,
     path::Path,
     process::{Command, ExitStatus},
-    time::{SystemTime, UNIX_EPOCH},
+    sync::OnceLock,
+    thread::JoinHandle,
+    time::{Duration, Instant},
 };
 
+use crossbeam_channel::RecvTimeoutError;
+
 use crate::fs::builds::{
+    chase::AnsiMode,
+    chase_env::ChaseEnv,
+    chase_log::LogStream,
     chase_runner::ChaseRunner,
     job::Job,
     reporter::{Reporter, Updater},
-    runtime::ChaseStopMode,
+    runtime::{ChaseCommand, ChaseStopMode},
 };
 
-/// A single line of output from either stdout or stderr with the timestamp in micros
+static LOG_CLOCK: OnceLock<Instant> = OnceLock::new();
+
+/// Default size cap for a single line of command output
+pub const DEFAULT_MAX_LINE: usize = 64 * 1024;
+
+/// Monotonic timestamp in micros, since the first line was logged
+pub fn monotonic_micros() -> u128 {
+    LOG_CLOCK.get_or_init(Instant::now).elapsed().as_micros()
+}
+
+/// A single line of output from either stdout or stderr with the monotonic timestamp in micros
 #[derive(Debug, Clone)]
 pub struct LogLine {
     pub t_stmp: u128,
+    pub stream: LogStream,
     pub line: Vec<u8>,
 }
 
 impl LogLine {
-    pub fn new(line: &[u8]) -> Self {
-        let stamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_micros();
-
+    pub fn new(line: &[u8], stream: LogStream) -> Self {
         Self {
-            t_stmp: stamp,
+            t_stmp: monotonic_micros(),
+            stream,
             line: Vec::from(line),
         }
     }
+
+    /// A line written by guse itself
+    pub fn guse(line: &[u8]) -> Self {
+        Self::new(line, LogStream::Guse)
+    }
+}
+
+/// Reads a line of raw bytes, keeping at most `cap` bytes of it.
+///
+/// The rest of the line is dropped and a marker is written in its place.
+/// Secrets are masked before the line is cut, so the start of a secret cut by the cap is hidden too.
+/// Returns the number of bytes read, 0 at EOF.
+pub fn read_line_capped<R: BufRead>(
+    reader: &mut R,
+    buf: &mut Vec<u8>,
+    cap: usize,
+    env: &ChaseEnv,
+) -> std::io::Result<usize> {
+    let cap_ahead = cap.saturating_add(env.lookahead());
+    let mut read = 0;
+    let mut dropped = 0;
+    let mut newline = false;
+    while !newline {
+        let available = reader.fill_buf()?;
+        if available.is_empty() {
+            break;
+        }
+        let content = match available.iter().position(|b| *b == b'\n') {
+            Some(i) => {
+                newline = true;
+                &available[..i]
+            }
+            None => available,
+        };
+        let keep = content.len().min(cap_ahead.saturating_sub(buf.len()));
+        buf.extend_from_slice(&content[..keep]);
+        dropped += content.len() - keep;
+        let used = content.len() + newline as usize;
+        reader.consume(used);
+        read += used;
+    }
+    if let Some(masked) = env.mask(buf) {
+        *buf = masked;
+    }
+    if buf.len() > cap {
+        dropped += buf.len() - cap;
+        buf.truncate(cap);
+    }
+    if dropped > 0 {
+        buf.extend_from_slice(format!(" [... {dropped} bytes cut]").as_bytes());
+    }
+    if newline {
+        buf.push(b'\n');
+    }
+    Ok(read)
+}
+
+/// Command output is not always valid UTF-8. Only the text shown to the user is decoded.
+pub fn display_line(line: &[u8], ansi: AnsiMode) -> String {
+    let text = String::from_utf8_lossy(line);
+    match ansi {
+        AnsiMode::Keep => text.into_owned(),
+        AnsiMode::Strip => {
+            // Progress bars redraw their line with \r, only the last state is kept
+            let body = text.trim_end_matches(['\r', '\n']);
+            let last = match body.rsplit_once('\r') {
+                Some((_, last)) if text.ends_with('\n') => format!("{last}\n"),
+                Some((_, last)) => last.to_string(),
+                None => text.into_owned(),
+            };
+            strip_ansi_escapes::strip_str(&last)
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -87,86 +174,219 @@ impl<T> CmdResult<T> {
         Ok(self)
     }
 
-    fn is_err(&self) -> bool {
+    pub fn is_err(&self) -> bool {
         matches!(self, Self::Err(_)) || matches!(self, Self::ExitFail(_))
     }
 }
 
+/// How the runner treats a job
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobMode {
+    /// A chase command. Anything it leaves running is killed when it exits.
+    Command,
+    /// A setup step. What it leaves running (a local database...) is kept until the teardown.
+    Setup,
+    /// A teardown step. It is not interrupted when the chase is stopped.
+    Teardown,
+}
+
+/// The result of a job and the output it printed
+pub struct JobOutput {
+    pub result: CmdResult<()>,
+    pub lines: Vec<LogLine>,
+    /// Setup steps that left processes running
+    pub kept: Option<KeptJob>,
+}
+
+/// Processes left running by a setup step, with the threads still reading their output
+pub struct KeptJob {
+    job: Job,
+    readers: Vec<JoinHandle<()>>,
+}
+
+impl KeptJob {
+    /// Terminates the processes, then waits for the readers to see the end of the output
+    pub fn terminate(mut self) {
+        self.job.terminate();
+        for reader in self.readers {
+            let _ = reader.join();
+        }
+    }
+}
+
+impl JobOutput {
+    fn failed(error: String) -> Self {
+        Self {
+            result: CmdResult::Err(error),
+            lines: Vec::new(),
+            kept: None,
+        }
+    }
+}
+
+/// `<shell> -c <command>` for the commands ran through a shell, the split command otherwise
+fn build_command(command: &ChaseCommand) -> Result<Command, String> {
+    match &command.shell {
+        None => {
+            let Ok(parts) = shell_words::split(&command.command) else {
+                return Err("Error parsing command.\n".to_string());
+            };
+            let Some((prog, args)) = parts.split_first() else {
+                return Err(format!(
+                    "Could not parse chase command: {}",
+                    command.command
+                ));
+            };
+            let mut cmd = Command::new(prog);
+            cmd.args(args);
+            Ok(cmd)
+        }
+        Some(shell) => {
+            let mut cmd = Command::new(shell);
+            cmd.arg("-c").arg(&command.command);
+            Ok(cmd)
+        }
+    }
+}
+
 impl<'a, R: Updater> ChaseRunner<'a, R> {
-    pub fn run_command_on_snap(&mut self, path: &Path, command: &str) -> CmdResult<()> {
-        let parts = match shell_words::split(command) {
-            Ok(p) => p,
-            Err(_) => return CmdResult::Err("Error parsing command.\n".to_string()),
+    pub fn run_command_on_snap(&mut self, path: &Path, command: &ChaseCommand) -> JobOutput {
+        self.run_job(path, command, JobMode::Command)
+    }
+
+    /// Runs a command in `path`, or in its cwd relative to `path`.
+    ///
+    /// For setup steps, the job is returned when it left processes running. They are terminated with it.
+    pub fn run_job(&mut self, path: &Path, command: &ChaseCommand, mode: JobMode) -> JobOutput {
+        let mut output = self.run_job_unmasked(path, command, mode);
+        // The errors end up in the report and the record
+        if let CmdResult::Err(e) = &output.result {
+            output.result = CmdResult::Err(self.chase.env.mask_str(e));
+        }
+        output
+    }
+
+    fn run_job_unmasked(
+        &mut self,
+        path: &Path,
+        command: &ChaseCommand,
+        mode: JobMode,
+    ) -> JobOutput {
+        let mut cmd = match build_command(command) {
+            Ok(cmd) => cmd,
+            Err(e) => return JobOutput::failed(e),
         };
-        let Some((prog, args)) = parts.split_first() else {
-            return CmdResult::Err(format!("Could not parse chase command: {command}"));
+        let dir = match &command.cwd {
+            Some(cwd) => path.join(cwd),
+            None => path.to_path_buf(),
         };
-        let mut command = Command::new(prog);
-        command.current_dir(path).args(args);
+        if !dir.is_dir() {
+            return JobOutput::failed(format!("Working directory {} not found\n", dir.display()));
+        }
+        cmd.current_dir(dir)
+            .envs(self.chase.env.vars().iter().map(|(k, v)| (k, v)))
+            .envs(self.curr_envs.iter().map(|(k, v)| (k, v)));
 
         // Spawn a child process for this cli command
-        let mut job = match Job::spawn(command) {
+        let grace = Duration::from_secs(self.chase.args.kill_grace);
+        let spawned = match command.pty {
+            Some(size) => match Job::spawn_pty(cmd, grace, size) {
+                CmdResult::Ok((job, pty)) => CmdResult::Ok((
+                    job,
+                    vec![(Box::new(pty) as Box<dyn Read + Send>, LogStream::Stdout)],
+                )),
+                CmdResult::Err(e) => CmdResult::Err(e),
+                CmdResult::ExitFail(e) => CmdResult::ExitFail(e),
+            },
+            None => match Job::spawn(cmd, grace) {
+                CmdResult::Ok(mut job) => {
+                    let Some(out) = job.child.stdout.take() else {
+                        return JobOutput::failed("Could not read stdout.\n".to_string());
+                    };
+                    let Some(err) = job.child.stderr.take() else {
+                        return JobOutput::failed("Could not read stderr.\n".to_string());
+                    };
+                    CmdResult::Ok((
+                        job,
+                        vec![
+                            (Box::new(out) as Box<dyn Read + Send>, LogStream::Stdout),
+                            (Box::new(err), LogStream::Stderr),
+                        ],
+                    ))
+                }
+                CmdResult::Err(e) => CmdResult::Err(e),
+                CmdResult::ExitFail(e) => CmdResult::ExitFail(e),
+            },
+        };
+        let (mut job, pipes) = match spawned {
             CmdResult::Ok(val) => val,
-            CmdResult::Err(e) => return CmdResult::Err(e),
-            CmdResult::ExitFail(e) => return CmdResult::ExitFail(e),
+            CmdResult::Err(e) => return JobOutput::failed(e),
+            CmdResult::ExitFail(e) => {
+                return JobOutput {
+                    result: CmdResult::ExitFail(e),
+                    lines: Vec::new(),
+                    kept: None,
+                };
+            }
         };
 
         let mut out_lines = Vec::new();
 
-        let Some(out) = job.child.stdout.take() else {
-            return CmdResult::Err("Could not read stdout.\n".to_string());
-        };
-        let Some(err) = job.child.stderr.take() else {
-            return CmdResult::Err("Could not read stderr.\n".to_string());
-        };
-
         let (tx, rx) = crossbeam_channel::unbounded::<LogLine>();
+        let max_line = self.chase.args.max_line;
 
-        let mut interrupted = false;
-
-        std::thread::scope(|s| {
-            {
+        // Not scoped, the processes kept by a setup step can hold the pipes until the teardown
+        let readers = pipes
+            .into_iter()
+            .map(|(pipe, stream)| {
                 let tx = tx.clone();
-                s.spawn(move || {
-                    let mut reader = BufReader::new(out);
+                // Secrets never reach the log file or the cli
+                let env = self.chase.env.clone();
+                std::thread::spawn(move || {
+                    let mut reader = BufReader::new(pipe);
                     let mut buf = Vec::new();
-                    while reader.read_until(b'\n', &mut buf).unwrap_or(0) != 0 {
-                        let line = LogLine::new(&buf);
+                    while read_line_capped(&mut reader, &mut buf, max_line, &env).unwrap_or(0) != 0
+                    {
+                        let line = LogLine::new(&buf, stream);
                         let _ = tx.send(line);
                         buf.clear();
                     }
-                });
-            }
-            {
-                let tx = tx.clone();
-                s.spawn(move || {
-                    let mut reader = BufReader::new(err);
-                    let mut buf = Vec::new();
-                    while reader.read_until(b'\n', &mut buf).unwrap_or(0) != 0 {
-                        let line = LogLine::new(&buf);
-                        let _ = tx.send(line);
-                        buf.clear();
+                })
+            })
+            .collect::<Vec<_>>();
+        drop(tx);
+
+        let mut interrupted = false;
+        loop {
+            match rx.recv_timeout(Duration::from_millis(100)) {
+                Ok(line) => {
+                    let _ = self.log(&line);
+                    out_lines.push(line);
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    job.track();
+                    // The command exited, but something it started still holds the pipes open
+                    if matches!(job.child.try_wait(), Ok(Some(_))) {
+                        if mode == JobMode::Setup {
+                            break;
+                        }
+                        job.terminate();
                     }
-                });
-            }
-            drop(tx);
-
-            while let Ok(line) = rx.recv() {
-                out_lines.push(line.clone());
-                let _ = self.report(str::from_utf8(&line.line).unwrap());
-                let status = self
-                    .handle
-                    .stop_flag
-                    .load(std::sync::atomic::Ordering::Relaxed);
-                if status {
-                    job.terminate();
-                    interrupted = true;
-                    return;
-                };
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
             }
-        });
+            let status = self
+                .handle
+                .stop_flag
+                .load(std::sync::atomic::Ordering::Relaxed);
+            if status && mode != JobMode::Teardown {
+                job.terminate();
+                interrupted = true;
+                break;
+            };
+        }
 
-        if !interrupted {
+        let res = if !interrupted {
             match job.child.wait() {
                 Ok(a) if a.success() => CmdResult::Ok(()),
                 Ok(a) => CmdResult::ExitFail(a),
@@ -174,6 +394,24 @@ impl<'a, R: Updater> ChaseRunner<'a, R> {
             }
         } else {
             CmdResult::Err("Terminated by ctrl+c signal\n".to_string())
+        };
+        // Whether the pipes are still open or not, a daemon can close or redirect them
+        if mode == JobMode::Setup && !res.is_err() && job.has_running() {
+            return JobOutput {
+                result: res,
+                lines: out_lines,
+                kept: Some(KeptJob { job, readers }),
+            };
+        }
+        // Kill anything the command left running
+        job.terminate();
+        for reader in readers {
+            let _ = reader.join();
+        }
+        JobOutput {
+            result: res,
+            lines: out_lines,
+            kept: None,
         }
     }
 }
@@ -209,3 +447,94 @@ impl<T> Deref for RingBuffer<T> {
         &self.inner
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fs::builds::{
+        chase_env::ENV_FILE,
+        chase_log::{ChaseLog, read_log},
+        runtime::DEFAULT_SHELL,
+    };
+
+    #[test]
+    fn test_binary_and_long_lines() -> anyhow::Result<()> {
+        let mut output = b"bad \xff\xfe utf8 \x1b[31mred\x1b[0m\n".to_vec();
+        output.extend(std::iter::repeat_n(b'x', 10_000));
+        output.extend_from_slice(b"\nlast without newline");
+        let mut reader = BufReader::with_capacity(64, output.as_slice());
+
+        let mut lines = Vec::new();
+        let mut buf = Vec::new();
+        while read_line_capped(&mut reader, &mut buf, 100, &ChaseEnv::default())? != 0 {
+            lines.push(buf.clone());
+            buf.clear();
+        }
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], b"bad \xff\xfe utf8 \x1b[31mred\x1b[0m\n");
+        assert!(lines[1].starts_with(&[b'x'; 100]));
+        assert!(lines[1].ends_with(b" [... 9900 bytes cut]\n"));
+        assert_eq!(lines[2], b"last without newline");
+
+        assert_eq!(
+            display_line(&lines[0], AnsiMode::Strip),
+            "bad \u{fffd}\u{fffd} utf8 red\n"
+        );
+        assert!(display_line(&lines[0], AnsiMode::Keep).contains("\x1b[31m"));
+        assert_eq!(
+            display_line(
+                b"\x1b[32m 10%\r\x1b[32m 55%\r100%\x1b[0m\n",
+                AnsiMode::Strip
+            ),
+            "100%\n"
+        );
+
+        // The raw bytes reach the log file untouched
+        let dir = tempfile::tempdir()?;
+        let mut log = ChaseLog::create(dir.path(), "01_abcdef1", 1024 * 1024)?;
+        for line in &lines {
+            log.write(&LogLine::new(line, LogStream::Stdout))?;
+        }
+        let stored = read_log(&log.finish()?)?;
+        assert_eq!(stored[0].line, lines[0]);
+        assert_eq!(stored[1].line, lines[1]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_secret_cut_by_cap() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join(ENV_FILE), "API_TOKEN=abcdefgh\n")?;
+        let env = ChaseEnv::load(dir.path())?;
+
+        let mut reader = BufReader::new(&b"0123456789abcdefgh tail\n"[..]);
+        let mut buf = Vec::new();
+        read_line_capped(&mut reader, &mut buf, 13, &env)?;
+        assert_eq!(buf, b"0123456789*** [... 5 bytes cut]\n");
+
+        let mut reader = BufReader::new(&b"abcdefgh\n"[..]);
+        buf.clear();
+        read_line_capped(&mut reader, &mut buf, 100, &env)?;
+        assert_eq!(buf, b"***\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_command_shell() -> anyhow::Result<()> {
+        let output = |shell: Option<&str>| -> anyhow::Result<String> {
+            let mut command = ChaseCommand::default();
+            command.command = "echo a | tr a b && echo $((1 + 1))".to_string();
+            command.shell = shell.map(str::to_string);
+            let mut cmd = build_command(&command).map_err(anyhow::Error::msg)?;
+            Ok(String::from_utf8(cmd.output()?.stdout)?)
+        };
+        assert_eq!(output(Some(DEFAULT_SHELL))?, "b\n2\n");
+        // Without a shell, the pipe and && are arguments of echo
+        assert_eq!(output(None)?, "a | tr a b && echo $((1 + 1))\n");
+
+        let mut unclosed = ChaseCommand::default();
+        unclosed.command = "echo 'a".to_string();
+        assert!(build_command(&unclosed).is_err());
+        Ok(())
+    }
+}
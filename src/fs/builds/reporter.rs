@@ -8,7 +8,10 @@ use std::{
 use anyhow::bail;
 
 use crate::{
-    fs::builds::{chase_runner::ChaseRunner, logger::LogLine},
+    fs::builds::{
+        chase_runner::ChaseRunner,
+        logger::{LogLine, display_line},
+    },
     internals::sock::ControlRes,
 };
 
@@ -18,9 +21,15 @@ pub enum ChaseError {
     ParsingMisc { msg: String },
     ScriptNotFound { path: PathBuf },
     LuaError { source: mlua::Error, msg: String },
+    LuaLimit { msg: String },
     NoCommits,
     NoCommands,
     BadInputType { input: String, oid: String },
+    BadRepoName { name: String },
+    DuplicateRepo { name: String },
+    BadDate { date: String },
+    BadCwd { command: String, cwd: PathBuf },
+    BadArtifact { path: PathBuf },
 }
 
 pub type GuseGitResult<T> = core::result::Result<T, ChaseGitError>;
@@ -57,6 +66,16 @@ pub enum ChaseGitError {
         oid1: String,
         oid2: String,
     },
+    RepoNotFound {
+        repo: String,
+    },
+    // Pr = "all" or PrMerge = "all" matched nothing
+    NoOpenPrs,
+    // No commit in the secondary repo is older than the chased commit
+    NoPinnedCommit {
+        repo: String,
+        commit: String,
+    },
 }
 
 pub type GuseFsResult<T> = core::result::Result<T, ChaseFsError>;
@@ -74,6 +93,8 @@ pub trait Reporter {
     ///
     /// For strictly sending cli updates, use the Updater trait
     fn report(&mut self, log: &str) -> anyhow::Result<()>;
+    /// Sends a line of command output to cli and optionally to disk, keeping its stream
+    fn log(&mut self, line: &LogLine) -> anyhow::Result<()>;
 }
 
 impl<'a, R: Updater> Reporter for ChaseRunner<'a, R> {
@@ -81,15 +102,25 @@ impl<'a, R: Updater> Reporter for ChaseRunner<'a, R> {
     ///
     /// It will write to a log file, and the log file only exists when the chase started for that commit.
     fn report(&mut self, log: &str) -> anyhow::Result<()> {
-        if let Some(file_ref) = &self.curr_log_file
-            && let Ok(mut file) = file_ref.try_clone()
-        {
+        let log = self.chase.env.mask_str(log);
+        let log = log.as_str();
+        if let Some(chase_log) = &mut self.curr_log {
             let stripped = strip_ansi_escapes::strip_str(log);
-            let _ = file.write_all(stripped.as_bytes());
+            let _ = chase_log.write(&LogLine::guse(stripped.as_bytes()));
         }
         self.reporter.update(log)?;
         Ok(())
     }
+
+    fn log(&mut self, line: &LogLine) -> anyhow::Result<()> {
+        // Masked when read, see read_line_capped
+        if let Some(chase_log) = &mut self.curr_log {
+            let _ = chase_log.write(line);
+        }
+        self.reporter
+            .update(&display_line(&line.line, self.chase.args.ansi))?;
+        Ok(())
+    }
 }
 
 pub trait Updater {
@@ -136,6 +167,10 @@ impl<T> ErrorResolver<T> for GuseResult<T> {
                     stream.update(&prepare_lua_error(&source, &msg))?;
                     bail!("")
                 }
+                ChaseError::LuaLimit { msg } => {
+                    stream.update(&prepare_lua_limit_error(&msg))?;
+                    bail!("")
+                }
                 ChaseError::ParsingMisc { msg: _ } => {
                     // TODO. What was this for anyway?
                     bail!("")
@@ -159,6 +194,35 @@ impl<T> ErrorResolver<T> for GuseResult<T> {
                     )?;
                     bail!("")
                 }
+                ChaseError::BadRepoName { name } => {
+                    stream.update(&format!("Bad name {name:?} for a secondary repo.\n"))?;
+                    stream.update("Names can only contain letters, numbers and \"_\".\n")?;
+                    bail!("")
+                }
+                ChaseError::BadDate { date } => {
+                    stream.update(&format!("Incorrect date {date:?}.\n"))?;
+                    stream.update("Please input dates as \"YYYY-MM-DD\" or RFC 3339.\n")?;
+                    bail!("")
+                }
+                ChaseError::DuplicateRepo { name } => {
+                    stream.update(&format!("Secondary repo {name:?} was added twice.\n"))?;
+                    bail!("")
+                }
+                ChaseError::BadCwd { command, cwd } => {
+                    stream.update(&format!(
+                        "Bad cwd {} for command {command:?}.\n",
+                        cwd.display()
+                    ))?;
+                    stream.update("The cwd must be a relative path, without \"..\".\n")?;
+                    bail!("")
+                }
+                ChaseError::BadArtifact { path } => {
+                    stream.update(&format!("Bad artifact {}.\n", path.display()))?;
+                    stream.update(
+                        "Artifacts must be relative to the Snap folder, without \"..\".\n",
+                    )?;
+                    bail!("")
+                }
             },
         }
     }
@@ -171,6 +235,12 @@ fn prepare_lua_error(source: &mlua::Error, msg: &str) -> String {
     format!("{}\n{} {}\n", error, source, msg)
 }
 
+/// ChaseError::LuaLimit
+fn prepare_lua_limit_error(msg: &str) -> String {
+    let error = color_red("The chase script was stopped.");
+    format!("{error}\nThe script {msg}.\n")
+}
+
 /// ChaseError::ScriptNotFound
 fn prepare_not_found_error(path: &Path) -> String {
     let text1 = white_underline("Could not find the script as path:");
@@ -227,6 +297,25 @@ impl<T> ErrorResolver<T> for GuseGitResult<T> {
                     ))?;
                     bail!("")
                 }
+                ChaseGitError::RepoNotFound { repo } => {
+                    stream.update(&format!(
+                        "Secondary repo {repo:?} does not exist. Please check correct spelling.\n"
+                    ))?;
+                    bail!("")
+                }
+                ChaseGitError::NoOpenPrs => {
+                    stream.update("No open pull requests matched the PR filters.\n")?;
+                    stream.update(
+                        "Run guse repo update to refresh the pull requests of the repo.\n",
+                    )?;
+                    bail!("")
+                }
+                ChaseGitError::NoPinnedCommit { repo, commit } => {
+                    stream.update(&format!(
+                        "No commit found in repo {repo} before the date of commit {commit}.\n"
+                    ))?;
+                    bail!("")
+                }
             },
         }
     }
@@ -255,7 +344,7 @@ fn prepare_git_error(msg: &str, source: git2::Error) -> String {
 
 /// ChaseGitError::NoRefKindsFound
 fn prepare_norefs_error() -> String {
-    "Snap folders not found on disk. Try restarting the session".to_string()
+    "The refs of the repo could not be read. Try restarting the session".to_string()
 }
 
 /// ChaseGitError::RefKindNotFound
@@ -1,19 +1,42 @@
 use std::{
     path::{Path, PathBuf},
-    sync::Arc,
+    sync::{
+        Arc, OnceLock,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    time::Instant,
 };
 
-use mlua::Lua;
+use mlua::{HookTriggers, Lua, LuaOptions, StdLib, VmState};
 use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
 
-use crate::fs::builds::reporter::{ChaseError, GuseResult};
+use crate::fs::builds::{
+    chase_hook::ChaseHooks,
+    chase_live::LiveMode,
+    chase_repro::{ReproCheck, ReproMode},
+    chase_tests::ResultParser,
+    chase_watch::WatchRule,
+    reporter::{ChaseError, GuseResult},
+};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum InputTypes {
     Commit,
     Range,
     Pr,
+    /// The merge commit of a PR
+    PrMerge,
     Branch,
+    Tag,
+    /// Any git revspec resolving to a commit, like main~10 or v1.2^{commit}
+    Rev,
+    /// The commits made between two dates, on a branch (HEAD if not set)
+    Window {
+        since: Option<String>,
+        until: Option<String>,
+        branch: Option<String>,
+    },
     Unknown(String),
 }
 
@@ -22,12 +45,29 @@ impl InputTypes {
         match itype.to_lowercase().as_str() {
             "range" => Self::Range,
             "pr" => Self::Pr,
+            "prmerge" => Self::PrMerge,
             "branch" => Self::Branch,
             "commit" => Self::Commit,
+            "tag" => Self::Tag,
+            "rev" => Self::Rev,
             _ => Self::Unknown(itype.to_string()),
         }
     }
 
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Commit => "Commit",
+            Self::Range => "Range",
+            Self::Pr => "Pr",
+            Self::PrMerge => "PrMerge",
+            Self::Branch => "Branch",
+            Self::Tag => "Tag",
+            Self::Rev => "Rev",
+            Self::Window { .. } => "Window",
+            Self::Unknown(input) => input,
+        }
+    }
+
     fn is_unknown(&self) -> bool {
         matches!(self, Self::Unknown(_))
     }
@@ -40,7 +80,7 @@ impl InputTypes {
     }
 }
 
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub enum ChaseRunMode {
     #[default]
     Continuous,
@@ -57,7 +97,7 @@ impl ChaseRunMode {
     }
 }
 
-#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ChaseStopMode {
     #[default]
     Continuous,
@@ -74,21 +114,611 @@ impl ChaseStopMode {
     }
 }
 
-#[derive(Default, Debug)]
+/// Shell used by the commands set to run through a shell
+pub const DEFAULT_SHELL: &str = "/bin/sh";
+
+/// A command ran on every commit
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "CommandRecord")]
+pub struct ChaseCommand {
+    pub command: String,
+    /// Executed with `<shell> -c <command>` when set, so pipes, && and redirections work.
+    /// Split with shell_words and executed directly otherwise.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shell: Option<String>,
+    /// Folder the command runs in, relative to the Snap folder
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<PathBuf>,
+    /// Reads the results of single tests from the output
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parser: Option<ResultParser>,
+    /// Runs the command on a pseudo-terminal instead of pipes, stdout and stderr are merged
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pty: Option<PtySize>,
+    /// `shell` option of the script. Resolved with cfg.set_shell once the script ran.
+    #[serde(skip)]
+    shell_opt: Option<bool>,
+}
+
+/// Window size of the pseudo-terminal of a command
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PtySize {
+    pub cols: u16,
+    pub rows: u16,
+}
+
+impl Default for PtySize {
+    fn default() -> Self {
+        Self { cols: 80, rows: 24 }
+    }
+}
+
+impl PtySize {
+    /// `pty = true` for the default size, or `pty = {cols = 120, rows = 40}`
+    fn from_opts(opts: &mlua::Table) -> mlua::Result<Option<Self>> {
+        match opts.get::<mlua::Value>("pty")? {
+            mlua::Value::Nil | mlua::Value::Boolean(false) => Ok(None),
+            mlua::Value::Boolean(true) => Ok(Some(Self::default())),
+            mlua::Value::Table(size) => {
+                let default = Self::default();
+                let size = Self {
+                    cols: size.get::<Option<u16>>("cols")?.unwrap_or(default.cols),
+                    rows: size.get::<Option<u16>>("rows")?.unwrap_or(default.rows),
+                };
+                if size.cols == 0 || size.rows == 0 {
+                    return Err(mlua::Error::RuntimeError(
+                        "pty cols and rows must be above 0".to_string(),
+                    ));
+                }
+                Ok(Some(size))
+            }
+            _ => Err(mlua::Error::RuntimeError(
+                "pty takes true or a table with cols and rows".to_string(),
+            )),
+        }
+    }
+}
+
+/// Older chase records saved the direct commands as plain strings
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum CommandRecord {
+    Direct(String),
+    Full {
+        command: String,
+        #[serde(default)]
+        shell: Option<String>,
+        #[serde(default)]
+        cwd: Option<PathBuf>,
+        #[serde(default)]
+        parser: Option<ResultParser>,
+        #[serde(default)]
+        pty: Option<PtySize>,
+    },
+}
+
+impl From<CommandRecord> for ChaseCommand {
+    fn from(value: CommandRecord) -> Self {
+        match value {
+            CommandRecord::Direct(command) => Self {
+                command,
+                ..Self::default()
+            },
+            CommandRecord::Full {
+                command,
+                shell,
+                cwd,
+                parser,
+                pty,
+            } => Self {
+                command,
+                shell,
+                cwd,
+                parser,
+                pty,
+                shell_opt: None,
+            },
+        }
+    }
+}
+
+impl ChaseCommand {
+    pub fn as_str(&self) -> &str {
+        &self.command
+    }
+
+    /// The cwd must stay inside the folder the command runs in
+    fn has_valid_cwd(&self) -> bool {
+        self.cwd.as_deref().is_none_or(is_inside)
+    }
+}
+
+/// A relative path without "..", so it can't leave the folder it is joined to
+fn is_inside(path: &Path) -> bool {
+    path.components().all(|c| {
+        matches!(
+            c,
+            std::path::Component::Normal(_) | std::path::Component::CurDir
+        )
+    })
+}
+
+/// Commands ran around the chase commands. The teardown steps always run, even after a failure or a cancel.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ChaseSteps {
+    /// Ran in the Snap folder, before the commands of each commit
+    pub setup: Vec<ChaseCommand>,
+    /// Ran in the Snap folder, after the commands of each commit
+    pub teardown: Vec<ChaseCommand>,
+    /// Ran in the script folder, before the first commit
+    pub chase_setup: Vec<ChaseCommand>,
+    /// Ran in the script folder, after the last commit
+    pub chase_teardown: Vec<ChaseCommand>,
+}
+
+impl ChaseSteps {
+    fn all(&self) -> impl Iterator<Item = &ChaseCommand> {
+        self.setup
+            .iter()
+            .chain(&self.teardown)
+            .chain(&self.chase_setup)
+            .chain(&self.chase_teardown)
+    }
+
+    fn all_mut(&mut self) -> impl Iterator<Item = &mut ChaseCommand> {
+        self.setup
+            .iter_mut()
+            .chain(&mut self.teardown)
+            .chain(&mut self.chase_setup)
+            .chain(&mut self.chase_teardown)
+    }
+}
+
+/// The order in which the commits are chased
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChaseOrder {
+    /// The order of the inputs in the script
+    #[default]
+    AsGiven,
+    /// Parents before children
+    Topo,
+    /// Oldest commit time first
+    Date,
+    /// The order of the inputs, reversed
+    Reverse,
+}
+
+impl ChaseOrder {
+    fn from_str(order: &str) -> Option<Self> {
+        match order.to_lowercase().as_str() {
+            "as_given" => Some(Self::AsGiven),
+            "topo" => Some(Self::Topo),
+            "date" => Some(Self::Date),
+            "reverse" => Some(Self::Reverse),
+            _ => None,
+        }
+    }
+}
+
+/// Keeps only the first or last N commits, after they are ordered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommitLimit {
+    First(usize),
+    Last(usize),
+}
+
+impl CommitLimit {
+    fn from_str(kind: &str, n: usize) -> Option<Self> {
+        match kind.to_lowercase().as_str() {
+            "first" => Some(Self::First(n)),
+            "last" => Some(Self::Last(n)),
+            _ => None,
+        }
+    }
+
+    pub fn apply<T>(&self, items: &mut Vec<T>) {
+        match *self {
+            Self::First(n) => items.truncate(n),
+            Self::Last(n) => {
+                let skip = items.len().saturating_sub(n);
+                items.drain(..skip);
+            }
+        }
+    }
+}
+
+/// Selects the open PRs chased with `{Pr = "all"}` or `{PrMerge = "all"}`
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PrFilter {
+    /// Lowest PR number
+    pub min: Option<u64>,
+    /// Only the PRs with a commit since this date, or in the last N days ("14d")
+    pub since: Option<String>,
+}
+
+impl PrFilter {
+    /// `changed` is the time of the latest commit of the PR
+    pub fn matches(&self, number: &str, changed: i64) -> bool {
+        if let Some(min) = self.min
+            && number.parse::<u64>().is_ok_and(|n| n < min)
+        {
+            return false;
+        }
+        match self.since.as_deref().and_then(parse_since) {
+            Some(since) => changed >= since,
+            None => true,
+        }
+    }
+}
+
+/// A date, or a number of days before now ("14d")
+pub fn parse_since(since: &str) -> Option<i64> {
+    if let Some(days) = since.strip_suffix('d')
+        && let Ok(days) = days.parse::<u32>()
+    {
+        let secs = i64::from(days).checked_mul(24 * 60 * 60)?;
+        return chrono::offset::Utc::now().timestamp().checked_sub(secs);
+    }
+    parse_date(since)
+}
+
+/// Parses a date as YYYY-MM-DD (UTC midnight) or RFC 3339. Returns the unix timestamp.
+pub fn parse_date(date: &str) -> Option<i64> {
+    if let Ok(day) = chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+        return Some(day.and_hms_opt(0, 0, 0)?.and_utc().timestamp());
+    }
+    chrono::DateTime::parse_from_rfc3339(date)
+        .ok()
+        .map(|d| d.timestamp())
+}
+
+/// How the commit of a secondary repo is chosen for each commit in the chase
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RepoPin {
+    /// The same commit is used for every commit in the chase
+    Commit(String),
+    /// The latest commit before the date of the chased commit, on the given branch.
+    ///
+    /// Without a branch, main is used, then HEAD when the repo has no main branch.
+    Before(Option<String>),
+}
+
+impl RepoPin {
+    fn from_str(pin: &str) -> Self {
+        let lower = pin.to_lowercase();
+        if lower == "before" {
+            return Self::Before(None);
+        }
+        match lower.strip_prefix("before:") {
+            Some(_) => Self::Before(Some(pin["before:".len()..].to_string())),
+            None => Self::Commit(pin.to_string()),
+        }
+    }
+}
+
+/// A secondary repo declared by the script with cfg.add_repo
+#[derive(Debug, Clone)]
+pub struct RepoInput {
+    /// Used to name the env variable holding the path: GUSE_REPO_<NAME>
+    pub name: String,
+    /// Name of the repo, as found in the mount point
+    pub repo: String,
+    pub pin: RepoPin,
+}
+
+/// Default cap for the number of Lua instructions a script can run
+pub const DEFAULT_LUA_INSTRUCTIONS: u64 = 100_000_000;
+/// Default cap for the memory used by a script
+pub const DEFAULT_LUA_MEMORY: usize = 64 * 1024 * 1024;
+/// The instruction count is checked once every this many instructions
+const HOOK_STEP: u32 = 10_000;
+
+/// Set once, from the `guse run` arguments
+pub static LUA_SANDBOX: OnceLock<LuaSandbox> = OnceLock::new();
+
+/// Restrictions for the Lua scripts, so a script cannot take over or hang the daemon
+///
+/// Scripts only get the table, string, math, utf8 and coroutine libraries.
+#[derive(Debug, Clone)]
+pub struct LuaSandbox {
+    /// Extra libraries made available: os, io, package
+    pub allow: Vec<String>,
+    pub max_instructions: u64,
+    pub max_memory: usize,
+}
+
+impl Default for LuaSandbox {
+    fn default() -> Self {
+        Self {
+            allow: Vec::new(),
+            max_instructions: DEFAULT_LUA_INSTRUCTIONS,
+            max_memory: DEFAULT_LUA_MEMORY,
+        }
+    }
+}
+
+impl LuaSandbox {
+    pub fn current() -> Self {
+        LUA_SANDBOX.get().cloned().unwrap_or_default()
+    }
+
+    fn libs(&self) -> StdLib {
+        let mut libs =
+            StdLib::TABLE | StdLib::STRING | StdLib::MATH | StdLib::UTF8 | StdLib::COROUTINE;
+        for lib in &self.allow {
+            match lib.as_str() {
+                "os" => libs |= StdLib::OS,
+                "io" => libs |= StdLib::IO,
+                "package" => libs |= StdLib::PACKAGE,
+                _ => {}
+            }
+        }
+        libs
+    }
+
+    /// Creates the Lua state for a script, with the limits installed
+    ///
+    /// The flag is set when the script is stopped for going over the instruction limit
+    pub(crate) fn new_lua(&self) -> GuseResult<(Lua, Arc<AtomicBool>)> {
+        self.new_lua_until(None)
+    }
+
+    /// Same as `new_lua`, and the script is also stopped once past the deadline
+    pub(crate) fn new_lua_until(
+        &self,
+        deadline: Option<Instant>,
+    ) -> GuseResult<(Lua, Arc<AtomicBool>)> {
+        let lua = Lua::new_with(self.libs(), LuaOptions::default()).map_err(|e| {
+            ChaseError::LuaError {
+                source: e,
+                msg: "Could not create the Lua state".to_string(),
+            }
+        })?;
+        // The base library can still read files
+        if !self.allow.iter().any(|lib| lib == "io") {
+            for name in ["dofile", "loadfile"] {
+                lua.globals()
+                    .set(name, mlua::Value::Nil)
+                    .map_err(|e| ChaseError::LuaError {
+                        source: e,
+                        msg: format!("Could not remove {name}"),
+                    })?;
+            }
+        }
+        lua.set_memory_limit(self.max_memory)
+            .map_err(|e| ChaseError::LuaError {
+                source: e,
+                msg: "Could not set the memory limit".to_string(),
+            })?;
+
+        let over_limit = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&over_limit);
+        let count = AtomicU64::new(0);
+        let max = self.max_instructions;
+        lua.set_hook(
+            HookTriggers::new().every_nth_instruction(HOOK_STEP),
+            move |_, _| {
+                if count.fetch_add(HOOK_STEP as u64, Ordering::Relaxed) >= max {
+                    flag.store(true, Ordering::Relaxed);
+                    return Err(mlua::Error::RuntimeError(
+                        "instruction limit reached".to_string(),
+                    ));
+                }
+                if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                    return Err(mlua::Error::RuntimeError("time budget reached".to_string()));
+                }
+                Ok(VmState::Continue)
+            },
+        )
+        .map_err(|e| ChaseError::LuaError {
+            source: e,
+            msg: "Could not set the instruction limit".to_string(),
+        })?;
+        Ok((lua, over_limit))
+    }
+
+    /// Turns the errors caused by the limits into a clear message
+    pub(crate) fn check_limits(&self, error: mlua::Error, over_limit: &AtomicBool) -> ChaseError {
+        if over_limit.load(Ordering::Relaxed) {
+            return ChaseError::LuaLimit {
+                msg: format!(
+                    "went over the limit of {} instructions (guse run --lua-max-instructions)",
+                    self.max_instructions
+                ),
+            };
+        }
+        let out_of_memory = error
+            .chain()
+            .any(|e| matches!(e.downcast_ref(), Some(mlua::Error::MemoryError(_))));
+        if out_of_memory {
+            return ChaseError::LuaLimit {
+                msg: format!(
+                    "went over the memory limit of {} MB (guse run --lua-max-memory)",
+                    self.max_memory / (1024 * 1024)
+                ),
+            };
+        }
+        ChaseError::LuaError {
+            source: error,
+            msg: "Error running exec on cfg table.".to_string(),
+        }
+    }
+}
+
+/// The result of the chunks ran in the Lua console
+#[derive(Debug)]
+pub struct LuaEval {
+    pub cfg: LuaConfig,
+    /// Printed lines and returned values of the last chunk
+    pub output: Vec<String>,
+    /// The last chunk changed the inputs or how they resolve
+    pub inputs_changed: bool,
+}
+
+/// Formats a value returned in the console. Nested tables are shown up to a few levels.
+fn show_value(value: &mlua::Value, depth: usize) -> String {
+    match value {
+        mlua::Value::String(s) => format!("{:?}", s.to_string_lossy()),
+        mlua::Value::Table(t) if depth < 3 => {
+            let len = t.raw_len();
+            let mut items = (1..=len)
+                .filter_map(|i| t.raw_get::<mlua::Value>(i).ok())
+                .map(|v| show_value(&v, depth + 1))
+                .collect::<Vec<String>>();
+            for (k, v) in t.pairs::<mlua::Value, mlua::Value>().flatten() {
+                if let mlua::Value::Integer(i) = k
+                    && i >= 1
+                    && i as usize <= len
+                {
+                    continue;
+                }
+                let key = match &k {
+                    mlua::Value::String(s) => s.to_string_lossy(),
+                    other => format!("[{}]", show_value(other, depth + 1)),
+                };
+                items.push(format!("{key} = {}", show_value(&v, depth + 1)));
+            }
+            format!("{{{}}}", items.join(", "))
+        }
+        other => other.to_string().unwrap_or_else(|_| format!("{other:?}")),
+    }
+}
+
+/// A console line that is not a complete chunk yet, like the first line of a function
+pub fn is_incomplete_chunk(chunk: &str) -> bool {
+    let lua = Lua::new_with(StdLib::NONE, LuaOptions::default());
+    let Ok(lua) = lua else {
+        return false;
+    };
+    matches!(
+        lua.load(chunk).into_function(),
+        Err(mlua::Error::SyntaxError {
+            incomplete_input: true,
+            ..
+        })
+    )
+}
+
+#[derive(Debug, Default)]
 pub struct LuaConfig {
     pub commits: Vec<(InputTypes, String)>,
-    pub commands: Vec<String>,
+    pub order: ChaseOrder,
+    // Ranges and date windows only follow the first parent of merge commits
+    pub first_parent: bool,
+    pub limit: Option<CommitLimit>,
+    pub pr_filter: PrFilter,
+    pub commands: Vec<ChaseCommand>,
+    // The commands run through this shell, unless they opt out
+    pub shell: Option<String>,
+    pub steps: ChaseSteps,
     pub run_mode: ChaseRunMode,
     pub stop_mode: ChaseStopMode,
     pub patches: Vec<(PathBuf, String)>,
+    pub repos: Vec<RepoInput>,
+    pub hooks: ChaseHooks,
+    // New commits on these refs start the chase after a fetch
+    pub watch: Vec<WatchRule>,
+    pub repro: Option<ReproCheck>,
+    // The live folder is chased after the commits
+    pub live: Option<LiveMode>,
 }
 
 impl LuaConfig {
     pub fn read_lua(path: &Path) -> GuseResult<Self> {
-        let lua = Lua::new();
+        Self::read_lua_sandboxed(path, &LuaSandbox::current())
+    }
+
+    pub fn read_lua_sandboxed(path: &Path, sandbox: &LuaSandbox) -> GuseResult<Self> {
         let script_path = path.join("chase.lua");
         let lua_src = std::fs::read_to_string(&script_path)
             .map_err(|_| ChaseError::ScriptNotFound { path: script_path })?;
+        let config = Self::load_sandboxed(sandbox, |lua, _, over_limit| {
+            lua.load(&lua_src)
+                .set_name("chase.lua")
+                .exec()
+                .map_err(|e| sandbox.check_limits(e, over_limit))
+        })?;
+        config.check_config_fields()?;
+        Ok(config)
+    }
+
+    /// Evaluates the chunks of the Lua console, in the same runtime as the scripts.
+    ///
+    /// The previous chunks are ran again to rebuild the state, only the output of the last one is kept.
+    /// `setup` adds the console globals before the first chunk.
+    pub fn eval_console<F>(chunks: &[String], sandbox: &LuaSandbox, setup: F) -> GuseResult<LuaEval>
+    where
+        F: FnOnce(&Lua) -> mlua::Result<()>,
+    {
+        let output = Arc::new(Mutex::new(Vec::<String>::new()));
+        let mut inputs_changed = false;
+        let cfg = Self::load_sandboxed(sandbox, |lua, lua_config, over_limit| {
+            let console_err = |e: mlua::Error| ChaseError::LuaError {
+                source: e,
+                msg: "Could not set up the console".to_string(),
+            };
+            let print_ref = Arc::clone(&output);
+            let print = lua
+                .create_function(move |_, values: mlua::MultiValue| {
+                    let line = values
+                        .iter()
+                        .map(|v| v.to_string())
+                        .collect::<mlua::Result<Vec<String>>>()?;
+                    print_ref.lock().push(line.join("\t"));
+                    Ok(())
+                })
+                .map_err(console_err)?;
+            lua.globals().set("print", print).map_err(console_err)?;
+            setup(lua).map_err(console_err)?;
+
+            for (i, chunk) in chunks.iter().enumerate() {
+                let last = i + 1 == chunks.len();
+                if last {
+                    output.lock().clear();
+                }
+                let before = lua_config.lock().inputs_key();
+                let name = format!("console:{}", i + 1);
+                // Like the lua interpreter, try the chunk as an expression first
+                let func = match lua
+                    .load(format!("return {chunk}"))
+                    .set_name(&name)
+                    .into_function()
+                {
+                    Ok(func) => func,
+                    Err(_) => lua
+                        .load(chunk)
+                        .set_name(&name)
+                        .into_function()
+                        .map_err(|e| sandbox.check_limits(e, over_limit))?,
+                };
+                let values = func
+                    .call::<mlua::MultiValue>(())
+                    .map_err(|e| sandbox.check_limits(e, over_limit))?;
+                if last {
+                    inputs_changed = before != lua_config.lock().inputs_key();
+                    let mut output = output.lock();
+                    for value in values.iter() {
+                        output.push(show_value(value, 0));
+                    }
+                }
+            }
+            Ok(())
+        })?;
+        let output = std::mem::take(&mut *output.lock());
+        Ok(LuaEval {
+            cfg,
+            output,
+            inputs_changed,
+        })
+    }
+
+    /// Creates the Lua state with the cfg table, and runs `run` in it
+    fn load_sandboxed<F>(sandbox: &LuaSandbox, run: F) -> GuseResult<Self>
+    where
+        F: FnOnce(&Lua, &Arc<Mutex<LuaConfig>>, &AtomicBool) -> GuseResult<()>,
+    {
+        let (lua, over_limit) = sandbox.new_lua()?;
         let globals = lua.globals();
 
         let lua_config = Arc::new(Mutex::new(LuaConfig::default()));
@@ -119,14 +749,280 @@ impl LuaConfig {
                     })?;
             }
 
+            // SET_ORDER
+            {
+                let order_ref = Arc::clone(&lua_config);
+                let set_order = scope
+                    .create_function(move |_, order: String| {
+                        let Some(chase_order) = ChaseOrder::from_str(&order) else {
+                            return Err(mlua::Error::RuntimeError(format!(
+                                "Unknown order {order}. Use as_given, topo, date or reverse"
+                            )));
+                        };
+                        order_ref.lock().order = chase_order;
+                        Ok(())
+                    })
+                    .map_err(|e| ChaseError::LuaError {
+                        source: e,
+                        msg: "Could not create set_order function".to_string(),
+                    })?;
+                cfg.set("set_order", set_order)
+                    .map_err(|e| ChaseError::LuaError {
+                        source: e,
+                        msg: "Error setting cfg table: ".to_string(),
+                    })?;
+            }
+
+            // ADD_WINDOW
+            {
+                let window_ref = Arc::clone(&lua_config);
+                let add_window = scope
+                    .create_function(
+                        move |_,
+                              (since, until, branch): (
+                            Option<String>,
+                            Option<String>,
+                            Option<String>,
+                        )| {
+                            let label = format!(
+                                "{}..{} on {}",
+                                since.as_deref().unwrap_or(""),
+                                until.as_deref().unwrap_or(""),
+                                branch.as_deref().unwrap_or("HEAD")
+                            );
+                            let window = InputTypes::Window {
+                                since,
+                                until,
+                                branch,
+                            };
+                            window_ref.lock().commits.push((window, label));
+                            Ok(())
+                        },
+                    )
+                    .map_err(|e| ChaseError::LuaError {
+                        source: e,
+                        msg: "Could not create add_window function".to_string(),
+                    })?;
+                cfg.set("add_window", add_window)
+                    .map_err(|e| ChaseError::LuaError {
+                        source: e,
+                        msg: "Error setting cfg table: ".to_string(),
+                    })?;
+            }
+
+            // SET_LIMIT
+            {
+                let limit_ref = Arc::clone(&lua_config);
+                let set_limit = scope
+                    .create_function(move |_, (kind, n): (String, usize)| {
+                        let Some(limit) = CommitLimit::from_str(&kind, n) else {
+                            return Err(mlua::Error::RuntimeError(format!(
+                                "Unknown limit {kind}. Use First or Last"
+                            )));
+                        };
+                        limit_ref.lock().limit = Some(limit);
+                        Ok(())
+                    })
+                    .map_err(|e| ChaseError::LuaError {
+                        source: e,
+                        msg: "Could not create set_limit function".to_string(),
+                    })?;
+                cfg.set("set_limit", set_limit)
+                    .map_err(|e| ChaseError::LuaError {
+                        source: e,
+                        msg: "Error setting cfg table: ".to_string(),
+                    })?;
+            }
+
+            // SET_LIVE
+            {
+                let live_ref = Arc::clone(&lua_config);
+                let set_live = scope
+                    .create_function(move |_, live: mlua::Value| {
+                        let mode = match &live {
+                            mlua::Value::Boolean(false) | mlua::Value::Nil => None,
+                            mlua::Value::Boolean(true) => Some(LiveMode::InPlace),
+                            mlua::Value::String(s) if s.to_str()?.eq_ignore_ascii_case("copy") => {
+                                Some(LiveMode::Copy)
+                            }
+                            _ => {
+                                return Err(mlua::Error::RuntimeError(
+                                    "Live takes true, false or \"copy\"".to_string(),
+                                ));
+                            }
+                        };
+                        live_ref.lock().live = mode;
+                        Ok(())
+                    })
+                    .map_err(|e| ChaseError::LuaError {
+                        source: e,
+                        msg: "Could not create set_live function".to_string(),
+                    })?;
+                cfg.set("set_live", set_live)
+                    .map_err(|e| ChaseError::LuaError {
+                        source: e,
+                        msg: "Error setting cfg table: ".to_string(),
+                    })?;
+            }
+
+            // SET_REPRO
+            {
+                let repro_ref = Arc::clone(&lua_config);
+                let set_repro = scope
+                    .create_function(move |_, (mode, artifacts): (String, Vec<String>)| {
+                        let Some(mode) = ReproMode::from_name(&mode) else {
+                            return Err(mlua::Error::RuntimeError(format!(
+                                "Unknown repro mode {mode}. Use Twice or Previous"
+                            )));
+                        };
+                        if artifacts.is_empty() {
+                            return Err(mlua::Error::RuntimeError(
+                                "set_repro needs at least one artifact".to_string(),
+                            ));
+                        }
+                        repro_ref.lock().repro = Some(ReproCheck {
+                            mode,
+                            artifacts: artifacts.into_iter().map(PathBuf::from).collect(),
+                        });
+                        Ok(())
+                    })
+                    .map_err(|e| ChaseError::LuaError {
+                        source: e,
+                        msg: "Could not create set_repro function".to_string(),
+                    })?;
+                cfg.set("set_repro", set_repro)
+                    .map_err(|e| ChaseError::LuaError {
+                        source: e,
+                        msg: "Error setting cfg table: ".to_string(),
+                    })?;
+            }
+
+            // SET_PR_FILTER
+            {
+                let filter_ref = Arc::clone(&lua_config);
+                let set_pr_filter = scope
+                    .create_function(move |_, (kind, value): (String, mlua::Value)| {
+                        let mut cfg = filter_ref.lock();
+                        match (kind.to_lowercase().as_str(), value) {
+                            ("prmin", mlua::Value::Integer(n)) if n >= 0 => {
+                                cfg.pr_filter.min = Some(n as u64)
+                            }
+                            ("prsince", mlua::Value::String(since)) => {
+                                cfg.pr_filter.since = Some(since.to_str()?.to_string())
+                            }
+                            _ => {
+                                return Err(mlua::Error::RuntimeError(format!(
+                                    "Unknown PR filter {kind}. Use PrMin = number or PrSince = date"
+                                )));
+                            }
+                        }
+                        Ok(())
+                    })
+                    .map_err(|e| ChaseError::LuaError {
+                        source: e,
+                        msg: "Could not create set_pr_filter function".to_string(),
+                    })?;
+                cfg.set("set_pr_filter", set_pr_filter)
+                    .map_err(|e| ChaseError::LuaError {
+                        source: e,
+                        msg: "Error setting cfg table: ".to_string(),
+                    })?;
+            }
+
+            // SET_FIRST_PARENT
+            {
+                let first_parent_ref = Arc::clone(&lua_config);
+                let set_first_parent = scope
+                    .create_function(move |_, first_parent: bool| {
+                        first_parent_ref.lock().first_parent = first_parent;
+                        Ok(())
+                    })
+                    .map_err(|e| ChaseError::LuaError {
+                        source: e,
+                        msg: "Could not create set_first_parent function".to_string(),
+                    })?;
+                cfg.set("set_first_parent", set_first_parent).map_err(|e| {
+                    ChaseError::LuaError {
+                        source: e,
+                        msg: "Error setting cfg table: ".to_string(),
+                    }
+                })?;
+            }
+
+            // SET_SHELL
+            {
+                let shell_ref = Arc::clone(&lua_config);
+                let set_shell = scope
+                    .create_function(move |_, shell: mlua::Value| {
+                        let shell = match shell {
+                            mlua::Value::Nil | mlua::Value::Boolean(false) => None,
+                            mlua::Value::Boolean(true) => Some(DEFAULT_SHELL.to_string()),
+                            mlua::Value::String(path) => Some(path.to_str()?.to_string()),
+                            _ => {
+                                return Err(mlua::Error::RuntimeError(
+                                    "shell must be true, false or the path to a shell".to_string(),
+                                ));
+                            }
+                        };
+                        shell_ref.lock().shell = shell;
+                        Ok(())
+                    })
+                    .map_err(|e| ChaseError::LuaError {
+                        source: e,
+                        msg: "Could not create set_shell function".to_string(),
+                    })?;
+                cfg.set("set_shell", set_shell)
+                    .map_err(|e| ChaseError::LuaError {
+                        source: e,
+                        msg: "Error setting cfg table: ".to_string(),
+                    })?;
+            }
+
             // ADD_COMMANDS
             {
                 let commands_ref = Arc::clone(&lua_config);
                 let add_command = scope
-                    .create_function(move |_, command: String| {
-                        commands_ref.lock().commands.push(command);
-                        Ok(())
-                    })
+                    .create_function(
+                        move |_, (command, opts, cwd): (String, mlua::Value, Option<String>)| {
+                            // Either a table of options, or the shell and cwd
+                            let (shell, cwd, parser, pty) = match opts {
+                                mlua::Value::Table(opts) => {
+                                    let parser = match opts.get::<Option<String>>("parser")? {
+                                        Some(name) => {
+                                            let results = opts.get::<Option<String>>("results")?;
+                                            let parser = ResultParser::from_name(&name, results)
+                                                .ok_or_else(|| {
+                                                    mlua::Error::RuntimeError(format!(
+                                                        "unknown parser {name:?}, junit also needs the results path"
+                                                    ))
+                                                })?;
+                                            Some(parser)
+                                        }
+                                        None => None,
+                                    };
+                                    (
+                                        opts.get::<Option<bool>>("shell")?,
+                                        opts.get::<Option<String>>("cwd")?,
+                                        parser,
+                                        PtySize::from_opts(&opts)?,
+                                    )
+                                }
+                                mlua::Value::Boolean(shell) => (Some(shell), cwd, None, None),
+                                mlua::Value::Nil => (None, cwd, None, None),
+                                _ => {
+                                    return Err(mlua::Error::RuntimeError(
+                                        "add_command takes a table of options".to_string(),
+                                    ));
+                                }
+                            };
+                            let mut cfg = commands_ref.lock();
+                            let mut command = cfg.new_command(command, shell, cwd);
+                            command.parser = parser;
+                            command.pty = pty;
+                            cfg.commands.push(command);
+                            Ok(())
+                        },
+                    )
                     .map_err(|e| ChaseError::LuaError {
                         source: e,
                         msg: "Could not create add_command function: ".to_string(),
@@ -138,14 +1034,51 @@ impl LuaConfig {
                     })?;
             }
 
+            // SETUP / TEARDOWN
+            for (name, teardown) in [("setup", false), ("teardown", true)] {
+                let steps_ref = Arc::clone(&lua_config);
+                let add_step = scope
+                    .create_function(move |_, (command, opts): (String, Option<mlua::Table>)| {
+                        let (once, shell, cwd) = match opts {
+                            Some(opts) => (
+                                opts.get::<Option<bool>>("once")?.unwrap_or(false),
+                                opts.get::<Option<bool>>("shell")?,
+                                opts.get::<Option<String>>("cwd")?,
+                            ),
+                            None => (false, None, None),
+                        };
+                        let mut cfg = steps_ref.lock();
+                        let command = cfg.new_command(command, shell, cwd);
+                        let steps = match (once, teardown) {
+                            (false, false) => &mut cfg.steps.setup,
+                            (false, true) => &mut cfg.steps.teardown,
+                            (true, false) => &mut cfg.steps.chase_setup,
+                            (true, true) => &mut cfg.steps.chase_teardown,
+                        };
+                        steps.push(command);
+                        Ok(())
+                    })
+                    .map_err(|e| ChaseError::LuaError {
+                        source: e,
+                        msg: format!("Could not create {name} function"),
+                    })?;
+                cfg.set(name, add_step).map_err(|e| ChaseError::LuaError {
+                    source: e,
+                    msg: "Error setting cfg table: ".to_string(),
+                })?;
+            }
+
             // RUN_MODE
             {
                 let run_mode_ref = Arc::clone(&lua_config);
                 let set_run_mode = scope
                     .create_function(move |_, run_mode: String| {
-                        let chase_run_mode = ChaseRunMode::from_str(&run_mode);
-                        let run_opt = chase_run_mode.unwrap_or_default();
-                        run_mode_ref.lock().run_mode = run_opt;
+                        let Some(chase_run_mode) = ChaseRunMode::from_str(&run_mode) else {
+                            return Err(mlua::Error::RuntimeError(format!(
+                                "Unknown run mode {run_mode}. Use Continuous or Binary"
+                            )));
+                        };
+                        run_mode_ref.lock().run_mode = chase_run_mode;
                         Ok(())
                     })
                     .map_err(|e| ChaseError::LuaError {
@@ -202,18 +1135,101 @@ impl LuaConfig {
                     })?;
             }
 
+            // ADD_REPO
+            {
+                let repos_ref = Arc::clone(&lua_config);
+                let add_repo = scope
+                    .create_function(move |_, (name, repo, pin): (String, String, String)| {
+                        repos_ref.lock().repos.push(RepoInput {
+                            name,
+                            repo,
+                            pin: RepoPin::from_str(&pin),
+                        });
+                        Ok(())
+                    })
+                    .map_err(|e| ChaseError::LuaError {
+                        source: e,
+                        msg: "Could not create add_repo function".to_string(),
+                    })?;
+                cfg.set("add_repo", add_repo)
+                    .map_err(|e| ChaseError::LuaError {
+                        source: e,
+                        msg: "Error setting cfg table: ".to_string(),
+                    })?;
+            }
+
+            // ADD_HOOK
+            {
+                let hooks_ref = Arc::clone(&lua_config);
+                let add_hook = scope
+                    .create_function(move |_, command: String| {
+                        hooks_ref.lock().hooks.commands.push(command);
+                        Ok(())
+                    })
+                    .map_err(|e| ChaseError::LuaError {
+                        source: e,
+                        msg: "Could not create add_hook function".to_string(),
+                    })?;
+                cfg.set("add_hook", add_hook)
+                    .map_err(|e| ChaseError::LuaError {
+                        source: e,
+                        msg: "Error setting cfg table: ".to_string(),
+                    })?;
+            }
+
+            // ADD_WATCH
+            {
+                let watch_ref = Arc::clone(&lua_config);
+                let add_watch = scope
+                    .create_function(move |_, (kind, name): (String, String)| {
+                        let Some(rule) = WatchRule::from_str(&kind, &name) else {
+                            return Err(mlua::Error::RuntimeError(format!(
+                                "Unknown watch {kind}. Use Branch, Pr, PrMerge or Tag"
+                            )));
+                        };
+                        watch_ref.lock().watch.push(rule);
+                        Ok(())
+                    })
+                    .map_err(|e| ChaseError::LuaError {
+                        source: e,
+                        msg: "Could not create add_watch function".to_string(),
+                    })?;
+                cfg.set("add_watch", add_watch)
+                    .map_err(|e| ChaseError::LuaError {
+                        source: e,
+                        msg: "Error setting cfg table: ".to_string(),
+                    })?;
+            }
+
+            // ADD_NOTIFY_FILE
+            {
+                let notify_ref = Arc::clone(&lua_config);
+                let add_notify_file = scope
+                    .create_function(move |_, path: String| {
+                        notify_ref
+                            .lock()
+                            .hooks
+                            .notify_files
+                            .push(PathBuf::from(path));
+                        Ok(())
+                    })
+                    .map_err(|e| ChaseError::LuaError {
+                        source: e,
+                        msg: "Could not create add_notify_file function".to_string(),
+                    })?;
+                cfg.set("add_notify_file", add_notify_file)
+                    .map_err(|e| ChaseError::LuaError {
+                        source: e,
+                        msg: "Error setting cfg table: ".to_string(),
+                    })?;
+            }
+
             globals.set("cfg", cfg).map_err(|e| ChaseError::LuaError {
                 source: e,
                 msg: "Error setting cfg table: ".to_string(),
             })?;
 
-            lua.load(&lua_src)
-                .set_name("chase.lua")
-                .exec()
-                .map_err(|e| ChaseError::LuaError {
-                    source: e,
-                    msg: "Error running exec on cfg table.".to_string(),
-                })?;
+            run(&lua, &lua_config, &over_limit)?;
 
             globals
                 .set("cfg", mlua::Value::Nil)
@@ -235,11 +1251,55 @@ impl LuaConfig {
             msg: "Could not run lua GC: ".to_string(),
         })?;
 
-        let config = Arc::try_unwrap(lua_config).unwrap().into_inner();
-        config.check_config_fields()?;
+        let mut config = Arc::try_unwrap(lua_config).unwrap().into_inner();
+        config.resolve_shells();
         Ok(config)
     }
 
+    /// The fields deciding which commits the inputs resolve to
+    fn inputs_key(&self) -> String {
+        format!(
+            "{:?}",
+            (
+                &self.commits,
+                &self.order,
+                self.first_parent,
+                &self.limit,
+                &self.pr_filter,
+                &self.live,
+            )
+        )
+    }
+
+    /// The shell is set once the script ran, see `resolve_shells`
+    fn new_command(
+        &self,
+        command: String,
+        shell: Option<bool>,
+        cwd: Option<String>,
+    ) -> ChaseCommand {
+        ChaseCommand {
+            command,
+            cwd: cwd.map(PathBuf::from),
+            shell_opt: shell,
+            ..ChaseCommand::default()
+        }
+    }
+
+    /// Commands run through the shell set with cfg.set_shell, unless their `shell` option says otherwise.
+    ///
+    /// Done after the script, so cfg.set_shell applies to every command wherever it is called.
+    fn resolve_shells(&mut self) {
+        let shell = self.shell.clone();
+        for command in self.commands.iter_mut().chain(self.steps.all_mut()) {
+            command.shell = match command.shell_opt.take() {
+                None => shell.clone(),
+                Some(true) => Some(shell.clone().unwrap_or(DEFAULT_SHELL.to_string())),
+                Some(false) => None,
+            };
+        }
+    }
+
     fn check_config_fields(&self) -> GuseResult<()> {
         for (input, oid) in &self.commits {
             if input.is_unknown() {
@@ -249,9 +1309,60 @@ impl LuaConfig {
                     oid: oid.to_string(),
                 });
             }
+            if let InputTypes::Window { since, until, .. } = input {
+                for date in [since, until].into_iter().flatten() {
+                    if parse_date(date).is_none() {
+                        return Err(ChaseError::BadDate { date: date.clone() });
+                    }
+                }
+            }
+        }
+        if let Some(since) = &self.pr_filter.since
+            && parse_since(since).is_none()
+        {
+            return Err(ChaseError::BadDate {
+                date: since.clone(),
+            });
+        }
+
+        for (i, input) in self.repos.iter().enumerate() {
+            let valid_name = !input.name.is_empty()
+                && input
+                    .name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if !valid_name {
+                return Err(ChaseError::BadRepoName {
+                    name: input.name.clone(),
+                });
+            }
+            if self.repos[..i]
+                .iter()
+                .any(|r| r.name.eq_ignore_ascii_case(&input.name))
+            {
+                return Err(ChaseError::DuplicateRepo {
+                    name: input.name.clone(),
+                });
+            }
+        }
+
+        for command in self.commands.iter().chain(self.steps.all()) {
+            if !command.has_valid_cwd() {
+                return Err(ChaseError::BadCwd {
+                    command: command.command.clone(),
+                    cwd: command.cwd.clone().unwrap_or_default(),
+                });
+            }
         }
 
-        if self.commits.is_empty() {
+        if let Some(repro) = &self.repro
+            && let Some(path) = repro.artifacts.iter().find(|p| !is_inside(p))
+        {
+            return Err(ChaseError::BadArtifact { path: path.clone() });
+        }
+
+        // A script that only watches refs gets its commits from the fetch
+        if self.commits.is_empty() && self.watch.is_empty() && self.live.is_none() {
             return Err(ChaseError::NoCommits);
         }
         if self.commands.is_empty() {
@@ -260,3 +1371,211 @@ impl LuaConfig {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn run_script(body: &str, sandbox: &LuaSandbox) -> GuseResult<LuaConfig> {
+        let dir = tempfile::tempdir().unwrap();
+        let script =
+            format!("{body}\ncfg.add_commit(\"commit\", \"abc\")\ncfg.add_command(\"true\")\n");
+        std::fs::write(dir.path().join("chase.lua"), script).unwrap();
+        LuaConfig::read_lua_sandboxed(dir.path(), sandbox)
+    }
+
+    #[test]
+    fn test_lua_sandbox() {
+        let sandbox = LuaSandbox {
+            allow: Vec::new(),
+            max_instructions: 1_000_000,
+            max_memory: 8 * 1024 * 1024,
+        };
+        assert!(run_script("local x = string.rep('a', 10)", &sandbox).is_ok());
+        assert!(matches!(
+            run_script("os.execute('true')", &sandbox),
+            Err(ChaseError::LuaError { .. })
+        ));
+        assert!(matches!(
+            run_script("dofile('/etc/hostname')", &sandbox),
+            Err(ChaseError::LuaError { .. })
+        ));
+        let Err(ChaseError::LuaLimit { msg }) = run_script("while true do end", &sandbox) else {
+            panic!("The loop was not stopped");
+        };
+        assert!(msg.contains("instructions"));
+        let Err(ChaseError::LuaLimit { msg }) =
+            run_script("local s = string.rep('a', 100000000)", &sandbox)
+        else {
+            panic!("The memory limit was not enforced");
+        };
+        assert!(msg.contains("memory"));
+
+        let allowed = LuaSandbox {
+            allow: vec!["os".to_string()],
+            ..sandbox
+        };
+        assert!(run_script("local t = os.time()", &allowed).is_ok());
+    }
+
+    #[test]
+    fn test_steps_and_cwd() {
+        let sandbox = LuaSandbox::default();
+        let Ok(cfg) = run_script(
+            r#"
+            cfg.add_command("npm test", false, "frontend")
+            cfg.add_command("mvn test", {parser = "junit", results = "target/surefire-reports"})
+            cfg.add_command("cargo build", {pty = true})
+            cfg.add_command("pytest", {pty = {cols = 200}})
+            cfg.set_repro("twice", {"target/release/app"})
+            cfg.set_live("copy")
+            cfg.setup("make gen")
+            cfg.setup("./start_db.sh", {once = true, shell = false})
+            cfg.teardown("./stop_db.sh", {once = true, cwd = "scripts"})
+            cfg.set_shell(true)
+            "#,
+            &sandbox,
+        ) else {
+            panic!("The steps were not read");
+        };
+        assert_eq!(cfg.commands[0].cwd, Some(PathBuf::from("frontend")));
+        assert_eq!(cfg.commands[0].shell, None);
+        assert_eq!(
+            cfg.commands[1].parser,
+            Some(ResultParser::Junit {
+                results: PathBuf::from("target/surefire-reports")
+            })
+        );
+        assert_eq!(cfg.commands[1].shell.as_deref(), Some(DEFAULT_SHELL));
+        assert_eq!(cfg.commands[1].pty, None);
+        assert_eq!(cfg.commands[2].pty, Some(PtySize::default()));
+        assert_eq!(
+            cfg.commands[3].pty,
+            Some(PtySize {
+                cols: 200,
+                rows: 24
+            })
+        );
+        assert_eq!(cfg.steps.setup[0].shell.as_deref(), Some(DEFAULT_SHELL));
+        assert_eq!(cfg.steps.chase_setup[0].as_str(), "./start_db.sh");
+        assert_eq!(cfg.steps.chase_setup[0].shell, None);
+        assert_eq!(
+            cfg.steps.chase_teardown[0].cwd,
+            Some(PathBuf::from("scripts"))
+        );
+        assert!(cfg.steps.teardown.is_empty());
+        assert_eq!(
+            cfg.repro,
+            Some(ReproCheck {
+                mode: ReproMode::Twice,
+                artifacts: vec![PathBuf::from("target/release/app")]
+            })
+        );
+        assert_eq!(cfg.live, Some(LiveMode::Copy));
+        assert!(matches!(
+            run_script("cfg.set_live(\"yes\")", &sandbox),
+            Err(ChaseError::LuaError { .. })
+        ));
+        assert!(matches!(
+            run_script("cfg.set_repro(\"previous\", {\"../out\"})", &sandbox),
+            Err(ChaseError::BadArtifact { .. })
+        ));
+
+        for opts in [
+            "{parser = \"junit\"}",
+            "{pty = {rows = 0}}",
+            "{pty = \"yes\"}",
+        ] {
+            assert!(matches!(
+                run_script(&format!("cfg.add_command(\"true\", {opts})"), &sandbox),
+                Err(ChaseError::LuaError { .. })
+            ));
+        }
+        for cwd in ["..", "/tmp", "a/../../b"] {
+            assert!(matches!(
+                run_script(&format!("cfg.setup(\"true\", {{cwd = {cwd:?}}})"), &sandbox),
+                Err(ChaseError::BadCwd { .. })
+            ));
+        }
+
+        // Records saved before cwd was added
+        let old: Vec<ChaseCommand> =
+            serde_json::from_str(r#"["cargo test", {"command": "a | b", "shell": "/bin/sh"}]"#)
+                .unwrap();
+        assert_eq!(old[0].as_str(), "cargo test");
+        assert_eq!(old[1].shell.as_deref(), Some("/bin/sh"));
+        assert_eq!(old[1].cwd, None);
+    }
+
+    #[test]
+    fn test_pr_filter() {
+        let sandbox = LuaSandbox::default();
+        let Ok(cfg) = run_script(
+            r#"
+            cfg.add_commit("PrMerge", "all")
+            cfg.set_pr_filter("PrMin", 100)
+            cfg.set_pr_filter("PrSince", "2024-05-01")
+            "#,
+            &sandbox,
+        ) else {
+            panic!("The PR filter was not read");
+        };
+        assert!(matches!(cfg.commits[0].0, InputTypes::PrMerge));
+        let may = parse_date("2024-05-02").unwrap();
+        let april = parse_date("2024-04-30").unwrap();
+        assert!(cfg.pr_filter.matches("120", may));
+        assert!(!cfg.pr_filter.matches("99", may));
+        assert!(!cfg.pr_filter.matches("120", april));
+
+        let recent = PrFilter {
+            min: None,
+            since: Some("14d".to_string()),
+        };
+        let now = chrono::offset::Utc::now().timestamp();
+        assert!(recent.matches("1", now - 60));
+        assert!(!recent.matches("1", now - 15 * 24 * 60 * 60));
+        assert!(matches!(
+            run_script("cfg.set_pr_filter(\"PrSince\", \"last week\")", &sandbox),
+            Err(ChaseError::BadDate { .. })
+        ));
+        assert!(parse_since("-3d").is_none());
+        assert!(parse_since("99999999999999d").is_none());
+        assert!(parse_since(&format!("{}d", u32::MAX)).is_some_and(|t| t < now));
+    }
+
+    #[test]
+    fn test_commit_limit() {
+        let limited = |limit: CommitLimit| {
+            let mut items = vec![1, 2, 3, 4];
+            limit.apply(&mut items);
+            items
+        };
+        assert_eq!(limited(CommitLimit::First(2)), [1, 2]);
+        assert_eq!(limited(CommitLimit::Last(2)), [3, 4]);
+        assert_eq!(limited(CommitLimit::First(10)), [1, 2, 3, 4]);
+        assert_eq!(limited(CommitLimit::Last(10)), [1, 2, 3, 4]);
+        assert_eq!(limited(CommitLimit::Last(0)), Vec::<i32>::new());
+        assert_eq!(CommitLimit::from_str("LAST", 3), Some(CommitLimit::Last(3)));
+        assert_eq!(CommitLimit::from_str("middle", 3), None);
+    }
+
+    #[test]
+    fn test_unknown_order_and_run_mode() {
+        let sandbox = LuaSandbox::default();
+        let Ok(cfg) = run_script(
+            "cfg.set_order(\"TOPO\")\ncfg.set_run_mode(\"binary\")",
+            &sandbox,
+        ) else {
+            panic!("The script should load");
+        };
+        assert_eq!(cfg.order, ChaseOrder::Topo);
+        assert!(matches!(cfg.run_mode, ChaseRunMode::Binary));
+
+        for body in [
+            "cfg.set_order(\"topological\")",
+            "cfg.set_run_mode(\"bisect\")",
+        ] {
+            assert!(run_script(body, &sandbox).is_err(), "{body}");
+        }
+    }
+}
@@ -214,6 +214,11 @@ pub enum InoFlag {
     IndexFile = 1 << 21,
     VirIndexFile = 1 << 22,
     VirDotGitRoot = 1 << 23,
+    ChaseResultsRoot = 1 << 24,
+    ChaseResultFile = 1 << 25,
+    ChaseLogLink = 1 << 26,
+    CommitsRoot = 1 << 27,
+    ViewFolder = 1 << 28,
 }
 impl InoFlag {
     pub const fn as_str(&self) -> &'static str {
@@ -243,6 +248,14 @@ impl InoFlag {
             // Used for creating the .git folder and index inside a VDIR
             InoFlag::VirIndexFile => "VirIndexFile",
             InoFlag::VirDotGitRoot => "VirDotGitRoot",
+            // Used for the .chase folder inside a Snap folder
+            InoFlag::ChaseResultsRoot => "ChaseResultsRoot",
+            InoFlag::ChaseResultFile => "ChaseResultFile",
+            InoFlag::ChaseLogLink => "ChaseLogLink",
+            // Holds the Snap folders created on demand for commits outside the refs
+            InoFlag::CommitsRoot => "CommitsRoot",
+            // A folder defined in views.lua, holding the Snap folders returned by its rule
+            InoFlag::ViewFolder => "ViewFolder",
         }
     }
 }
@@ -281,6 +294,11 @@ impl TryFrom<u64> for InoFlag {
             x if x == InoFlag::IndexFile as u64 => Ok(InoFlag::IndexFile),
             x if x == InoFlag::VirIndexFile as u64 => Ok(InoFlag::VirIndexFile),
             x if x == InoFlag::VirDotGitRoot as u64 => Ok(InoFlag::VirDotGitRoot),
+            x if x == InoFlag::ChaseResultsRoot as u64 => Ok(InoFlag::ChaseResultsRoot),
+            x if x == InoFlag::ChaseResultFile as u64 => Ok(InoFlag::ChaseResultFile),
+            x if x == InoFlag::ChaseLogLink as u64 => Ok(InoFlag::ChaseLogLink),
+            x if x == InoFlag::CommitsRoot as u64 => Ok(InoFlag::CommitsRoot),
+            x if x == InoFlag::ViewFolder as u64 => Ok(InoFlag::ViewFolder),
             _ => {
                 bail!("Unknown InoFlag valueL {v:#x}")
             }
@@ -1,10 +1,10 @@
-use std::{ffi::OsStr, sync::Arc};
+use std::{ffi::OsStr, os::unix::ffi::OsStrExt, path::Path, sync::Arc};
 
 use crate::{
     fs::{
         GitFs, SourceTypes,
         builds::BuildOperationCtx,
-        fileattr::{FileAttr, InoFlag, StorageNode, file_attr},
+        fileattr::{FileAttr, FileType, InoFlag, StorageNode, file_attr},
     },
     inodes::NormalIno,
     mount::InvalMsg,
@@ -87,3 +87,36 @@ pub fn create_git(
     let fh = fs.open(ino, true, write, false)?;
     Ok((attr, fh))
 }
+
+pub fn symlink_git(
+    fs: &GitFs,
+    parent: NormalIno,
+    name: &OsStr,
+    target: &Path,
+) -> anyhow::Result<FileAttr> {
+    let ctx = BuildOperationCtx::new(fs, parent)?;
+
+    let link_path = ctx.path().join(name);
+    let ino = fs.next_inode_checked(parent.to_norm_u64())?;
+    let mut attr: FileAttr = file_attr(InoFlag::InsideBuild).into();
+    attr.ino = ino;
+    attr.kind = FileType::Symlink;
+    attr.perm = 0o777;
+    attr.size = target.as_os_str().as_bytes().len() as u64;
+    attr.oid = fs.get_oid_from_db(parent.into())?;
+
+    std::os::unix::fs::symlink(target, &link_path)?;
+
+    let nodes = vec![StorageNode {
+        parent_ino: parent.to_norm_u64(),
+        name: name.into(),
+        attr,
+    }];
+    fs.write_inodes_to_db(nodes)?;
+    let _ = fs.notifier.try_send(InvalMsg::Inode {
+        ino: parent.to_norm_u64(),
+        off: 0,
+        len: 0,
+    });
+    Ok(attr)
+}
@@ -1,7 +1,7 @@
 use anyhow::bail;
 
 use crate::{
-    fs::{FileAttr, GitFs, fileattr::InoFlag, meta_db::DbReturn},
+    fs::{FileAttr, GitFs, fileattr::InoFlag, meta_db::DbReturn, ops::readdir::chase_result_data},
     inodes::NormalIno,
 };
 
@@ -14,7 +14,7 @@ pub fn getattr_live_dir(fs: &GitFs, ino: NormalIno) -> anyhow::Result<FileAttr>
 }
 
 pub fn getattr_git_dir(fs: &GitFs, ino: NormalIno) -> anyhow::Result<FileAttr> {
-    let attr = match fs.get_metadata(ino.to_norm_u64())? {
+    let mut attr = match fs.get_metadata(ino.to_norm_u64())? {
         DbReturn::Found { value: a } => a,
         _ => bail!(std::io::Error::from_raw_os_error(libc::ENOENT)),
     };
@@ -24,5 +24,12 @@ pub fn getattr_git_dir(fs: &GitFs, ino: NormalIno) -> anyhow::Result<FileAttr> {
         // Walk all the folders inside and add entries to DB
         fs.cache_snap_readdir(target_ino, true)?;
     };
+    if matches!(
+        attr.ino_flag,
+        InoFlag::ChaseResultFile | InoFlag::ChaseLogLink
+    ) {
+        // A newer chase might have changed the result
+        attr.size = chase_result_data(fs, target_ino)?.len() as u64;
+    }
     Ok(attr)
 }
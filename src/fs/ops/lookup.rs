@@ -97,13 +97,20 @@ pub fn lookup_live(
 
 pub fn lookup_git(fs: &GitFs, parent: NormalIno, name: &OsStr) -> anyhow::Result<Option<FileAttr>> {
     match fs.get_metadata_by_name(parent, name)? {
-        DbReturn::Found { value } => Ok(Some(value)),
+        DbReturn::Found { value } => match value.ino_flag {
+            // Refresh the size, a newer chase might have changed the result
+            InoFlag::ChaseResultFile | InoFlag::ChaseLogLink => {
+                fs::ops::getattr::getattr_git_dir(fs, value.ino.into()).map(Some)
+            }
+            _ => Ok(Some(value)),
+        },
         DbReturn::Negative => Ok(None),
         DbReturn::Missing => {
             let p_flag = fs.get_ino_flag_from_db(parent)?;
             if p_flag == InoFlag::InsideSnap
                 || p_flag == InoFlag::InsideDotGit
                 || p_flag == InoFlag::HeadFile
+                || p_flag == InoFlag::ChaseResultsRoot
             {
                 fs::ops::readdir::readdir_git_dir(fs, parent)?;
                 match fs.get_metadata_by_name(parent, name)? {
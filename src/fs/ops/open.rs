@@ -14,7 +14,9 @@ use crate::{
         GitFs, Handle, SourceTypes, VFileEntry,
         builds::inject::InjectedMetadata,
         fileattr::{InoFlag, ObjectAttr},
-        ops::readdir::{DirCase, build_chase_path, build_dot_git_path, classify_inode},
+        ops::readdir::{
+            DirCase, build_chase_path, build_dot_git_path, chase_result_data, classify_inode,
+        },
         repo::{GitRepo, RefKind},
     },
     inodes::{Inodes, NormalIno, VirtualIno},
@@ -169,6 +171,18 @@ pub fn open_git(
             };
             fs.handles.open(handle)
         }
+        InoFlag::ChaseResultFile | InoFlag::ChaseLogLink => {
+            let file = SourceTypes::Blob {
+                oid: metadata.oid,
+                data: chase_result_data(fs, ino)?.into(),
+            };
+            let handle = Handle {
+                ino: ino.to_norm_u64(),
+                source: file,
+                write: false,
+            };
+            fs.handles.open(handle)
+        }
         InoFlag::InsideChase => {
             let file = {
                 let path = build_chase_path(fs, ino)?;
@@ -460,7 +474,7 @@ fn build_commits_text(
     ino: u64,
 ) -> anyhow::Result<Arc<[u8]>> {
     let mut contents: Vec<u8> = Vec::new();
-    entries.sort_unstable_by(|a, b| a.commit_time.cmp(&b.commit_time));
+    entries.sort_unstable_by_key(|a| a.commit_time);
 
     for e in entries {
         let ts = git_commit_time(e.commit_time);
@@ -10,12 +10,15 @@ use git2::{ObjectType, Oid};
 
 use crate::{
     fs::{
-        CHASE_FOLDER, FileAttr, GitFs, LIVE_FOLDER,
+        CHASE_FOLDER, COMMITS_FOLDER, FileAttr, GitFs, LIVE_FOLDER, RESULTS_FOLDER,
+        builds::chase_record::{RECORD_FILE, latest_commit_results},
         fileattr::{FileType, InoFlag, ObjectAttr, StorageNode, dir_attr, file_attr},
         meta_db::DbReturn,
         repo::git2time_to_system,
+        views::{view_names, view_snaps},
     },
     inodes::{Inodes, NormalIno, VirtualIno},
+    mount::InvalMsg,
     namespec,
 };
 
@@ -111,7 +114,7 @@ pub fn readdir_root_dir(fs: &GitFs) -> anyhow::Result<Vec<DirectoryEntry>> {
 }
 
 // We are in repo root. This should show:
-// . .. MONTH MONTH MONTH Tags Branches Pr Prmerge chase live
+// . .. MONTH MONTH MONTH Tags Branches Pr Prmerge Commits VIEWS chase live
 pub fn readdir_repo_dir(fs: &GitFs, parent: NormalIno) -> anyhow::Result<Vec<DirectoryEntry>> {
     let parent = parent.to_norm_u64();
     let repo_id = GitFs::ino_to_repo_id(parent);
@@ -199,6 +202,14 @@ pub fn readdir_repo_dir(fs: &GitFs, parent: NormalIno) -> anyhow::Result<Vec<Dir
             }
         }
     });
+    // Add the Commits folder, once a commit outside the refs was chased
+    if repo.with_ino_state(|s| !s.detached_snaps.is_empty()) {
+        folders.push((OsString::from(COMMITS_FOLDER), InoFlag::CommitsRoot));
+    }
+    // Add the folders defined in views.lua
+    for name in view_names(&repo) {
+        folders.push((OsString::from(name), InoFlag::ViewFolder));
+    }
     for (ref_name, flag) in folders {
         let dir_entry = match fs.exists_by_name(parent, &ref_name)? {
             DbReturn::Found { value: i } => {
@@ -579,6 +590,138 @@ fn dot_git_root(fs: &GitFs, parent_ino: u64) -> anyhow::Result<DirectoryEntry> {
     Ok(entry)
 }
 
+fn chase_results_root(fs: &GitFs, parent_ino: u64) -> anyhow::Result<DirectoryEntry> {
+    let name = OsStr::new(RESULTS_FOLDER);
+    let entry_ino = match fs.exists_by_name(parent_ino, name)? {
+        DbReturn::Found { value: ino } => ino,
+        DbReturn::Missing => {
+            let ino = fs.next_inode_checked(parent_ino)?;
+            let mut attr: FileAttr = dir_attr(InoFlag::ChaseResultsRoot).into();
+            attr.ino = ino;
+            attr.perm = 0o555;
+            let nodes: Vec<StorageNode> = vec![StorageNode {
+                parent_ino,
+                name: name.to_os_string(),
+                attr,
+            }];
+            fs.write_inodes_to_db(nodes)?;
+            ino
+        }
+        DbReturn::Negative => {
+            tracing::error!(".chase entry not found");
+            bail!(std::io::Error::from_raw_os_error(libc::ENOENT))
+        }
+    };
+    Ok(DirectoryEntry::new(
+        entry_ino,
+        Oid::zero(),
+        name.to_os_string(),
+        FileType::Directory,
+    ))
+}
+
+/// One read-only file per script, holding the latest result for the commit, and a symlink to its log
+///
+/// The results are read from the chase folder each time, so the sizes are refreshed here
+fn read_chase_results(fs: &GitFs, parent: NormalIno) -> anyhow::Result<Vec<DirectoryEntry>> {
+    let commit_oid = fs.get_parent_commit(parent.into())?;
+    let repo = fs.get_repo(parent.into())?;
+    let results = latest_commit_results(&repo.chase_dir, commit_oid).unwrap_or_default();
+
+    let mut entries: Vec<DirectoryEntry> = vec![];
+    let mut nodes: Vec<StorageNode> = vec![];
+    for res in results {
+        let mtime = std::fs::metadata(res.run_dir.join(RECORD_FILE))
+            .and_then(|m| m.modified())
+            .ok();
+        let mut files = vec![(
+            OsString::from(&res.script),
+            InoFlag::ChaseResultFile,
+            FileType::RegularFile,
+            res.summary().len() as u64,
+        )];
+        if let Some(log) = &res.log {
+            files.push((
+                OsString::from(format!("{}.log", res.script)),
+                InoFlag::ChaseLogLink,
+                FileType::Symlink,
+                log.as_os_str().len() as u64,
+            ));
+        }
+        for (name, ino_flag, kind, size) in files {
+            let ino = match fs.exists_by_name(parent.into(), &name)? {
+                DbReturn::Found { value: ino } => {
+                    fs.update_size_in_storage(ino.into(), size)?;
+                    ino
+                }
+                DbReturn::Missing => {
+                    let ino = fs.next_inode_checked(parent.into())?;
+                    let mut attr: FileAttr = file_attr(ino_flag).into();
+                    attr.ino = ino;
+                    attr.kind = kind;
+                    attr.size = size;
+                    attr.perm = if kind == FileType::Symlink {
+                        0o777
+                    } else {
+                        0o444
+                    };
+                    if let Some(mtime) = mtime {
+                        attr.mtime = mtime;
+                        attr.ctime = mtime;
+                    }
+                    nodes.push(StorageNode {
+                        parent_ino: parent.into(),
+                        name: name.clone(),
+                        attr,
+                    });
+                    ino
+                }
+                DbReturn::Negative => continue,
+            };
+            entries.push(DirectoryEntry::new(ino, Oid::zero(), name, kind));
+        }
+    }
+    fs.write_inodes_to_db(nodes)?;
+
+    // Entries of runs that were deleted since the last listing
+    for stale in fs.read_children(parent, false)? {
+        if entries.iter().any(|e| e.name == stale.name) {
+            continue;
+        }
+        fs.remove_db_dentry(parent, &stale.name)?;
+        let _ = fs.notifier.try_send(InvalMsg::Entry {
+            parent: parent.into(),
+            name: stale.name,
+        });
+    }
+    Ok(entries)
+}
+
+/// Contents of an entry in the .chase folder: the result summary, or the path of the log for the symlink
+pub fn chase_result_data(fs: &GitFs, ino: NormalIno) -> anyhow::Result<Vec<u8>> {
+    let metadata = fs.get_builctx_metadata(ino)?;
+    let commit_oid = fs.get_parent_commit(ino.into())?;
+    let repo = fs.get_repo(ino.into())?;
+    let name = metadata.name.to_string_lossy();
+    let script = match metadata.ino_flag {
+        InoFlag::ChaseLogLink => name.strip_suffix(".log").unwrap_or(&name),
+        _ => &name,
+    };
+    let results = latest_commit_results(&repo.chase_dir, commit_oid)?;
+    let Some(res) = results.into_iter().find(|r| r.script == script) else {
+        bail!(std::io::Error::from_raw_os_error(libc::ENOENT))
+    };
+    match metadata.ino_flag {
+        InoFlag::ChaseLogLink => {
+            let Some(log) = res.log else {
+                bail!(std::io::Error::from_raw_os_error(libc::ENOENT))
+            };
+            Ok(log.into_os_string().into_encoded_bytes())
+        }
+        _ => Ok(res.summary().into_bytes()),
+    }
+}
+
 pub fn readdir_git_dir(fs: &GitFs, parent: NormalIno) -> anyhow::Result<Vec<DirectoryEntry>> {
     let metadata = fs.get_builctx_metadata(parent)?;
     let repo = fs.get_repo(parent.into())?;
@@ -606,6 +749,14 @@ pub fn readdir_git_dir(fs: &GitFs, parent: NormalIno) -> anyhow::Result<Vec<Dire
             let objects = repo.non_branch_folders(metadata.ino_flag)?;
             objects_to_dir_entries(fs, parent, objects, InoFlag::SnapFolder)?
         }
+        InoFlag::CommitsRoot => {
+            let objects = repo.detached_snaps();
+            objects_to_dir_entries(fs, parent, objects, InoFlag::SnapFolder)?
+        }
+        InoFlag::ViewFolder => {
+            let objects = view_snaps(&repo, &metadata.name)?;
+            objects_to_dir_entries(fs, parent, objects, InoFlag::SnapFolder)?
+        }
         // Treat Branch folder separately
         // Try to find merge_base with main and list Snap folders if succesfull
         // If it fails, list everything in MONTH folders
@@ -624,6 +775,10 @@ pub fn readdir_git_dir(fs: &GitFs, parent: NormalIno) -> anyhow::Result<Vec<Dire
             dir_entries.extend(build_objects);
             // .git folder
             dir_entries.push(dot_git_root(fs, parent.into())?);
+            // .chase folder, unless the commit has its own .chase
+            if !dir_entries.iter().any(|e| e.name == RESULTS_FOLDER) {
+                dir_entries.push(chase_results_root(fs, parent.into())?);
+            }
 
             dir_entries
         }
@@ -644,6 +799,7 @@ pub fn readdir_git_dir(fs: &GitFs, parent: NormalIno) -> anyhow::Result<Vec<Dire
             let path = build_chase_path(fs, parent)?;
             populate_entries_by_path(fs, parent, &path)?
         }
+        InoFlag::ChaseResultsRoot => read_chase_results(fs, parent)?,
         InoFlag::DotGitRoot | InoFlag::VirDotGitRoot | InoFlag::InsideDotGit => {
             let commit_oid = fs.get_parent_commit(parent.into())?;
             read_inside_dot_git(fs, parent, commit_oid)?
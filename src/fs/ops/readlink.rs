@@ -1,11 +1,18 @@
+use std::os::unix::ffi::OsStrExt;
+
 use anyhow::bail;
 
 use crate::{
-    fs::{self, GitFs},
+    fs::{self, GitFs, builds::BuildOperationCtx},
     inodes::NormalIno,
 };
 
 pub fn readlink_git(fs: &GitFs, ino: NormalIno) -> anyhow::Result<Vec<u8>> {
+    // Symlinks of the build folders are real symlinks on disk
+    if fs.is_in_build(ino)? {
+        let path = BuildOperationCtx::new(fs, ino)?.path();
+        return Ok(std::fs::read_link(path)?.as_os_str().as_bytes().to_vec());
+    }
     let file_size = fs.get_file_size_from_db(ino)?;
     let fh = match fs.open(ino.to_norm_u64(), true, false, false) {
         Ok(fh) => fh,
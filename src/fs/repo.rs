@@ -62,6 +62,40 @@ pub struct InoState {
     pub vdir_cache: BTreeMap<VirtualIno, VirtualNode>,
     /// Oid = Commit Oid
     pub build_sessions: HashMap<Oid, Arc<BuildSession>>,
+    /// Commits chased without a Snap folder in the refs. Listed in the Commits folder
+    ///
+    /// i64 -> commit_time -> seconds since EPOCH
+    pub detached_snaps: BTreeMap<Oid, i64>,
+}
+
+/// The new commits of each ref after a refresh, oldest first
+pub type RefChanges = HashMap<RefKind, Vec<(i64, Oid)>>;
+
+fn ref_changes(
+    old: &HashMap<RefKind, Vec<(i64, Oid)>>,
+    new: &HashMap<RefKind, Vec<(i64, Oid)>>,
+) -> RefChanges {
+    let mut changes = RefChanges::new();
+    if old.is_empty() {
+        return changes;
+    }
+    for (rf, commits) in new {
+        let known = old
+            .get(rf)
+            .map(|c| c.iter().map(|(_, oid)| *oid).collect::<HashSet<Oid>>())
+            .unwrap_or_default();
+        let mut added = commits
+            .iter()
+            .filter(|(_, oid)| !known.contains(oid))
+            .copied()
+            .collect::<Vec<(i64, Oid)>>();
+        if added.is_empty() {
+            continue;
+        }
+        added.sort_by_key(|(time, _)| *time);
+        changes.insert(rf.clone(), added);
+    }
+    changes
 }
 
 // TODO: Learn how to write a macro
@@ -285,7 +319,9 @@ impl GitRepo {
     /// - Pr: Checks if a Pr-Merge exists and if it does, walks between the head and base parent. Otherwise, they are ignored
     ///
     /// - Tags and Pr-Merges: Does not walk the history. Only saves the tip
-    pub fn refresh_refs(&self) -> anyhow::Result<()> {
+    ///
+    /// Returns the commits that were not on each ref before. Empty the first time the refs are read.
+    pub fn refresh_refs(&self) -> anyhow::Result<RefChanges> {
         let repo = self.inner.lock();
 
         let mut ref_tips = Vec::new();
@@ -392,12 +428,14 @@ impl GitRepo {
             }
         }
 
-        self.with_ref_state_mut(|s| {
+        let changes = self.with_ref_state_mut(|s| {
+            let changes = ref_changes(&s.refs_to_snaps, &refs_to_snaps);
             s.refs_to_snaps = refs_to_snaps;
             s.snaps_to_ref = snaps_to_ref;
             s.unique_namespaces = unique_namespaces;
+            changes
         });
-        Ok(())
+        Ok(changes)
     }
 
     // Looks for the commits under `main` and splits them by months
@@ -541,7 +579,7 @@ impl GitRepo {
         self.with_ref_state(|s| {
             if let Some(objects) = s.refs_to_snaps.iter().find(|k| *k.0 == rf_kind) {
                 let mut sorted = objects.1.clone();
-                sorted.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+                sorted.sort_unstable_by_key(|a| a.0);
                 for (secs_utc, commit_oid) in sorted {
                     commit_num += 1;
                     let folder_name =
@@ -566,7 +604,7 @@ impl GitRepo {
         &self,
         custom_remote: Option<String>,
         repo_path: &Path,
-    ) -> anyhow::Result<()> {
+    ) -> anyhow::Result<RefChanges> {
         let inner = self.inner.lock();
         let remotes = inner.remotes()?;
         let remotes_vec = remotes.iter().flatten().collect::<Vec<_>>();
@@ -595,11 +633,11 @@ impl GitRepo {
         drop(inner);
         if let Some(url) = url {
             self.fetch(url.as_str())?;
-            self.refresh_refs()?;
+            let changes = self.refresh_refs()?;
             let fingerprint = self.get_refs_fingerprint()?;
             self.with_ref_state_mut(|s| s.fingerprint = fingerprint);
             self.store_refs_to_file(repo_path)?;
-            Ok(())
+            Ok(changes)
         } else {
             bail!("Could not find remote")
         }
@@ -1121,6 +1159,62 @@ impl GitRepo {
         })
     }
 
+    /// Pinned sessions survive the cleanup at the end of a chase
+    ///
+    /// Returns false if the commit has no build session
+    pub fn set_build_session_pinned(&self, commit_oid: Oid, pinned: bool) -> bool {
+        self.with_ino_state(|s| match s.build_sessions.get(&commit_oid) {
+            Some(session) => {
+                session
+                    .pinned
+                    .store(pinned, std::sync::atomic::Ordering::Relaxed);
+                true
+            }
+            None => false,
+        })
+    }
+
+    pub fn is_build_session_pinned(&self, commit_oid: Oid) -> bool {
+        self.with_ino_state(|s| {
+            s.build_sessions
+                .get(&commit_oid)
+                .is_some_and(|session| session.pinned.load(std::sync::atomic::Ordering::Relaxed))
+        })
+    }
+
+    /// Returns the commits with a pinned build session and the path of the build folder
+    pub fn pinned_build_sessions(&self) -> Vec<(Oid, PathBuf)> {
+        self.with_ino_state(|s| {
+            s.build_sessions
+                .iter()
+                .filter(|(_, session)| session.pinned.load(std::sync::atomic::Ordering::Relaxed))
+                .map(|(oid, session)| (*oid, session.temp_dir()))
+                .collect()
+        })
+    }
+
+    /// Creates a Snap folder in the Commits folder, for a commit not found in any ref
+    pub fn add_detached_snap(&self, oid: Oid, commit_time: i64) {
+        self.with_ino_state_mut(|s| s.detached_snaps.insert(oid, commit_time));
+    }
+
+    /// Used for the Snap folders inside the Commits folder
+    pub fn detached_snaps(&self) -> Vec<ObjectAttr> {
+        self.with_ino_state(|s| {
+            s.detached_snaps
+                .iter()
+                .map(|(oid, secs_utc)| ObjectAttr {
+                    name: OsString::from(format!("Snap_{oid:.10}")),
+                    oid: *oid,
+                    kind: ObjectType::Commit,
+                    git_mode: 0o040000,
+                    size: 0,
+                    commit_time: git2::Time::new(*secs_utc, 0),
+                })
+                .collect()
+        })
+    }
+
     pub fn print_commit_summary(fs: &GitFs, repo_id: u16, oid: Oid) -> anyhow::Result<Vec<u8>> {
         let repo = fs
             .repos_list
@@ -1,11 +1,14 @@
-use std::ffi::OsStr;
+use std::ffi::{OsStr, OsString};
 
 use anyhow::anyhow;
 
 use crate::{
-    fs::{FileType, GitFs, LIVE_FOLDER, REPO_SHIFT},
+    fs::{
+        FileType, GitFs, LIVE_FOLDER, REPO_SHIFT,
+        builds::chase_record::{ChaseRecord, ResultKind},
+    },
     inodes::Inodes,
-    test_setup::{GitFsTestSetup, get_fs, run_git_fs_test},
+    test_setup::{GitFsTestSetup, get_fs, local_repo_with_commits, run_git_fs_test},
 };
 
 use crate::fs::ROOT_INO;
@@ -483,3 +486,51 @@ fn test_rename_live_source_missing() -> anyhow::Result<()> {
     )?;
     Ok(())
 }
+
+#[test]
+fn test_chase_results_folder_name() -> anyhow::Result<()> {
+    run_git_fs_test(
+        GitFsTestSetup {
+            key: "test_chase_results_folder_name",
+            read_only: false,
+        },
+        |_| -> anyhow::Result<()> {
+            let fs = get_fs();
+            let (_, snaps) = local_repo_with_commits("local", &["a.txt", ".chase"])?;
+
+            let names = |ino: u64| -> anyhow::Result<Vec<(OsString, FileType)>> {
+                Ok(fs
+                    .readdir(ino)?
+                    .into_iter()
+                    .filter(|e| e.name == ".chase")
+                    .map(|e| (e.name, e.kind))
+                    .collect())
+            };
+            // The results folder is added to a commit without its own .chase
+            assert_eq!(
+                names(snaps[0].1)?,
+                [(OsString::from(".chase"), FileType::Directory)]
+            );
+            // Results of a run are listed, and dropped once the run is deleted
+            let (commit, snap) = snaps[0];
+            let repo = fs.get_repo(snap)?;
+            let run_dir = repo.chase_dir.join("build.lua").join("2025-01-01_00-00-00");
+            std::fs::create_dir_all(&run_dir)?;
+            ChaseRecord::with_results(&[commit], &[(commit, ResultKind::Ok)]).save(&run_dir)?;
+            let results = fs.lookup(snap, OsStr::new(".chase"))?.unwrap().ino;
+            let listed = fs.readdir(results)?;
+            assert_eq!(listed.len(), 1);
+            assert_eq!(listed[0].name, "build.lua");
+            std::fs::remove_dir_all(&run_dir)?;
+            assert!(fs.readdir(results)?.is_empty());
+            assert!(fs.lookup(results, OsStr::new("build.lua"))?.is_none());
+
+            // The .chase of the commit is not hidden or listed twice
+            assert_eq!(
+                names(snaps[1].1)?,
+                [(OsString::from(".chase"), FileType::RegularFile)]
+            );
+            Ok(())
+        },
+    )
+}
@@ -0,0 +1,447 @@
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::{OsStr, OsString},
+    path::PathBuf,
+    sync::{Arc, LazyLock},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail};
+use git2::{DiffOptions, ObjectType, Oid, Repository};
+use mlua::{Lua, Table, Value};
+use parking_lot::Mutex;
+
+use crate::fs::{
+    CHASE_FOLDER, COMMITS_FOLDER, LIVE_FOLDER, ObjectAttr,
+    builds::{
+        chase_console::RepoHelpers,
+        reporter::ChaseError,
+        runtime::{LuaSandbox, parse_date},
+    },
+    repo::GitRepo,
+};
+
+/// Per repo file defining the extra folders of the repo root, inside the chase folder
+pub const VIEWS_FILE: &str = "views.lua";
+
+/// Folder names used by the repo root
+const RESERVED: [&str; 7] = [
+    LIVE_FOLDER,
+    CHASE_FOLDER,
+    COMMITS_FOLDER,
+    "Branches",
+    "Tags",
+    "Pr",
+    "PrMerge",
+];
+
+/// views.lua runs in the FUSE thread, while listing the repo root or a view.
+/// Kept well below the FUSE timeout, a miss of the cache runs every rule at once.
+const VIEWS_BUDGET: Duration = Duration::from_millis(200);
+
+/// A rule of views.lua: a function returning the commits, or the list of commits itself
+type ViewRules = Arc<Mutex<Vec<(String, Value)>>>;
+
+/// The commits returned by the rule of a view, or the error of the rule
+type ViewCommits = Result<Vec<String>, String>;
+
+/// The refs fingerprint and the hash of views.lua the views were computed with
+type CacheKey = ([u8; 32], blake3::Hash);
+
+/// The views of a repo, dropped once its refs or views.lua change
+struct ViewCache {
+    key: CacheKey,
+    /// In the order they were added
+    views: Vec<(String, ViewCommits)>,
+}
+
+/// key: chase dir of the repo
+static VIEW_CACHE: LazyLock<Mutex<HashMap<PathBuf, ViewCache>>> = LazyLock::new(Default::default);
+
+/// The names of the folders defined in views.lua, in the order they were added.
+pub fn view_names(repo: &Arc<GitRepo>) -> Vec<String> {
+    views(repo).into_iter().map(|(name, _)| name).collect()
+}
+
+/// Lists the commits returned by the rule of a view as Snap folders, oldest first
+pub fn view_snaps(repo: &Arc<GitRepo>, name: &OsStr) -> anyhow::Result<Vec<ObjectAttr>> {
+    let name = name.to_string_lossy();
+    let Some((_, commits)) = views(repo).into_iter().find(|(n, _)| *n == name) else {
+        bail!(std::io::Error::from_raw_os_error(libc::ENOENT))
+    };
+    let commits = commits.map_err(|e| anyhow!(e))?;
+
+    let mut seen = HashSet::new();
+    let mut snaps = repo.with_repo(|r| {
+        commits
+            .iter()
+            .filter_map(|rev| match resolve_commit(r, rev) {
+                Some(found) => Some(found),
+                None => {
+                    tracing::warn!(view = %name, rev = %rev, "Commit not found");
+                    None
+                }
+            })
+            .filter(|(oid, _)| seen.insert(*oid))
+            .collect::<Vec<(Oid, i64)>>()
+    });
+    snaps.sort_by_key(|(_, time)| *time);
+    Ok(snaps
+        .into_iter()
+        .enumerate()
+        .map(|(i, (oid, secs_utc))| ObjectAttr {
+            name: OsString::from(format!("Snap{:03}_{oid:.7}", i + 1)),
+            oid,
+            kind: ObjectType::Commit,
+            git_mode: 0o040000,
+            size: 0,
+            commit_time: git2::Time::new(secs_utc, 0),
+        })
+        .collect())
+}
+
+/// Adds `repo.log(since)` and `repo.touches(id, path)` to the `repo` table
+pub fn add_git_helpers(lua: &Lua, repo: Arc<GitRepo>) -> mlua::Result<()> {
+    let table = lua.globals().get::<Table>("repo")?;
+
+    // LOG
+    {
+        let repo = Arc::clone(&repo);
+        let log = lua.create_function(move |lua, since: Option<String>| {
+            let since = match since {
+                Some(date) => Some(parse_date(&date).ok_or_else(|| {
+                    mlua::Error::RuntimeError(format!("Cannot parse date {date}"))
+                })?),
+                None => None,
+            };
+            let commits =
+                repo.with_ref_state(|s| s.snaps_to_ref.keys().copied().collect::<Vec<Oid>>());
+            let entries = repo.with_repo(|r| {
+                let mut entries = commits
+                    .into_iter()
+                    .filter_map(|oid| r.find_commit(oid).ok())
+                    .filter(|c| since.is_none_or(|since| c.time().seconds() >= since))
+                    .map(|c| {
+                        let author = c.author();
+                        (
+                            c.time().seconds(),
+                            c.id().to_string(),
+                            author.name().unwrap_or_default().to_string(),
+                            author.email().unwrap_or_default().to_string(),
+                            c.summary().unwrap_or_default().to_string(),
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                entries.sort_unstable_by_key(|e| std::cmp::Reverse(e.0));
+                entries
+            });
+            let out = lua.create_table()?;
+            for (time, id, author, email, summary) in entries {
+                let commit = lua.create_table()?;
+                commit.set("id", id)?;
+                commit.set("author", author)?;
+                commit.set("email", email)?;
+                commit.set("summary", summary)?;
+                commit.set("time", time)?;
+                out.push(commit)?;
+            }
+            Ok(out)
+        })?;
+        table.set("log", log)?;
+    }
+
+    // TOUCHES
+    {
+        let touches = lua.create_function(move |_, (id, path): (String, String)| {
+            repo.with_repo(|r| commit_touches(r, &id, &path))
+                .map_err(|e| mlua::Error::RuntimeError(format!("{e}")))
+        })?;
+        table.set("touches", touches)?;
+    }
+    Ok(())
+}
+
+/// The views of the repo with their commits, from the cache or from one run of views.lua
+///
+/// A views.lua that does not load is logged and shows no folders, until it or the refs change.
+fn views(repo: &Arc<GitRepo>) -> Vec<(String, ViewCommits)> {
+    let src = match read_views(repo) {
+        Ok(Some(src)) => src,
+        Ok(None) => return vec![],
+        Err(e) => {
+            tracing::error!(e = %e, repo = %repo.repo_dir, "Could not read {VIEWS_FILE}");
+            return vec![];
+        }
+    };
+    let key = cache_key(repo, &src);
+    if let Some(key) = key
+        && let Some(cached) = VIEW_CACHE
+            .lock()
+            .get(&repo.chase_dir)
+            .filter(|cached| cached.key == key)
+    {
+        return cached.views.clone();
+    }
+    let views = match run_views(repo, &src, VIEWS_BUDGET, run_rules) {
+        Ok(views) => views,
+        Err(e) => {
+            tracing::error!(e = %e, repo = %repo.repo_dir, "Could not load {VIEWS_FILE}");
+            vec![]
+        }
+    };
+    if let Some(key) = key {
+        VIEW_CACHE.lock().insert(
+            repo.chase_dir.clone(),
+            ViewCache {
+                key,
+                views: views.clone(),
+            },
+        );
+    }
+    views
+}
+
+/// Runs the rule of every view. The error of a rule only hides the commits of its view.
+fn run_rules(rules: &ViewRules) -> mlua::Result<Vec<(String, ViewCommits)>> {
+    let rules = rules.lock().clone();
+    Ok(rules
+        .into_iter()
+        .map(|(name, rule)| {
+            let commits = rule_commits(&name, rule).map_err(|e| format!("{VIEWS_FILE}: {e}"));
+            (name, commits)
+        })
+        .collect())
+}
+
+fn rule_commits(name: &str, rule: Value) -> mlua::Result<Vec<String>> {
+    let list = match rule {
+        Value::Function(f) => f.call::<Value>(())?,
+        other => other,
+    };
+    let Value::Table(list) = list else {
+        return Err(mlua::Error::RuntimeError(format!(
+            "View {name} did not return a list of commits"
+        )));
+    };
+    list.sequence_values::<String>().collect()
+}
+
+/// The content of views.lua, None if the repo has none
+fn read_views(repo: &GitRepo) -> anyhow::Result<Option<String>> {
+    match std::fs::read_to_string(repo.chase_dir.join(VIEWS_FILE)) {
+        Ok(src) => Ok(Some(src)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// None if the refs cannot be read, then the views are not cached
+fn cache_key(repo: &GitRepo, src: &str) -> Option<CacheKey> {
+    match repo.get_refs_fingerprint() {
+        Ok(fingerprint) => Some((fingerprint, blake3::hash(src.as_bytes()))),
+        Err(e) => {
+            tracing::warn!(e = %e, repo = %repo.repo_dir, "Could not read the refs fingerprint");
+            None
+        }
+    }
+}
+
+/// Loads views.lua with the view function and the repo helpers, then runs `f` on the rules
+///
+/// The run is stopped, and fails, once it takes longer than `budget`.
+fn run_views<R, F>(repo: &Arc<GitRepo>, src: &str, budget: Duration, f: F) -> anyhow::Result<R>
+where
+    F: FnOnce(&ViewRules) -> mlua::Result<R>,
+{
+    let sandbox = LuaSandbox::current();
+    let deadline = Instant::now() + budget;
+    let (lua, over_limit) = sandbox.new_lua_until(Some(deadline)).map_err(lua_error)?;
+    let rules = ViewRules::default();
+
+    let rules_ref = Arc::clone(&rules);
+    let run = || -> mlua::Result<R> {
+        let view = lua.create_function(move |_, (name, rule): (String, Value)| {
+            if !is_valid_name(&name) {
+                return Err(mlua::Error::RuntimeError(format!(
+                    "Cannot name a view {name:?}"
+                )));
+            }
+            if !matches!(rule, Value::Function(_) | Value::Table(_)) {
+                return Err(mlua::Error::RuntimeError(format!(
+                    "View {name} needs a function or a list of commits"
+                )));
+            }
+            let mut rules = rules_ref.lock();
+            if rules.iter().any(|(n, _)| *n == name) {
+                return Err(mlua::Error::RuntimeError(format!(
+                    "View {name} is defined twice"
+                )));
+            }
+            rules.push((name, rule));
+            Ok(())
+        })?;
+        lua.globals().set("view", view)?;
+        RepoHelpers::new(repo, &repo.repo_dir).set_globals(&lua)?;
+        add_git_helpers(&lua, Arc::clone(repo))?;
+        lua.load(src).set_name(VIEWS_FILE).exec()?;
+        f(&rules)
+    };
+    let res = run();
+    // The rules that were stopped only have an error
+    if Instant::now() >= deadline {
+        bail!(
+            "{VIEWS_FILE} went over its time budget of {}ms",
+            budget.as_millis()
+        );
+    }
+    res.map_err(|e| lua_error(sandbox.check_limits(e, &over_limit)))
+}
+
+fn lua_error(e: ChaseError) -> anyhow::Error {
+    match e {
+        ChaseError::LuaError { source, .. } => anyhow!("{VIEWS_FILE}: {source}"),
+        ChaseError::LuaLimit { msg } => anyhow!("{VIEWS_FILE} {msg}"),
+        _ => anyhow!("{VIEWS_FILE} could not be loaded"),
+    }
+}
+
+/// Names that would hide a folder of the repo root, a MONTH folder or a virtual file are refused
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && !name.contains(['/', '@', '\0'])
+        && !RESERVED.contains(&name)
+        && !is_month_name(name)
+}
+
+fn is_month_name(name: &str) -> bool {
+    name.split_once('-').is_some_and(|(year, month)| {
+        year.len() == 4 && year.parse::<i32>().is_ok() && month.parse::<u32>().is_ok()
+    })
+}
+
+/// A full or short hash, or any revspec resolving to a commit
+fn resolve_commit(r: &Repository, rev: &str) -> Option<(Oid, i64)> {
+    let commit = r.revparse_single(rev).ok()?.peel_to_commit().ok()?;
+    Some((commit.id(), commit.time().seconds()))
+}
+
+/// The commit changed a file at or under `path`, compared to its first parent
+fn commit_touches(r: &Repository, id: &str, path: &str) -> anyhow::Result<bool> {
+    let commit = r.revparse_single(id)?.peel_to_commit()?;
+    let tree = commit.tree()?;
+    let parent = match commit.parent(0) {
+        Ok(parent) => Some(parent.tree()?),
+        Err(_) => None,
+    };
+    let mut opts = DiffOptions::new();
+    opts.pathspec(path);
+    let diff = r.diff_tree_to_tree(parent.as_ref(), Some(&tree), Some(&mut opts))?;
+    Ok(diff.deltas().len() > 0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_setup::{GitFsTestSetup, get_fs, local_repo_with_commits, run_git_fs_test};
+
+    #[test]
+    fn test_view_names_and_touches() -> anyhow::Result<()> {
+        for name in ["by_alice", "release candidates", "net-2"] {
+            assert!(is_valid_name(name), "{name}");
+        }
+        for name in ["", ".hidden", "a/b", "Snap@", "Branches", "live", "2024-05"] {
+            assert!(!is_valid_name(name), "{name}");
+        }
+
+        let dir = tempfile::tempdir()?;
+        let r = Repository::init(dir.path())?;
+        let sig = git2::Signature::now("guse", "guse@localhost")?;
+        let commit = |file: &str, parents: &[&git2::Commit]| -> anyhow::Result<Oid> {
+            std::fs::create_dir_all(dir.path().join(file).parent().unwrap())?;
+            std::fs::write(dir.path().join(file), file)?;
+            let mut index = r.index()?;
+            index.add_path(std::path::Path::new(file))?;
+            let tree = r.find_tree(index.write_tree()?)?;
+            Ok(r.commit(Some("HEAD"), &sig, &sig, file, &tree, parents)?)
+        };
+        let first = commit("src/net/mod.rs", &[])?;
+        let first_commit = r.find_commit(first)?;
+        let second = commit("README", &[&first_commit])?;
+
+        assert!(commit_touches(&r, &first.to_string(), "src/net")?);
+        assert!(!commit_touches(&r, &second.to_string(), "src/net")?);
+        assert!(commit_touches(&r, "HEAD", "README")?);
+        assert_eq!(
+            resolve_commit(&r, "HEAD~1").map(|(oid, _)| oid),
+            Some(first)
+        );
+        assert!(resolve_commit(&r, "nope").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_views_cache_and_budget() -> anyhow::Result<()> {
+        run_git_fs_test(
+            GitFsTestSetup {
+                key: "test_views_cache_and_budget",
+                read_only: false,
+            },
+            |_| -> anyhow::Result<()> {
+                let fs = get_fs();
+                let (repo_ino, snaps) = local_repo_with_commits("views", &["a", "b"])?;
+                let repo = fs.get_repo(repo_ino)?;
+                std::fs::create_dir_all(&repo.chase_dir)?;
+                let views = repo.chase_dir.join(VIEWS_FILE);
+                std::fs::write(
+                    &views,
+                    "view(\"first\", function() return {\"HEAD~1\"} end)",
+                )?;
+
+                assert_eq!(view_names(&repo), ["first"]);
+                let first = view_snaps(&repo, OsStr::new("first"))?;
+                assert_eq!(first.len(), 1);
+                assert_eq!(first[0].oid, snaps[0].0);
+
+                // Not run again while the refs and views.lua stay the same
+                VIEW_CACHE
+                    .lock()
+                    .get_mut(&repo.chase_dir)
+                    .expect("cached views")
+                    .views = vec![("cached".to_string(), Ok(vec![snaps[1].0.to_string()]))];
+                assert_eq!(view_names(&repo), ["cached"]);
+                let cached = view_snaps(&repo, OsStr::new("cached"))?;
+                assert_eq!(cached[0].oid, snaps[1].0);
+                assert!(view_snaps(&repo, OsStr::new("first")).is_err());
+
+                // A new commit changes the refs
+                repo.with_repo(|r| -> anyhow::Result<()> {
+                    let sig = git2::Signature::now("guse", "guse@localhost")?;
+                    let head = r.head()?.peel_to_commit()?;
+                    r.commit(Some("HEAD"), &sig, &sig, "c", &head.tree()?, &[&head])?;
+                    Ok(())
+                })?;
+                assert_eq!(view_names(&repo), ["first"]);
+                let first = view_snaps(&repo, OsStr::new("first"))?;
+                assert_eq!(first[0].oid, snaps[1].0);
+
+                // So does an edit of views.lua. A failing rule only hides its own commits.
+                std::fs::write(
+                    &views,
+                    "view(\"second\", {\"HEAD\"})\nview(\"bad\", function() error(\"no\") end)",
+                )?;
+                assert_eq!(view_names(&repo), ["second", "bad"]);
+                assert_eq!(view_snaps(&repo, OsStr::new("second"))?.len(), 1);
+                assert!(view_snaps(&repo, OsStr::new("bad")).is_err());
+
+                let src = "while true do end";
+                let start = Instant::now();
+                let err = run_views(&repo, src, Duration::from_millis(50), |_| Ok(()))
+                    .expect_err("stopped by the budget");
+                assert!(err.to_string().contains("time budget"), "{err}");
+                assert!(start.elapsed() < Duration::from_secs(1));
+                Ok(())
+            },
+        )
+    }
+}
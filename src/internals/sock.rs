@@ -16,8 +16,13 @@ use crate::{
     fs::{
         GitFs,
         builds::{
-            chase::{ChaseArgs, ChaseId, start_chase, start_chase_connection},
+            chase::{
+                ChaseArgs, ChaseId, list_pinned_builds, print_chase_diff, print_chase_log,
+                release_pinned_builds, resume_chase, start_chase, start_chase_connection,
+            },
+            chase_console::eval_lua_console,
             chase_handle::{ChaseHandle, ChaseState},
+            chase_watch::{queue_watch_runs, start_watch_worker},
             logger::CmdResult,
             reporter::Updater,
         },
@@ -51,6 +56,33 @@ pub enum ControlReq<'a> {
         args: ChaseArgs,
         chase_id: ChaseId,
     },
+    ResumeChase {
+        repo: &'a str,
+        build: &'a str,
+        run: &'a str,
+        chase_id: ChaseId,
+    },
+    ChaseLog {
+        repo: &'a str,
+        build: &'a str,
+        run: &'a str,
+        commit: &'a str,
+        streams: Vec<String>,
+    },
+    ChaseDiff {
+        repo: &'a str,
+        build: &'a str,
+        run_a: &'a str,
+        run_b: &'a str,
+        tail: usize,
+    },
+    BuildList {
+        repo: &'a str,
+    },
+    BuildRelease {
+        repo: &'a str,
+        commit: Option<&'a str>,
+    },
     NewScript {
         repo: &'a str,
         build: &'a str,
@@ -67,6 +99,11 @@ pub enum ControlReq<'a> {
     StopChase {
         id: ChaseId,
     },
+    // One line of `guse lua`, sent with the previous chunks of the session
+    Lua {
+        repo: &'a str,
+        chunks: Vec<String>,
+    },
     Connect,
     Status,
 }
@@ -83,6 +120,8 @@ pub enum ControlRes {
     Draw { message: Vec<Vec<u8>> },
     RepoList { repos: Vec<String> },
     Status { running: bool, mount_point: String },
+    // Whether the console keeps the chunk
+    LuaDone { ok: bool },
 }
 
 pub fn start_control_server(
@@ -91,6 +130,7 @@ pub fn start_control_server(
     mountpoint: String,
 ) -> anyhow::Result<()> {
     let listener = bind_socket(socket_path)?;
+    start_watch_worker(fs.clone());
 
     thread::spawn(move || {
         for incomming in &listener {
@@ -145,11 +185,21 @@ fn handle_client(
             ControlReq::RepoUpdate { name, remote } => {
                 let name = name.strip_suffix("/").unwrap_or(name);
                 let fs = inner.getfs();
-                if let Err(e) = fs.update_repo(name, remote) {
-                    stream.update(&format!("Error fetching repo {name}: {e}\n"))?;
-                    return Ok(ControlRes::Ok);
-                }
+                let changes = match fs.update_repo(name, remote) {
+                    Ok(changes) => changes,
+                    Err(e) => {
+                        stream.update(&format!("Error fetching repo {name}: {e}\n"))?;
+                        return Ok(ControlRes::Ok);
+                    }
+                };
                 stream.update(&format!("Repo {name} has been fetched!\n"))?;
+                match queue_watch_runs(name, changes) {
+                    Ok(true) => stream.update(
+                        "The scripts watching the new commits will chase them, see their watch.log\n",
+                    )?,
+                    Ok(false) => {}
+                    Err(e) => stream.update(&format!("Error queueing watched chases: {e}\n"))?,
+                }
                 Ok(ControlRes::Ok)
             }
             ControlReq::RepoList => {
@@ -171,6 +221,53 @@ fn handle_client(
                 let _ = start_chase(&fs, repo, build, &mut stream, args, chase_id);
                 Ok(ControlRes::Ok)
             }
+            ControlReq::ResumeChase {
+                repo,
+                build,
+                run,
+                chase_id,
+            } => {
+                let repo = repo.strip_suffix("/").unwrap_or(repo);
+                let fs = inner.getfs();
+                let _ = resume_chase(&fs, repo, build, run, &mut stream, chase_id);
+                Ok(ControlRes::Ok)
+            }
+            ControlReq::ChaseLog {
+                repo,
+                build,
+                run,
+                commit,
+                streams,
+            } => {
+                let repo = repo.strip_suffix("/").unwrap_or(repo);
+                let fs = inner.getfs();
+                let _ = print_chase_log(&fs, repo, build, run, commit, &streams, &mut stream);
+                Ok(ControlRes::Ok)
+            }
+            ControlReq::ChaseDiff {
+                repo,
+                build,
+                run_a,
+                run_b,
+                tail,
+            } => {
+                let repo = repo.strip_suffix("/").unwrap_or(repo);
+                let fs = inner.getfs();
+                let _ = print_chase_diff(&fs, repo, build, (run_a, run_b), tail, &mut stream);
+                Ok(ControlRes::Ok)
+            }
+            ControlReq::BuildList { repo } => {
+                let repo = repo.strip_suffix("/").unwrap_or(repo);
+                let fs = inner.getfs();
+                let _ = list_pinned_builds(&fs, repo, &mut stream);
+                Ok(ControlRes::Ok)
+            }
+            ControlReq::BuildRelease { repo, commit } => {
+                let repo = repo.strip_suffix("/").unwrap_or(repo);
+                let fs = inner.getfs();
+                let _ = release_pinned_builds(&fs, repo, commit, &mut stream);
+                Ok(ControlRes::Ok)
+            }
             ControlReq::StopChase { id } => {
                 if let CmdResult::Err(e) = ChaseHandle::set_stop_flag(id) {
                     let _ =
@@ -267,6 +364,12 @@ fn handle_client(
                 }
                 Ok(ControlRes::Ok)
             }
+            ControlReq::Lua { repo, chunks } => {
+                let repo = repo.strip_suffix("/").unwrap_or(repo);
+                let fs = inner.getfs();
+                let ok = eval_lua_console(&fs, repo, &chunks, &mut stream).is_ok();
+                Ok(ControlRes::LuaDone { ok })
+            }
             ControlReq::Status => {
                 stream.update("Not implemented!\n")?;
                 Ok(ControlRes::Ok)
@@ -370,6 +473,7 @@ pub fn send_req(sock: &Path, req: &ControlReq) -> anyhow::Result<ControlRes> {
                     out.flush()?;
                 }
                 ControlRes::Accept { id } => return Ok(ControlRes::Accept { id }),
+                ControlRes::LuaDone { ok } => return Ok(ControlRes::LuaDone { ok }),
                 ControlRes::ChaseStop => {}
                 other => {
                     println!("Ending GUSE command");
@@ -1,7 +1,8 @@
 #![allow(dead_code)]
 
 use std::{
-    path::PathBuf,
+    io::Write,
+    path::{Path, PathBuf},
     sync::{Arc, atomic::AtomicBool},
     thread,
 };
@@ -10,7 +11,12 @@ use anyhow::{anyhow, bail};
 use clap::{Arg, ArgAction, ArgMatches, Command, command, crate_authors, crate_version};
 
 use guse::{
-    fs::builds::chase::ChaseArgs,
+    fs::builds::{
+        chase::{AnsiMode, ChaseArgs, ChaseId, KeepMode},
+        chase_env::CHASE_ENV_FILE,
+        chase_hook::ChaseHooks,
+        runtime::{LUA_SANDBOX, LuaSandbox, is_incomplete_chunk},
+    },
     internals::sock::{ControlReq, ControlRes, send_req, socket_path},
 };
 use tracing_subscriber::{EnvFilter, filter::Directive};
@@ -51,57 +57,178 @@ fn main() -> anyhow::Result<()> {
                 tracing::error!("Wrong command!")
             }
         },
-        Some(("chase", m)) => {
+        Some(("chase", m)) => match m.subcommand() {
+            Some(("resume", r)) => {
+                let sock = socket_path()?;
+                let repo = r
+                    .get_one::<String>("repo")
+                    .ok_or_else(|| anyhow!("Cannot parse argument"))?
+                    .clone();
+                let build = r
+                    .get_one::<String>("build")
+                    .ok_or_else(|| anyhow!("Cannot parse argument"))?
+                    .clone();
+                let run = r
+                    .get_one::<String>("run")
+                    .ok_or_else(|| anyhow!("Cannot parse argument"))?
+                    .clone();
+                run_chase(&sock, move |work_sock, id| {
+                    let chase_req = ControlReq::ResumeChase {
+                        repo: &repo,
+                        build: &build,
+                        run: &run,
+                        chase_id: id,
+                    };
+                    let _ = send_req(&work_sock, &chase_req);
+                })?;
+            }
+            Some(("log", l)) => {
+                let sock = socket_path()?;
+                let repo = l
+                    .get_one::<String>("repo")
+                    .ok_or_else(|| anyhow!("Cannot parse argument"))?;
+                let build = l
+                    .get_one::<String>("build")
+                    .ok_or_else(|| anyhow!("Cannot parse argument"))?;
+                let run = l
+                    .get_one::<String>("run")
+                    .ok_or_else(|| anyhow!("Cannot parse argument"))?;
+                let commit = l
+                    .get_one::<String>("commit")
+                    .ok_or_else(|| anyhow!("Cannot parse argument"))?;
+                let streams = l
+                    .get_many::<String>("stream")
+                    .map(|s| s.cloned().collect())
+                    .unwrap_or_default();
+                let req = ControlReq::ChaseLog {
+                    repo,
+                    build,
+                    run,
+                    commit,
+                    streams,
+                };
+                send_req(&sock, &req)?;
+            }
+            Some(("stop", st)) => {
+                let sock = socket_path()?;
+                let id = *st
+                    .get_one::<ChaseId>("id")
+                    .ok_or_else(|| anyhow!("Cannot parse argument"))?;
+                if let ControlRes::ChaseStop = send_req(&sock, &ControlReq::StopChase { id })? {
+                    println!("Chase {id} stopped");
+                }
+            }
+            Some(("diff", d)) => {
+                let sock = socket_path()?;
+                let repo = d
+                    .get_one::<String>("repo")
+                    .ok_or_else(|| anyhow!("Cannot parse argument"))?;
+                let build = d
+                    .get_one::<String>("build")
+                    .ok_or_else(|| anyhow!("Cannot parse argument"))?;
+                let run_a = d
+                    .get_one::<String>("run_a")
+                    .ok_or_else(|| anyhow!("Cannot parse argument"))?;
+                let run_b = d
+                    .get_one::<String>("run_b")
+                    .ok_or_else(|| anyhow!("Cannot parse argument"))?;
+                let tail = *d
+                    .get_one::<usize>("tail")
+                    .ok_or_else(|| anyhow!("Cannot parse argument"))?;
+                let req = ControlReq::ChaseDiff {
+                    repo,
+                    build,
+                    run_a,
+                    run_b,
+                    tail,
+                };
+                send_req(&sock, &req)?;
+            }
+            _ => {
+                let sock = socket_path()?;
+                let repo = m
+                    .get_one::<String>("repo")
+                    .ok_or_else(|| anyhow!("Cannot parse argument"))?
+                    .clone();
+                let build = m
+                    .get_one::<String>("build")
+                    .ok_or_else(|| anyhow!("Cannot parse argument"))?
+                    .clone();
+                let log = m.get_flag("log");
+                let no_move = m.get_flag("no-move");
+                let clone = m.get_flag("clone");
+                let log_limit = m
+                    .get_one::<u64>("log-limit")
+                    .ok_or_else(|| anyhow!("Cannot parse argument"))?
+                    .checked_mul(1024 * 1024)
+                    .ok_or_else(|| anyhow!("--log-limit is too large"))?;
+                let keep = m
+                    .get_one::<String>("keep")
+                    .and_then(|k| KeepMode::from_name(k))
+                    .unwrap_or_default();
+                let kill_grace = *m
+                    .get_one::<u64>("kill-grace")
+                    .ok_or_else(|| anyhow!("Cannot parse argument"))?;
+                let ansi = m
+                    .get_one::<String>("ansi")
+                    .and_then(|a| AnsiMode::from_name(a))
+                    .unwrap_or_default();
+                let max_line = m
+                    .get_one::<usize>("max-line")
+                    .ok_or_else(|| anyhow!("Cannot parse argument"))?
+                    .checked_mul(1024)
+                    .ok_or_else(|| anyhow!("--max-line is too large"))?;
+                let chase_args = ChaseArgs {
+                    log,
+                    no_move,
+                    clone,
+                    log_limit,
+                    keep,
+                    kill_grace,
+                    ansi,
+                    max_line,
+                };
+
+                run_chase(&sock, move |work_sock, id| {
+                    let chase_req = ControlReq::Chase {
+                        repo: &repo,
+                        build: &build,
+                        args: chase_args,
+                        chase_id: id,
+                    };
+                    let _ = send_req(&work_sock, &chase_req);
+                })?;
+            }
+        },
+        Some(("build", m)) => match m.subcommand() {
+            Some(("list", b)) => {
+                let sock = socket_path()?;
+                let repo = b
+                    .get_one::<String>("repo")
+                    .ok_or_else(|| anyhow!("Cannot parse argument"))?;
+                let req = ControlReq::BuildList { repo };
+                send_req(&sock, &req)?;
+            }
+            Some(("release", b)) => {
+                let sock = socket_path()?;
+                let repo = b
+                    .get_one::<String>("repo")
+                    .ok_or_else(|| anyhow!("Cannot parse argument"))?;
+                let commit = b.get_one::<String>("commit").map(|c| c.as_str());
+                let req = ControlReq::BuildRelease { repo, commit };
+                send_req(&sock, &req)?;
+            }
+            _ => {
+                dbg!("Wrong command!");
+                tracing::error!("Wrong command!")
+            }
+        },
+        Some(("lua", m)) => {
             let sock = socket_path()?;
             let repo = m
                 .get_one::<String>("repo")
                 .ok_or_else(|| anyhow!("Cannot parse argument"))?;
-            let build = m
-                .get_one::<String>("build")
-                .ok_or_else(|| anyhow!("Cannot parse argument"))?;
-            let log = m.get_flag("log");
-            let no_move = m.get_flag("no-move");
-            let chase_args = ChaseArgs { log, no_move };
-
-            // Send connection request
-            let conn_req = ControlReq::Connect;
-            let accept_res = send_req(&sock, &conn_req)?;
-
-            let ControlRes::Accept { id } = accept_res else {
-                println!("Chase ID not received");
-                bail!("")
-            };
-
-            let stop_signal = Arc::new(AtomicBool::new(false));
-            let signal_clone = stop_signal.clone();
-
-            let _ = ctrlc::set_handler(move || {
-                signal_clone.store(true, std::sync::atomic::Ordering::SeqCst);
-            });
-
-            let work_sock = sock.clone();
-            let repo_clone = repo.clone();
-            let build_clone = build.clone();
-            let worker = thread::spawn(move || {
-                let chase_req = ControlReq::Chase {
-                    repo: &repo_clone,
-                    build: &build_clone,
-                    args: chase_args,
-                    chase_id: id,
-                };
-                let _ = send_req(&work_sock, &chase_req);
-            });
-
-            loop {
-                if worker.is_finished() {
-                    break;
-                }
-                if stop_signal.load(std::sync::atomic::Ordering::Relaxed) {
-                    let stop_req = ControlReq::StopChase { id };
-                    let _ = send_req(&sock.clone(), &stop_req);
-                    break;
-                }
-            }
+            run_lua_console(&sock, repo)?;
         }
         Some(("script", m)) => match m.subcommand() {
             Some(("new", s)) => {
@@ -157,6 +284,92 @@ fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Connects to the daemon, and sends the chase request from a worker thread.
+///
+/// A ctrl+c will send a request to stop the chase.
+fn run_chase<F>(sock: &Path, send_chase: F) -> anyhow::Result<()>
+where
+    F: FnOnce(PathBuf, ChaseId) + Send + 'static,
+{
+    // Send connection request
+    let conn_req = ControlReq::Connect;
+    let accept_res = send_req(sock, &conn_req)?;
+
+    let ControlRes::Accept { id } = accept_res else {
+        println!("Chase ID not received");
+        bail!("")
+    };
+
+    let stop_signal = Arc::new(AtomicBool::new(false));
+    let signal_clone = stop_signal.clone();
+
+    let _ = ctrlc::set_handler(move || {
+        signal_clone.store(true, std::sync::atomic::Ordering::SeqCst);
+    });
+
+    let work_sock = sock.to_path_buf();
+    let worker = thread::spawn(move || send_chase(work_sock, id));
+
+    loop {
+        if worker.is_finished() {
+            break;
+        }
+        if stop_signal.load(std::sync::atomic::Ordering::Relaxed) {
+            let stop_req = ControlReq::StopChase { id };
+            let _ = send_req(sock, &stop_req);
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Reads Lua from stdin, one chunk at a time, and sends it to the daemon with the previous chunks.
+///
+/// Chunks that fail are dropped from the session.
+fn run_lua_console(sock: &Path, repo: &str) -> anyhow::Result<()> {
+    println!(
+        "Lua console for {repo}. cfg and repo are loaded, :reset clears the session, :quit exits"
+    );
+    let mut chunks: Vec<String> = Vec::new();
+    let mut pending = String::new();
+    let stdin = std::io::stdin();
+    loop {
+        print!("{}", if pending.is_empty() { "lua> " } else { ">> " });
+        std::io::stdout().flush()?;
+        let mut line = String::new();
+        if stdin.read_line(&mut line)? == 0 {
+            println!();
+            break;
+        }
+        match line.trim() {
+            ":quit" | ":q" => break,
+            ":reset" => {
+                chunks.clear();
+                pending.clear();
+                continue;
+            }
+            "" if pending.is_empty() => continue,
+            _ => {}
+        }
+        pending.push_str(&line);
+        if is_incomplete_chunk(&pending) {
+            continue;
+        }
+        chunks.push(std::mem::take(&mut pending));
+        let req = ControlReq::Lua {
+            repo,
+            chunks: chunks.clone(),
+        };
+        match send_req(sock, &req)? {
+            ControlRes::LuaDone { ok: true } => {}
+            _ => {
+                chunks.pop();
+            }
+        }
+    }
+    Ok(())
+}
+
 fn start_app(matches: &ArgMatches) -> anyhow::Result<()> {
     run_mount(matches)?;
     Ok(())
@@ -218,6 +431,55 @@ fn handle_cli_args() -> ArgMatches {
                         .action(ArgAction::SetTrue)
                         .requires("mount-point")
                         .help("Disables the socket communication, needed for commands such as `guse repo remove <repo-name>`"),
+                )
+                .arg(
+                    Arg::new("chase-hook")
+                        .long("chase-hook")
+                        .value_name("COMMAND")
+                        .action(ArgAction::Append)
+                        .requires("mount-point")
+                        .help("Command to run when a chase ends. It receives the path to the JSON report on stdin"),
+                )
+                .arg(
+                    Arg::new("chase-notify")
+                        .long("chase-notify")
+                        .value_name("FILE")
+                        .action(ArgAction::Append)
+                        .requires("mount-point")
+                        .help("File where a line summarizing each chase is appended when it ends"),
+                )
+                .arg(
+                    Arg::new("chase-env")
+                        .long("chase-env")
+                        .value_name("FILE")
+                        .requires("mount-point")
+                        .help("File with KEY=VALUE lines, set as env variables for every chase command. The values of the secrets (TOKEN, PASSWORD...) are masked in the output"),
+                )
+                .arg(
+                    Arg::new("lua-allow")
+                        .long("lua-allow")
+                        .value_name("LIB")
+                        .value_delimiter(',')
+                        .action(ArgAction::Append)
+                        .value_parser(["os", "io", "package"])
+                        .requires("mount-point")
+                        .help("Lua libraries the chase scripts can use, on top of table, string, math, utf8 and coroutine"),
+                )
+                .arg(
+                    Arg::new("lua-max-instructions")
+                        .long("lua-max-instructions")
+                        .value_name("MILLIONS")
+                        .value_parser(clap::value_parser!(u64))
+                        .default_value("100")
+                        .help("Stop the chase scripts after this many Lua instructions, in millions"),
+                )
+                .arg(
+                    Arg::new("lua-max-memory")
+                        .long("lua-max-memory")
+                        .value_name("MB")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("64")
+                        .help("Memory a chase script can use, in MB"),
                 ),
         )
         .subcommand(
@@ -241,6 +503,119 @@ fn handle_cli_args() -> ArgMatches {
             Command::new("chase")
             .about("Run an automated build for a repo")
             .arg_required_else_help(true)
+            .args_conflicts_with_subcommands(true)
+            .subcommand(
+                Command::new("resume")
+                    .about("Resume an interrupted chase, skipping the completed commits")
+                    .arg_required_else_help(true)
+                    .arg(
+                        Arg::new("repo")
+                            .value_name("REPO")
+                            .required(true)
+                            .help("The repo the chase was ran on")
+                    )
+                    .arg(
+                        Arg::new("build")
+                            .value_name("BUILD")
+                            .required(true)
+                            .help("The name of the automated build")
+                    )
+                    .arg(
+                        Arg::new("run")
+                            .value_name("RUN")
+                            .default_value("latest")
+                            .help("The run folder to resume (next to the script). Defaults to the latest run")
+                    )
+            )
+            .subcommand(
+                Command::new("log")
+                    .about("Read the log of a commit from a chase run")
+                    .arg_required_else_help(true)
+                    .arg(
+                        Arg::new("repo")
+                            .value_name("REPO")
+                            .required(true)
+                            .help("The repo the chase was ran on")
+                    )
+                    .arg(
+                        Arg::new("build")
+                            .value_name("BUILD")
+                            .required(true)
+                            .help("The name of the automated build")
+                    )
+                    .arg(
+                        Arg::new("run")
+                            .value_name("RUN")
+                            .required(true)
+                            .help("The run folder (next to the script), or \"latest\"")
+                    )
+                    .arg(
+                        Arg::new("commit")
+                            .value_name("COMMIT")
+                            .required(true)
+                            .help("The commit hash, or its position in the chase")
+                    )
+                    .arg(
+                        Arg::new("stream")
+                            .long("stream")
+                            .short('s')
+                            .value_name("STREAM")
+                            .value_delimiter(',')
+                            .action(ArgAction::Append)
+                            .value_parser(["out", "err", "guse"])
+                            .help("Only show these streams (out, err, guse). Defaults to all")
+                    )
+            )
+            .subcommand(
+                Command::new("stop")
+                    .about("Stop a running chase, like the ones started by the watch rules")
+                    .arg_required_else_help(true)
+                    .arg(
+                        Arg::new("id")
+                            .value_name("ID")
+                            .required(true)
+                            .value_parser(clap::value_parser!(ChaseId))
+                            .help("The id of the chase. The watched chases print it in watch.log")
+                    )
+            )
+            .subcommand(
+                Command::new("diff")
+                    .about("Compare the results of two runs of a chase")
+                    .arg_required_else_help(true)
+                    .arg(
+                        Arg::new("repo")
+                            .value_name("REPO")
+                            .required(true)
+                            .help("The repo the chase was ran on")
+                    )
+                    .arg(
+                        Arg::new("build")
+                            .value_name("BUILD")
+                            .required(true)
+                            .help("The name of the automated build")
+                    )
+                    .arg(
+                        Arg::new("run_a")
+                            .value_name("RUN_A")
+                            .required(true)
+                            .help("The older run folder (next to the script)")
+                    )
+                    .arg(
+                        Arg::new("run_b")
+                            .value_name("RUN_B")
+                            .required(true)
+                            .help("The newer run folder, or \"latest\"")
+                    )
+                    .arg(
+                        Arg::new("tail")
+                            .long("tail")
+                            .short('t')
+                            .value_name("LINES")
+                            .value_parser(clap::value_parser!(usize))
+                            .default_value("20")
+                            .help("Number of log lines compared for each regressed commit")
+                    )
+            )
             .arg(
                 Arg::new("repo")
                     .value_name("REPO")
@@ -266,6 +641,91 @@ fn handle_cli_args() -> ArgMatches {
                         .action(ArgAction::SetTrue)
                         .help("Disable moving files between targets during a GUSE chase")
                 )
+                .arg(Arg::new("clone")
+                        .long("clone")
+                        .short('c')
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("no-move")
+                        .help("Clone files between targets instead of moving them, so each commit keeps its own outputs")
+                )
+                .arg(Arg::new("log-limit")
+                        .long("log-limit")
+                        .value_name("MB")
+                        .value_parser(clap::value_parser!(u64))
+                        .default_value("64")
+                        .help("Size cap for the log of each commit, in MB")
+                )
+                .arg(Arg::new("keep")
+                        .long("keep")
+                        .value_name("KEEP")
+                        .value_parser(["failed", "all", "none"])
+                        .default_value("none")
+                        .help("Keep the build files of these commits after the chase. Release them with `guse build release`")
+                )
+                .arg(Arg::new("kill-grace")
+                        .long("kill-grace")
+                        .value_name("SECS")
+                        .value_parser(clap::value_parser!(u64))
+                        .default_value("5")
+                        .help("Seconds a command gets to exit after SIGTERM, before it is killed with SIGKILL")
+                )
+                .arg(Arg::new("ansi")
+                        .long("ansi")
+                        .value_name("ANSI")
+                        .value_parser(["keep", "strip"])
+                        .default_value("keep")
+                        .help("Keep or strip the colors of the command output shown in the terminal. The log file keeps them")
+                )
+                .arg(Arg::new("max-line")
+                        .long("max-line")
+                        .value_name("KB")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("64")
+                        .help("Size cap for a single line of command output, in KB. The rest of the line is cut")
+                )
+        )
+        .subcommand(
+            Command::new("build")
+            .about("Manage the build sessions kept after a GUSE chase")
+            .subcommand_required(true)
+            .arg_required_else_help(true)
+            .subcommand(
+                Command::new("list")
+                    .about("List the pinned build sessions")
+                    .arg(
+                        Arg::new("repo")
+                            .value_name("REPO")
+                            .required(true)
+                            .help("The repo the chase was ran on")
+                    )
+            )
+            .subcommand(
+                Command::new("release")
+                    .about("Release a pinned build session and remove its files")
+                    .arg(
+                        Arg::new("repo")
+                            .value_name("REPO")
+                            .required(true)
+                            .help("The repo the chase was ran on")
+                    )
+                    .arg(
+                        Arg::new("commit")
+                            .value_name("COMMIT")
+                            .required(false)
+                            .help("The commit of the session. If not given, all sessions are released")
+                    )
+            )
+        )
+        .subcommand(
+            Command::new("lua")
+                .about("Open a Lua console to try out chase inputs on a repo")
+                .arg_required_else_help(true)
+                .arg(
+                    Arg::new("repo")
+                        .value_name("REPO")
+                        .required(true)
+                        .help("The repo the inputs are resolved on")
+                )
         )
         .subcommand(
             Command::new("script")
@@ -337,6 +797,36 @@ fn run_mount(matches: &ArgMatches) -> anyhow::Result<()> {
     let allow_other = matches.get_flag("allow-other");
     let allow_root = matches.get_flag("allow-root");
     let disable_socket = matches.get_flag("disable-socket");
+    let chase_hooks = ChaseHooks {
+        commands: matches
+            .get_many::<String>("chase-hook")
+            .map(|h| h.cloned().collect())
+            .unwrap_or_default(),
+        notify_files: matches
+            .get_many::<String>("chase-notify")
+            .map(|n| n.map(PathBuf::from).collect())
+            .unwrap_or_default(),
+    };
+    let lua_sandbox = LuaSandbox {
+        allow: matches
+            .get_many::<String>("lua-allow")
+            .map(|l| l.cloned().collect())
+            .unwrap_or_default(),
+        max_instructions: matches
+            .get_one::<u64>("lua-max-instructions")
+            .ok_or_else(|| anyhow!("Cannot parse argument"))?
+            .checked_mul(1_000_000)
+            .ok_or_else(|| anyhow!("--lua-max-instructions is too large"))?,
+        max_memory: matches
+            .get_one::<usize>("lua-max-memory")
+            .ok_or_else(|| anyhow!("Cannot parse argument"))?
+            .checked_mul(1024 * 1024)
+            .ok_or_else(|| anyhow!("--lua-max-memory is too large"))?,
+    };
+    let _ = LUA_SANDBOX.set(lua_sandbox);
+    if let Some(env_file) = matches.get_one::<String>("chase-env") {
+        let _ = CHASE_ENV_FILE.set(std::path::absolute(env_file)?);
+    }
     let mount_point = guse::mount::MountPoint::new(
         mountpoint,
         repos_dir,
@@ -344,6 +834,7 @@ fn run_mount(matches: &ArgMatches) -> anyhow::Result<()> {
         allow_root,
         allow_other,
         disable_socket,
+        chase_hooks,
     );
 
     guse::mount::mount_fuse(mount_point)?;
@@ -23,6 +23,7 @@ use std::thread;
 use std::time::{Duration, SystemTime};
 use std::{num::NonZeroU32, path::PathBuf};
 
+use crate::fs::builds::chase_hook::ChaseHooks;
 use crate::fs::fileattr::{
     FileAttr, FileType, InoFlag, SetFileAttr, dir_attr, pair_to_system_time, system_time_to_pair,
 };
@@ -41,6 +42,7 @@ pub struct MountPoint {
     pub allow_root: bool,
     pub allow_other: bool,
     pub disable_socket: bool,
+    pub chase_hooks: ChaseHooks,
 }
 
 impl MountPoint {
@@ -51,6 +53,7 @@ impl MountPoint {
         allow_root: bool,
         allow_other: bool,
         disable_socket: bool,
+        chase_hooks: ChaseHooks,
     ) -> Self {
         Self {
             mountpoint,
@@ -59,6 +62,7 @@ impl MountPoint {
             allow_root,
             allow_other,
             disable_socket,
+            chase_hooks,
         }
     }
 }
@@ -71,6 +75,7 @@ pub fn mount_fuse(opts: MountPoint) -> anyhow::Result<()> {
         allow_root,
         allow_other,
         disable_socket,
+        chase_hooks,
     } = opts;
 
     try_force_unmount(&mountpoint);
@@ -102,6 +107,7 @@ pub fn mount_fuse(opts: MountPoint) -> anyhow::Result<()> {
         mountpoint.clone(),
         opts.read_only,
         notif.clone(),
+        chase_hooks,
     )?;
 
     let mut session = fuser::Session::new(fs.clone(), &mountpoint, &options)?;
@@ -180,8 +186,9 @@ impl GitFsAdapter {
         mount_point: PathBuf,
         read_only: bool,
         notifier: Arc<OnceLock<fuser::Notifier>>,
+        chase_hooks: ChaseHooks,
     ) -> anyhow::Result<Self> {
-        let fs = GitFs::new(repos_dir, mount_point, read_only, notifier)?;
+        let fs = GitFs::new(repos_dir, mount_point, read_only, notifier, chase_hooks)?;
         Ok(GitFsAdapter { inner: fs })
     }
 
@@ -917,6 +924,22 @@ impl fuser::Filesystem for GitFsAdapter {
         }
     }
 
+    fn symlink(
+        &mut self,
+        _req: &fuser::Request<'_>,
+        parent: u64,
+        link_name: &OsStr,
+        target: &Path,
+        reply: ReplyEntry,
+    ) {
+        let fs = self.getfs();
+
+        match fs.symlink(parent, link_name, target) {
+            Ok(attr) => reply.entry(&TTL, &attr.into(), 0),
+            Err(e) => reply.error(errno_from_anyhow(&e)),
+        }
+    }
+
     fn readlink(&mut self, _req: &fuser::Request<'_>, ino: u64, reply: ReplyData) {
         let fs = self.getfs();
 
@@ -1,12 +1,19 @@
 use std::{
-    path::PathBuf,
+    collections::HashMap,
+    ffi::OsStr,
+    path::{Path, PathBuf},
     sync::{Arc, OnceLock},
 };
 
+use anyhow::{Context, anyhow};
+use git2::Oid;
 use parking_lot::Mutex;
 use tempfile::TempDir;
 
-use crate::fs::GitFs;
+use crate::fs::{
+    GitFs, ROOT_INO,
+    builds::{chase_hook::ChaseHooks, chase_resolver::resolve_detached_snaps},
+};
 
 thread_local! {
     pub static GITFS_SETUP_RESULT: Mutex<Option<SetupResult>> = const { Mutex::new(None) };
@@ -35,6 +42,7 @@ fn git_fs_setup(setup: GitFsTestSetup) -> SetupResult {
         PathBuf::new(),
         setup.read_only,
         Arc::new(OnceLock::new()),
+        ChaseHooks::default(),
     )
     .expect("failed to init GitFs");
 
@@ -73,3 +81,41 @@ pub fn get_fs() -> Arc<GitFs> {
         guard.as_mut().unwrap().fs.clone().unwrap()
     })
 }
+
+/// Creates a repo without a remote, with one commit per file, and the Snap folders of the commits.
+///
+/// Returns the inode of the repo and, for each commit, its oid and the inode of its Snap folder.
+pub fn local_repo_with_commits(
+    name: &str,
+    files: &[&str],
+) -> anyhow::Result<(u64, Vec<(Oid, u64)>)> {
+    let fs = get_fs();
+    let repo_ino = fs.mkdir(ROOT_INO, OsStr::new(name))?.ino;
+    let repo = fs.get_repo(repo_ino)?;
+    let commits = repo.with_repo(|r| -> anyhow::Result<Vec<Oid>> {
+        let workdir = r.workdir().context("Repo has no workdir")?.to_path_buf();
+        let sig = git2::Signature::now("guse", "guse@localhost")?;
+        let mut commits = Vec::with_capacity(files.len());
+        for file in files {
+            std::fs::write(workdir.join(file), file)?;
+            let mut index = r.index()?;
+            index.add_path(Path::new(file))?;
+            let tree = r.find_tree(index.write_tree()?)?;
+            let parent = commits.last().map(|&oid| r.find_commit(oid)).transpose()?;
+            let parents = parent.iter().collect::<Vec<_>>();
+            commits.push(r.commit(Some("HEAD"), &sig, &sig, file, &tree, &parents)?);
+        }
+        Ok(commits)
+    })?;
+    let oids = commits.iter().collect::<Vec<&Oid>>();
+    let mut paths = HashMap::new();
+    resolve_detached_snaps(&fs, repo_ino, &oids, &mut paths)
+        .map_err(|_| anyhow!("Could not create the Snap folders"))?;
+    Ok((
+        repo_ino,
+        commits
+            .into_iter()
+            .map(|oid| (oid, paths[&oid].1))
+            .collect(),
+    ))
+}